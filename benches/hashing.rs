@@ -0,0 +1,72 @@
+//! Full scans of a synthetic tree, to catch hashing regressions and compare
+//! how much each configuration reads. Run with `cargo bench`.
+
+use criterion::{Criterion, criterion_group, criterion_main};
+use dupfind::DupFinder;
+use std::fs;
+use std::path::Path;
+
+const FILE_SIZE: usize = 256 * 1024;
+
+type Configure = fn(DupFinder) -> DupFinder;
+
+/// Deterministic filler so runs are comparable without a RNG dependency.
+fn pattern(seed: u64, len: usize) -> Vec<u8> {
+    let mut state = seed.wrapping_mul(6364136223846793005).wrapping_add(1);
+    (0..len)
+        .map(|_| {
+            state = state
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (state >> 56) as u8
+        })
+        .collect()
+}
+
+/// 40 duplicate pairs, 40 same-size files differing in the first byte and
+/// 40 differing only in the last byte, all of one size so every file is a
+/// candidate and the tiers have to tell them apart.
+fn create_tree(dir: &Path) {
+    for i in 0..40 {
+        let content = pattern(i, FILE_SIZE);
+        fs::write(dir.join(format!("dup_{i}_a.bin")), &content).unwrap();
+        fs::write(dir.join(format!("dup_{i}_b.bin")), &content).unwrap();
+    }
+    let base = pattern(1000, FILE_SIZE);
+    for i in 0..40u8 {
+        let mut head = base.clone();
+        head[0] = i;
+        fs::write(dir.join(format!("head_{i}.bin")), &head).unwrap();
+        let mut tail = base.clone();
+        tail[FILE_SIZE - 1] = i;
+        fs::write(dir.join(format!("tail_{i}.bin")), &tail).unwrap();
+    }
+}
+
+fn bench_scan(c: &mut Criterion) {
+    let dir = tempfile::Builder::new()
+        .prefix("dupfind-bench")
+        .tempdir()
+        .unwrap();
+    create_tree(dir.path());
+
+    let configs: [(&str, Configure); 3] = [
+        ("default", |f| f),
+        ("no_mid_tier", |f| f.mid_hash_size(0)),
+        ("small_quick_hash", |f| f.quick_hash_size(512)),
+    ];
+    let mut group = c.benchmark_group("scan");
+    for (name, configure) in configs {
+        let finder = configure(DupFinder::new(dir.path()));
+        let results = finder.run().unwrap();
+        eprintln!(
+            "{name}: read {} of {} bytes",
+            results.total_bytes_read, results.total_bytes_scanned
+        );
+        group.bench_function(name, |b| b.iter(|| finder.run().unwrap()));
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_scan);
+criterion_main!(benches);
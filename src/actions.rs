@@ -0,0 +1,1375 @@
+use anyhow::{Context, Result, bail};
+use clap::ValueEnum;
+use log::{info, warn};
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, IsTerminal, Write};
+use std::path::{Component, Path, PathBuf};
+use std::process::Command;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::SystemTime;
+
+use crate::output::format_bytes;
+use crate::statistics::group_digest;
+use crate::utils::{INTERRUPTED, file_id, link_count};
+
+/// Which file in a duplicate group survives a destructive action.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum KeepStrategy {
+    /// First file as listed in the group
+    #[default]
+    First,
+    /// File with the oldest modification time
+    Oldest,
+    /// File with the newest modification time
+    Newest,
+    /// File with the shortest path
+    ShortestPath,
+}
+
+impl KeepStrategy {
+    fn describe(self) -> &'static str {
+        match self {
+            KeepStrategy::First => "the first listed copy",
+            KeepStrategy::Oldest => "the oldest copy",
+            KeepStrategy::Newest => "the newest copy",
+            KeepStrategy::ShortestPath => "the copy with the shortest path",
+        }
+    }
+}
+
+/// Name of the manifest written into a `--move-to` quarantine directory.
+pub const MANIFEST_FILENAME: &str = "dupfind-manifest.jsonl";
+
+/// Default manifest for `--symlink`, written to the current directory.
+pub const SYMLINK_MANIFEST_FILENAME: &str = "dupfind-symlinks.jsonl";
+
+/// What to do with the redundant copies in each duplicate group.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Action {
+    Delete,
+    Hardlink,
+    /// Replace copies with copy-on-write clones of the kept file, which share
+    /// storage but stay independent files (Btrfs, XFS, APFS, ReFS).
+    Reflink,
+    /// Move copies into a quarantine directory, mirroring their absolute paths.
+    Move(PathBuf),
+    /// Replace copies with symlinks to the kept file, relative unless `absolute`,
+    /// recording each link in `manifest`.
+    Symlink {
+        absolute: bool,
+        manifest: PathBuf,
+    },
+}
+
+impl Action {
+    fn describe(&self) -> &'static str {
+        match self {
+            Action::Delete => "delete",
+            Action::Hardlink => "hardlink",
+            Action::Reflink => "reflink",
+            Action::Move(_) => "move",
+            Action::Symlink { .. } => "symlink",
+        }
+    }
+}
+
+/// One line of the quarantine manifest, enough to undo a move.
+#[derive(Serialize)]
+struct ManifestEntry<'a> {
+    original: &'a Path,
+    quarantined: &'a Path,
+}
+
+/// One line of the symlink manifest: replacing `link` with a copy of
+/// `target` undoes it.
+#[derive(Serialize)]
+struct SymlinkEntry<'a> {
+    link: &'a Path,
+    target: &'a Path,
+}
+
+#[derive(Debug, Default)]
+pub struct ActionSummary {
+    pub files_affected: usize,
+    pub bytes_reclaimed: u64,
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// Return the index of the file to keep. Files with unreadable mtimes never win
+/// an `oldest`/`newest` comparison.
+pub fn select_original(files: &[PathBuf], strategy: KeepStrategy) -> Option<usize> {
+    if files.is_empty() {
+        return None;
+    }
+
+    let indices = 0..files.len();
+    let mtimes = || files.iter().map(|p| modified(p)).collect::<Vec<_>>();
+    let keep = match strategy {
+        KeepStrategy::First => 0,
+        KeepStrategy::Oldest => {
+            let mtimes = mtimes();
+            indices
+                .min_by_key(|&i| (mtimes[i].is_none(), mtimes[i]))
+                .unwrap_or(0)
+        }
+        KeepStrategy::Newest => {
+            let mtimes = mtimes();
+            indices.max_by_key(|&i| mtimes[i]).unwrap_or(0)
+        }
+        KeepStrategy::ShortestPath => indices
+            .min_by_key(|&i| (files[i].as_os_str().len(), &files[i]))
+            .unwrap_or(0),
+    };
+    Some(keep)
+}
+
+/// Like `select_original`, but only among the files under the earliest
+/// `prefer` directory that holds any, so those locations always win. Paths
+/// are compared in absolute form.
+pub fn select_preferred(
+    files: &[PathBuf],
+    strategy: KeepStrategy,
+    prefer: &[PathBuf],
+) -> Option<usize> {
+    let prefer: Vec<PathBuf> = prefer.iter().map(|dir| absolute(dir)).collect();
+    let ranks: Vec<usize> = files
+        .iter()
+        .map(|file| {
+            let file = absolute(file);
+            prefer
+                .iter()
+                .position(|dir| file.starts_with(dir))
+                .unwrap_or(prefer.len())
+        })
+        .collect();
+    let best = ranks.iter().min()?;
+    let candidates: Vec<usize> = (0..files.len()).filter(|&i| ranks[i] == *best).collect();
+    let tied: Vec<PathBuf> = candidates.iter().map(|&i| files[i].clone()).collect();
+    select_original(&tied, strategy).map(|i| candidates[i])
+}
+
+fn absolute(path: &Path) -> PathBuf {
+    std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+/// Resolve symlinks and `..` in `paths` once up front, for `is_under`, so a
+/// tree named through an alias still matches. Missing paths stay absolute.
+pub fn canonical_paths(paths: &[PathBuf]) -> Vec<PathBuf> {
+    paths
+        .iter()
+        .map(|p| fs::canonicalize(p).unwrap_or_else(|_| absolute(p)))
+        .collect()
+}
+
+/// Whether `file` is one of `dirs` or lies under one of them, comparing its
+/// canonical path against `dirs` from `canonical_paths`.
+pub fn is_under(file: &Path, dirs: &[PathBuf]) -> bool {
+    if dirs.is_empty() {
+        return false;
+    }
+    let file = fs::canonicalize(file).unwrap_or_else(|_| absolute(file));
+    dirs.iter().any(|dir| file.starts_with(dir))
+}
+
+/// Files of a group that still exist as regular files.
+fn existing_files(files: &[PathBuf]) -> Vec<PathBuf> {
+    files.iter().filter(|p| p.is_file()).cloned().collect()
+}
+
+static TEMP_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// Create a temporary sibling of `path` with `create`, which must fail if the
+/// name is taken (hard links, symlinks and reflinks all do). Names carry the
+/// process id and a counter, so neither other copies in the directory nor
+/// concurrent runs collide, and an existing file is never overwritten.
+fn create_temp_sibling(
+    path: &Path,
+    create: impl Fn(&Path) -> io::Result<()>,
+) -> io::Result<PathBuf> {
+    loop {
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(format!(
+            ".dupfind-{}-{}",
+            std::process::id(),
+            TEMP_COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        let tmp = PathBuf::from(tmp);
+        match create(&tmp) {
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            result => return result.map(|_| tmp),
+        }
+    }
+}
+
+/// Replace `path` with a hard link to `original`. The link is created next to
+/// the duplicate and renamed over it, so the path never goes missing.
+fn replace_with_hardlink(original: &Path, path: &Path) -> std::io::Result<()> {
+    let tmp = create_temp_sibling(path, |tmp| fs::hard_link(original, tmp))?;
+    if let Err(e) = fs::rename(&tmp, path) {
+        let _ = fs::remove_file(&tmp);
+        return Err(e);
+    }
+    Ok(())
+}
+
+/// Replace `path` with a copy-on-write clone of `original`, swapped in by
+/// rename like a hard link. The copy keeps its own permissions and mtime.
+fn replace_with_reflink(original: &Path, path: &Path) -> std::io::Result<()> {
+    let metadata = fs::metadata(path)?;
+    let tmp = create_temp_sibling(path, |tmp| reflink_copy::reflink(original, tmp))?;
+    let finish = || {
+        File::options()
+            .write(true)
+            .open(&tmp)?
+            .set_modified(metadata.modified()?)?;
+        fs::set_permissions(&tmp, metadata.permissions())?;
+        fs::rename(&tmp, path)
+    };
+    if let Err(e) = finish() {
+        let _ = fs::remove_file(&tmp);
+        return Err(e);
+    }
+    Ok(())
+}
+
+/// Path to `to` as seen from the directory `from`, e.g. `../b/file` for
+/// `from = /x/a` and `to = /x/b/file`. Both must be absolute.
+fn relative_path(from: &Path, to: &Path) -> PathBuf {
+    let from: Vec<_> = from.components().collect();
+    let to: Vec<_> = to.components().collect();
+    let common = from.iter().zip(&to).take_while(|(a, b)| a == b).count();
+    // Different drives on Windows have nothing in common to climb to.
+    if common == 0 {
+        return to.iter().collect();
+    }
+    (common..from.len())
+        .map(|_| Component::ParentDir)
+        .chain(to[common..].iter().copied())
+        .collect()
+}
+
+#[cfg(unix)]
+fn symlink_file(target: &Path, link: &Path) -> std::io::Result<()> {
+    std::os::unix::fs::symlink(target, link)
+}
+
+#[cfg(windows)]
+fn symlink_file(target: &Path, link: &Path) -> std::io::Result<()> {
+    std::os::windows::fs::symlink_file(target, link)
+}
+
+/// Replace `path` with a symlink to `original`, swapped in by rename like a
+/// hard link. Returns the link target as written.
+fn replace_with_symlink(original: &Path, path: &Path, absolute: bool) -> std::io::Result<PathBuf> {
+    let original = fs::canonicalize(original)?;
+    let target = match path.parent() {
+        Some(dir) if !absolute => {
+            let dir = if dir.as_os_str().is_empty() {
+                Path::new(".")
+            } else {
+                dir
+            };
+            relative_path(&fs::canonicalize(dir)?, &original)
+        }
+        _ => original,
+    };
+
+    let tmp = create_temp_sibling(path, |tmp| symlink_file(&target, tmp))?;
+    if let Err(e) = fs::rename(&tmp, path) {
+        let _ = fs::remove_file(&tmp);
+        return Err(e);
+    }
+    Ok(target)
+}
+
+/// Whether `path` lies inside a `--move-to` quarantine from an earlier run.
+fn in_quarantine(path: &Path) -> bool {
+    std::path::absolute(path).is_ok_and(|path| {
+        path.ancestors()
+            .skip(1)
+            .any(|dir| dir.join(MANIFEST_FILENAME).is_file())
+    })
+}
+
+/// Where `path` lands inside `quarantine`: its absolute path re-rooted under
+/// the quarantine, with a numeric suffix if that name is already taken.
+fn quarantine_path(quarantine: &Path, path: &Path) -> std::io::Result<PathBuf> {
+    let absolute = std::path::absolute(path)?;
+    let relative: PathBuf = absolute
+        .components()
+        .filter(|c| matches!(c, Component::Normal(_)))
+        .collect();
+    let target = quarantine.join(relative);
+    if !target.exists() {
+        return Ok(target);
+    }
+
+    let stem = target.file_stem().unwrap_or_default().to_string_lossy();
+    let ext = target
+        .extension()
+        .map(|e| format!(".{}", e.to_string_lossy()))
+        .unwrap_or_default();
+    let candidate = (1..)
+        .map(|n| target.with_file_name(format!("{}.{}{}", stem, n, ext)))
+        .find(|p| !p.exists())
+        .unwrap();
+    Ok(candidate)
+}
+
+/// Move `path` to `target`, falling back to copy and delete when the
+/// quarantine lives on another filesystem.
+fn move_file(path: &Path, target: &Path) -> std::io::Result<()> {
+    if let Some(parent) = target.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    if fs::rename(path, target).is_ok() {
+        return Ok(());
+    }
+
+    fs::copy(path, target)?;
+    if let Err(e) = fs::remove_file(path) {
+        let _ = fs::remove_file(target);
+        return Err(e);
+    }
+    Ok(())
+}
+
+fn open_manifest(quarantine: &Path) -> Result<File> {
+    fs::create_dir_all(quarantine).with_context(|| {
+        format!(
+            "Failed to create quarantine directory: {}",
+            quarantine.display()
+        )
+    })?;
+    append_to(&quarantine.join(MANIFEST_FILENAME))
+}
+
+fn append_to(path: &Path) -> Result<File> {
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("Failed to open manifest: {}", path.display()))
+}
+
+fn short_hash(hash: &str) -> &str {
+    &hash[..hash.len().min(12)]
+}
+
+/// What an action would do to one group: the copy kept and the copies acted
+/// on, each with the bytes acting on it actually frees.
+#[derive(Debug, Clone)]
+pub struct PlannedGroup {
+    pub hash: String,
+    pub keep: PathBuf,
+    pub remove: Vec<(PathBuf, u64)>,
+}
+
+/// The groups an action would touch, worked out before anything changes.
+#[derive(Debug, Default)]
+pub struct ActionPlan {
+    pub groups: Vec<PlannedGroup>,
+    pub strategy: KeepStrategy,
+    /// The `prefer` and `protect` paths the plan was made with.
+    pub prefer: Vec<PathBuf>,
+    pub protect: Vec<PathBuf>,
+}
+
+impl ActionPlan {
+    pub fn files(&self) -> usize {
+        self.groups.iter().map(|g| g.remove.len()).sum()
+    }
+
+    pub fn bytes_freed(&self) -> u64 {
+        self.groups
+            .iter()
+            .flat_map(|g| &g.remove)
+            .map(|(_, bytes)| bytes)
+            .sum()
+    }
+
+    /// List every group with the copy kept and the copies acted on.
+    pub fn print(&self, action: &Action) {
+        for group in &self.groups {
+            println!("keep    {}", group.keep.display());
+            for (path, bytes) in &group.remove {
+                println!(
+                    "{:<7} {} ({})",
+                    action.describe(),
+                    path.display(),
+                    format_bytes(*bytes)
+                );
+            }
+            println!();
+        }
+        println!("{}", self.summary(action));
+    }
+
+    /// One line saying what the plan does and how the kept copies were chosen.
+    pub fn summary(&self, action: &Action) -> String {
+        let list = |paths: &[PathBuf]| {
+            paths
+                .iter()
+                .map(|p| p.display().to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        let mut line = format!(
+            "{} {} files in {} groups, freeing {}, keeping {} of each (--keep {})",
+            action.describe(),
+            self.files(),
+            self.groups.len(),
+            format_bytes(self.bytes_freed()),
+            self.strategy.describe(),
+            self.strategy.to_possible_value().unwrap().get_name()
+        );
+        if !self.prefer.is_empty() {
+            line += &format!(", preferring copies under {}", list(&self.prefer));
+        }
+        if !self.protect.is_empty() {
+            line += &format!(
+                ", keeping a copy under {} where there is one and never touching those",
+                list(&self.protect)
+            );
+        }
+        line
+    }
+}
+
+/// The gate in front of every destructive action: proceed with a non-empty
+/// plan only if `yes` was given or the user agrees at a prompt. There is
+/// nobody to ask without a terminal or under `--quiet`, so those need `yes`.
+pub fn confirm(plan: &ActionPlan, yes: bool, quiet: bool) -> Result<bool> {
+    if plan.files() == 0 {
+        return Ok(false);
+    }
+    if yes {
+        return Ok(true);
+    }
+    if quiet {
+        bail!("--quiet never prompts before changing files, pass --yes to proceed");
+    }
+    if !std::io::stdin().is_terminal() {
+        bail!("Cannot ask for confirmation without a terminal, pass --yes to proceed");
+    }
+    eprint!("Proceed? [y/N] ");
+    std::io::stderr().flush()?;
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+}
+
+/// Space only comes back once every link to a file is gone, so a copy that
+/// keeps a link outside the plan frees nothing, and an inode is counted once.
+fn count_freed_bytes(groups: &mut [PlannedGroup]) {
+    let mut removals: HashMap<(u64, u64), u64> = HashMap::new();
+    for (path, _) in groups.iter().flat_map(|g| &g.remove) {
+        if let Some(id) = file_id(path) {
+            *removals.entry(id).or_default() += 1;
+        }
+    }
+
+    let mut counted = HashSet::new();
+    for (path, freed) in groups.iter_mut().flat_map(|g| &mut g.remove) {
+        let size = fs::metadata(&*path).map(|m| m.len()).unwrap_or(0);
+        *freed = match file_id(path) {
+            Some(id) if removals[&id] < link_count(path) || !counted.insert(id) => 0,
+            _ => size,
+        };
+    }
+}
+
+/// Decide per group which file to keep and which copies `action` would touch,
+/// following `prefer` and the keep strategy and skipping groups it cannot handle.
+/// Files under `protect` are never touched, and one of them is kept if any.
+pub fn plan_action(
+    hashes: &HashMap<String, Vec<PathBuf>>,
+    action: &Action,
+    strategy: KeepStrategy,
+    prefer: &[PathBuf],
+    protect: &[PathBuf],
+) -> ActionPlan {
+    let protected_dirs = canonical_paths(protect);
+    let mut groups = Vec::new();
+    for (id, files) in hashes {
+        let hash = group_digest(id);
+        let mut files = existing_files(files);
+        if files.len() < 2 {
+            warn!(
+                "Skipping group {}: fewer than two copies still exist",
+                short_hash(hash)
+            );
+            continue;
+        }
+        // A symlink (kept in a group by --report-symlinks when its target
+        // was not scanned) is not a copy: keeping it would remove the data it
+        // points at, hard linking to it would spread the link, and acting on
+        // it frees nothing. Leave links out entirely.
+        files.retain(|p| !fs::symlink_metadata(p).is_ok_and(|m| m.file_type().is_symlink()));
+        if files.len() < 2 {
+            info!(
+                "Skipping group {}: only one copy is not a symlink",
+                short_hash(hash)
+            );
+            continue;
+        }
+        // A quarantine may be purged later, so never link into one, and
+        // leave its copies alone so it can still be restored.
+        if matches!(action, Action::Symlink { .. }) {
+            files.retain(|p| !in_quarantine(p));
+            if files.len() < 2 {
+                info!(
+                    "Skipping group {}: its other copies are quarantined",
+                    short_hash(hash)
+                );
+                continue;
+            }
+        }
+
+        let (mut protected, mut files): (Vec<_>, Vec<_>) = files
+            .into_iter()
+            .partition(|p| is_under(p, &protected_dirs));
+        if files.is_empty() {
+            info!(
+                "Skipping group {}: every copy is protected",
+                short_hash(hash)
+            );
+            continue;
+        }
+
+        let pool = if protected.is_empty() {
+            &mut files
+        } else {
+            &mut protected
+        };
+        let Some(keep_idx) = select_preferred(pool, strategy, prefer) else {
+            continue;
+        };
+        let original = pool.swap_remove(keep_idx);
+        let original_id = file_id(&original);
+
+        if matches!(action, Action::Hardlink | Action::Reflink)
+            && let Some((dev, _)) = original_id
+            && files
+                .iter()
+                .any(|p| file_id(p).is_some_and(|(d, _)| d != dev))
+        {
+            warn!(
+                "Skipping group {}: files span multiple filesystems, cannot {}",
+                short_hash(hash),
+                action.describe()
+            );
+            continue;
+        }
+
+        if *action == Action::Hardlink && original_id.is_some() {
+            files.retain(|path| {
+                let linked = file_id(path) == original_id;
+                if linked {
+                    info!(
+                        "Skipping {}: already hardlinked to {}",
+                        path.display(),
+                        original.display()
+                    );
+                }
+                !linked
+            });
+        }
+        if files.is_empty() {
+            continue;
+        }
+        files.sort();
+        groups.push(PlannedGroup {
+            hash: hash.to_string(),
+            keep: original,
+            remove: files.into_iter().map(|path| (path, 0)).collect(),
+        });
+    }
+
+    groups.sort_by(|a, b| a.keep.cmp(&b.keep));
+    count_freed_bytes(&mut groups);
+    ActionPlan {
+        groups,
+        strategy,
+        prefer: prefer.to_vec(),
+        protect: protect.to_vec(),
+    }
+}
+
+/// A plan for copies picked by hand, as `--interactive` does, with the bytes
+/// each frees worked out as in `plan_action`.
+pub fn plan_selection(mut groups: Vec<PlannedGroup>, protect: &[PathBuf]) -> ActionPlan {
+    count_freed_bytes(&mut groups);
+    ActionPlan {
+        groups,
+        protect: protect.to_vec(),
+        ..Default::default()
+    }
+}
+
+pub fn apply_action(
+    hashes: &HashMap<String, Vec<PathBuf>>,
+    action: Action,
+    strategy: KeepStrategy,
+    prefer: &[PathBuf],
+    protect: &[PathBuf],
+    dry_run: bool,
+) -> Result<ActionSummary> {
+    let plan = plan_action(hashes, &action, strategy, prefer, protect);
+    apply_plan(&plan, &action, None, dry_run)
+}
+
+/// Carry out a plan from `plan_action`, rechecking each file as it goes since
+/// the tree may have changed since planning. With `audit`, each group acted
+/// on is recorded there, even when the run stops partway through it.
+pub fn apply_plan(
+    plan: &ActionPlan,
+    action: &Action,
+    mut audit: Option<&mut AuditLog>,
+    dry_run: bool,
+) -> Result<ActionSummary> {
+    let mut summary = ActionSummary::default();
+    // Appended per move so the manifest stays complete if the run is cut short.
+    let mut manifest = match action {
+        Action::Move(dir) if !dry_run => Some(open_manifest(dir)?),
+        Action::Symlink { manifest, .. } if !dry_run => Some(append_to(manifest)?),
+        _ => None,
+    };
+
+    for group in &plan.groups {
+        let mut removed = Vec::new();
+        let result = apply_group(
+            group,
+            action,
+            dry_run,
+            &mut manifest,
+            &mut summary,
+            &mut removed,
+        );
+        if let Some(audit) = audit.as_deref_mut()
+            && !removed.is_empty()
+        {
+            audit.record(action, &group.hash, &group.keep, &removed)?;
+        }
+        result?;
+    }
+
+    Ok(summary)
+}
+
+/// Act on the copies of one planned group, pushing those changed to `removed`.
+fn apply_group(
+    group: &PlannedGroup,
+    action: &Action,
+    dry_run: bool,
+    manifest: &mut Option<File>,
+    summary: &mut ActionSummary,
+    removed: &mut Vec<PathBuf>,
+) -> Result<()> {
+    for (path, freed) in &group.remove {
+        if INTERRUPTED.load(Ordering::Relaxed) {
+            bail!("{} interrupted by user", action.describe());
+        }
+        if !group.keep.is_file() {
+            warn!(
+                "Original {} disappeared, leaving remaining copies untouched",
+                group.keep.display()
+            );
+            return Ok(());
+        }
+
+        if dry_run {
+            println!(
+                "Would {} {} (keeping {})",
+                action.describe(),
+                path.display(),
+                group.keep.display()
+            );
+        } else {
+            let result = match action {
+                Action::Delete => fs::remove_file(path).map(|_| None),
+                Action::Hardlink => replace_with_hardlink(&group.keep, path).map(|_| None),
+                Action::Reflink => replace_with_reflink(&group.keep, path).map(|_| None),
+                Action::Move(dir) => quarantine_path(dir, path)
+                    .and_then(|target| move_file(path, &target).map(|_| Some(target))),
+                Action::Symlink { absolute, .. } => {
+                    replace_with_symlink(&group.keep, path, *absolute).map(Some)
+                }
+            };
+            let moved_to = match result {
+                Ok(target) => target,
+                // Every other file on this filesystem would fail the same way.
+                Err(e)
+                    if *action == Action::Reflink
+                        && e.kind() == std::io::ErrorKind::Unsupported =>
+                {
+                    bail!(
+                        "The filesystem of {} does not support reflinks (Btrfs, XFS, APFS and ReFS do), use --hardlink instead",
+                        path.display()
+                    );
+                }
+                Err(e) => {
+                    warn!("Failed to {} {}: {}", action.describe(), path.display(), e);
+                    continue;
+                }
+            };
+            match action {
+                Action::Delete => {
+                    info!("Deleted {} (kept {})", path.display(), group.keep.display())
+                }
+                Action::Hardlink => {
+                    info!("Linked {} -> {}", path.display(), group.keep.display())
+                }
+                Action::Reflink => {
+                    info!("Cloned {} from {}", path.display(), group.keep.display())
+                }
+                Action::Move(_) => {
+                    let target = moved_to.as_deref().unwrap_or(path);
+                    info!("Moved {} -> {}", path.display(), target.display());
+                    if let Some(manifest) = manifest.as_mut() {
+                        let entry = ManifestEntry {
+                            original: &std::path::absolute(path)?,
+                            quarantined: target,
+                        };
+                        serde_json::to_writer(&mut *manifest, &entry)?;
+                        writeln!(manifest)?;
+                    }
+                }
+                Action::Symlink { .. } => {
+                    let target = moved_to.as_deref().unwrap_or(&group.keep);
+                    info!("Linked {} -> {}", path.display(), target.display());
+                    if let Some(manifest) = manifest.as_mut() {
+                        let entry = SymlinkEntry {
+                            link: &std::path::absolute(path)?,
+                            target: &fs::canonicalize(&group.keep)?,
+                        };
+                        serde_json::to_writer(&mut *manifest, &entry)?;
+                        writeln!(manifest)?;
+                    }
+                }
+            }
+        }
+        if !dry_run {
+            removed.push(path.clone());
+        }
+        summary.files_affected += 1;
+        summary.bytes_reclaimed += freed;
+    }
+    Ok(())
+}
+
+/// Append-only JSON lines record of destructive actions, for `--audit-log`:
+/// one line per group with the time, action, hash, kept copy and the copies
+/// removed, all as absolute paths. Unlike the result files it is never
+/// overwritten, so it accumulates across runs.
+pub struct AuditLog(File);
+
+/// One line of the audit log.
+#[derive(Serialize)]
+struct AuditEntry<'a> {
+    timestamp: String,
+    action: &'a str,
+    hash: &'a str,
+    kept: PathBuf,
+    removed: Vec<PathBuf>,
+}
+
+impl AuditLog {
+    pub fn open(path: &Path) -> Result<Self> {
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map(AuditLog)
+            .with_context(|| format!("Failed to open audit log: {}", path.display()))
+    }
+
+    pub fn record(
+        &mut self,
+        action: &Action,
+        hash: &str,
+        kept: &Path,
+        removed: &[PathBuf],
+    ) -> Result<()> {
+        let absolute = |p: &Path| std::path::absolute(p).unwrap_or_else(|_| p.to_path_buf());
+        let entry = AuditEntry {
+            timestamp: humantime::format_rfc3339_millis(SystemTime::now()).to_string(),
+            action: action.describe(),
+            hash,
+            kept: absolute(kept),
+            removed: removed.iter().map(|p| absolute(p)).collect(),
+        };
+        // One write per line, so an interrupted run cannot leave half of one.
+        let mut line = serde_json::to_string(&entry)?;
+        line.push('\n');
+        self.0
+            .write_all(line.as_bytes())
+            .context("Failed to write audit log")
+    }
+}
+
+/// Outcome of `--exec`: commands run and how many of them failed.
+#[derive(Debug, Default)]
+pub struct ExecSummary {
+    pub runs: usize,
+    pub failures: usize,
+}
+
+/// Run `command` for every duplicate group, `find -exec` style: an argument
+/// that is exactly `{+}` expands to all paths of the group and runs the
+/// command once per group; otherwise every `{}` is replaced by one path and
+/// the command runs once per file. Failures are logged and counted, not fatal.
+pub fn exec_groups(
+    hashes: &HashMap<String, Vec<PathBuf>>,
+    command: &str,
+    dry_run: bool,
+) -> Result<ExecSummary> {
+    let template = shell_words::split(command)
+        .with_context(|| format!("Invalid --exec command: {}", command))?;
+    if template.is_empty() {
+        bail!("The --exec command is empty");
+    }
+    let per_group = template.iter().any(|arg| arg == "{+}");
+    if !per_group && !template.iter().any(|arg| arg.contains("{}")) {
+        bail!("The --exec command needs a {{}} or {{+}} placeholder for the paths");
+    }
+
+    let mut groups: Vec<Vec<PathBuf>> =
+        hashes.values().map(|files| existing_files(files)).collect();
+    groups.retain(|files| files.len() >= 2);
+    groups.sort();
+
+    let mut invocations: Vec<Vec<String>> = Vec::new();
+    for files in &groups {
+        let paths: Vec<String> = files
+            .iter()
+            .map(|p| p.to_string_lossy().into_owned())
+            .collect();
+        if per_group {
+            invocations.push(
+                template
+                    .iter()
+                    .flat_map(|arg| match arg.as_str() {
+                        "{+}" => paths.clone(),
+                        _ => vec![arg.clone()],
+                    })
+                    .collect(),
+            );
+        } else {
+            for path in &paths {
+                invocations.push(template.iter().map(|arg| arg.replace("{}", path)).collect());
+            }
+        }
+    }
+
+    let mut summary = ExecSummary::default();
+    for argv in invocations {
+        if INTERRUPTED.load(Ordering::Relaxed) {
+            bail!("exec interrupted by user");
+        }
+        if dry_run {
+            println!("Would run {}", shell_words::join(&argv));
+            continue;
+        }
+
+        summary.runs += 1;
+        match Command::new(&argv[0]).args(&argv[1..]).status() {
+            Ok(status) if status.success() => {}
+            Ok(status) => {
+                warn!("{} exited with {}", shell_words::join(&argv), status);
+                summary.failures += 1;
+            }
+            Err(e) => {
+                warn!("Failed to run {}: {}", argv[0], e);
+                summary.failures += 1;
+            }
+        }
+    }
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_select_original_shortest_path() {
+        let files = vec![
+            PathBuf::from("/a/long/path/file.txt"),
+            PathBuf::from("/a/file.txt"),
+            PathBuf::from("/a/mid/file.txt"),
+        ];
+        assert_eq!(select_original(&files, KeepStrategy::First), Some(0));
+        assert_eq!(select_original(&files, KeepStrategy::ShortestPath), Some(1));
+        assert_eq!(select_original(&[], KeepStrategy::First), None);
+    }
+
+    #[test]
+    fn test_prefer_directories_in_order() {
+        let files = vec![
+            PathBuf::from("/tmp/x/file.txt"),
+            PathBuf::from("/archive/old/file.txt"),
+            PathBuf::from("/archive/file.txt"),
+            PathBuf::from("/home/file.txt"),
+        ];
+        let prefer = [PathBuf::from("/archive"), PathBuf::from("/tmp")];
+        let shortest = KeepStrategy::ShortestPath;
+        assert_eq!(
+            select_preferred(&files, KeepStrategy::First, &prefer),
+            Some(1)
+        );
+        assert_eq!(select_preferred(&files, shortest, &prefer), Some(2));
+        assert_eq!(select_preferred(&files, shortest, &prefer[1..]), Some(0));
+        assert_eq!(select_preferred(&files, shortest, &[]), Some(3));
+    }
+
+    #[test]
+    fn test_delete_keeps_exactly_one() {
+        let dir = tempdir().unwrap();
+        let paths: Vec<_> = (0..3)
+            .map(|i| {
+                let p = dir.path().join(format!("copy{}.txt", i));
+                fs::write(&p, "same").unwrap();
+                p
+            })
+            .collect();
+        let hashes = HashMap::from([("h".to_string(), paths.clone())]);
+
+        let summary = apply_action(
+            &hashes,
+            Action::Delete,
+            KeepStrategy::First,
+            &[],
+            &[],
+            false,
+        )
+        .unwrap();
+        assert_eq!(summary.files_affected, 2);
+        assert_eq!(summary.bytes_reclaimed, 8);
+        assert!(paths[0].exists());
+        assert!(!paths[1].exists());
+        assert!(!paths[2].exists());
+    }
+
+    #[test]
+    fn test_dry_run_touches_nothing() {
+        let dir = tempdir().unwrap();
+        let a = dir.path().join("a.txt");
+        let b = dir.path().join("b.txt");
+        fs::write(&a, "same").unwrap();
+        fs::write(&b, "same").unwrap();
+        let hashes = HashMap::from([("h".to_string(), vec![a.clone(), b.clone()])]);
+
+        let summary =
+            apply_action(&hashes, Action::Delete, KeepStrategy::First, &[], &[], true).unwrap();
+        assert_eq!(summary.files_affected, 1);
+        assert!(a.exists() && b.exists());
+    }
+
+    #[test]
+    fn test_never_deletes_last_copy() {
+        let dir = tempdir().unwrap();
+        let a = dir.path().join("a.txt");
+        fs::write(&a, "same").unwrap();
+        let missing = dir.path().join("gone.txt");
+        let hashes = HashMap::from([("h".to_string(), vec![a.clone(), missing])]);
+
+        let summary = apply_action(
+            &hashes,
+            Action::Delete,
+            KeepStrategy::First,
+            &[],
+            &[],
+            false,
+        )
+        .unwrap();
+        assert_eq!(summary.files_affected, 0);
+        assert!(a.exists());
+    }
+
+    #[test]
+    fn test_audit_log_appends_one_line_per_group() {
+        let dir = tempdir().unwrap();
+        let log_path = dir.path().join("audit.jsonl");
+        let write_pair = |name: &str| {
+            let a = dir.path().join(format!("{name}_a"));
+            let b = dir.path().join(format!("{name}_b"));
+            fs::write(&a, name).unwrap();
+            fs::write(&b, name).unwrap();
+            HashMap::from([(name.to_string(), vec![a, b])])
+        };
+
+        for name in ["first", "second"] {
+            let hashes = write_pair(name);
+            let plan = plan_action(&hashes, &Action::Delete, KeepStrategy::First, &[], &[]);
+            let mut audit = AuditLog::open(&log_path).unwrap();
+            apply_plan(&plan, &Action::Delete, Some(&mut audit), false).unwrap();
+        }
+
+        let log = fs::read_to_string(&log_path).unwrap();
+        let entries: Vec<serde_json::Value> = log
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[1]["action"], "delete");
+        assert_eq!(entries[1]["hash"], "second");
+        let kept = std::path::absolute(dir.path().join("second_a")).unwrap();
+        assert_eq!(entries[1]["kept"], kept.to_str().unwrap());
+        assert_eq!(entries[1]["removed"].as_array().unwrap().len(), 1);
+        assert!(entries[0]["timestamp"].as_str().unwrap().ends_with('Z'));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_exec_per_file_and_per_group() {
+        let dir = tempdir().unwrap();
+        let a = dir.path().join("a.txt");
+        let b = dir.path().join("b.txt");
+        fs::write(&a, "same").unwrap();
+        fs::write(&b, "same").unwrap();
+        let hashes = HashMap::from([("h".to_string(), vec![a.clone(), b.clone()])]);
+
+        let summary = exec_groups(&hashes, "touch {}.seen", false).unwrap();
+        assert_eq!((summary.runs, summary.failures), (2, 0));
+        assert!(dir.path().join("a.txt.seen").exists());
+        assert!(dir.path().join("b.txt.seen").exists());
+
+        let summary = exec_groups(&hashes, "test -e {+}", false).unwrap();
+        assert_eq!((summary.runs, summary.failures), (1, 1));
+        assert!(exec_groups(&hashes, "echo", false).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_hardlink_replaces_copies() {
+        let dir = tempdir().unwrap();
+        let a = dir.path().join("a.txt");
+        let b = dir.path().join("b.txt");
+        fs::write(&a, "same").unwrap();
+        fs::write(&b, "same").unwrap();
+        let hashes = HashMap::from([("h".to_string(), vec![a.clone(), b.clone()])]);
+
+        let summary = apply_action(
+            &hashes,
+            Action::Hardlink,
+            KeepStrategy::First,
+            &[],
+            &[],
+            false,
+        )
+        .unwrap();
+        assert_eq!(summary.files_affected, 1);
+        assert_eq!(summary.bytes_reclaimed, 4);
+        assert_eq!(file_id(&a), file_id(&b));
+        assert_eq!(fs::read_to_string(&b).unwrap(), "same");
+
+        // Running again finds the pair already linked and does nothing.
+        let summary = apply_action(
+            &hashes,
+            Action::Hardlink,
+            KeepStrategy::First,
+            &[],
+            &[],
+            false,
+        )
+        .unwrap();
+        assert_eq!(summary.files_affected, 0);
+    }
+
+    #[test]
+    fn test_move_to_quarantine_with_manifest() {
+        let dir = tempdir().unwrap();
+        let quarantine = dir.path().join("quarantine");
+        let a = dir.path().join("a.txt");
+        let b = dir.path().join("b.txt");
+        fs::write(&a, "same").unwrap();
+        fs::write(&b, "same").unwrap();
+
+        // Pre-existing file at the target forces a suffixed name.
+        let taken = quarantine_path(&quarantine, &b).unwrap();
+        fs::create_dir_all(taken.parent().unwrap()).unwrap();
+        fs::write(&taken, "other").unwrap();
+
+        let hashes = HashMap::from([("h".to_string(), vec![a.clone(), b.clone()])]);
+        let action = Action::Move(quarantine.clone());
+        let summary = apply_action(&hashes, action, KeepStrategy::First, &[], &[], false).unwrap();
+        assert_eq!(summary.files_affected, 1);
+        assert!(a.exists() && !b.exists());
+
+        let moved = taken.with_file_name("b.1.txt");
+        assert_eq!(fs::read_to_string(&moved).unwrap(), "same");
+        assert_eq!(fs::read_to_string(&taken).unwrap(), "other");
+
+        let manifest = fs::read_to_string(quarantine.join(MANIFEST_FILENAME)).unwrap();
+        let entry: serde_json::Value = serde_json::from_str(manifest.trim()).unwrap();
+        assert_eq!(entry["original"], b.display().to_string());
+        assert_eq!(entry["quarantined"], moved.display().to_string());
+    }
+
+    #[test]
+    fn test_temp_siblings_never_reuse_a_name() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("b.txt");
+        let first = create_temp_sibling(&path, |tmp| fs::write(tmp, "first")).unwrap();
+
+        // A name that turns out to be taken is skipped, not overwritten.
+        let tried = std::cell::RefCell::new(Vec::new());
+        let second = create_temp_sibling(&path, |tmp| {
+            tried.borrow_mut().push(tmp.to_path_buf());
+            if tried.borrow().len() == 1 {
+                Err(io::ErrorKind::AlreadyExists.into())
+            } else {
+                fs::write(tmp, "second")
+            }
+        })
+        .unwrap();
+        let tried = tried.into_inner();
+        assert_eq!(tried.len(), 2);
+        assert_eq!(second, tried[1]);
+        assert!(!tried[0].exists());
+        assert!(first != tried[0] && first != second);
+        assert_eq!(fs::read_to_string(&first).unwrap(), "first");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_symlink_replaces_copies_with_relative_links() {
+        let dir = tempdir().unwrap();
+        let manifest = dir.path().join("links.jsonl");
+        let quarantine = dir.path().join("quarantine");
+        fs::create_dir_all(dir.path().join("sub")).unwrap();
+        fs::create_dir_all(&quarantine).unwrap();
+        fs::write(quarantine.join(MANIFEST_FILENAME), "").unwrap();
+        let kept = quarantine.join("a.txt");
+        let a = dir.path().join("a.txt");
+        let b = dir.path().join("sub/b.txt");
+        for path in [&kept, &a, &b] {
+            fs::write(path, "same").unwrap();
+        }
+
+        // The quarantined copy would be kept first, but is never linked to.
+        let hashes = HashMap::from([("h".to_string(), vec![kept.clone(), a.clone(), b.clone()])]);
+        let action = Action::Symlink {
+            absolute: false,
+            manifest: manifest.clone(),
+        };
+        let summary = apply_action(&hashes, action, KeepStrategy::First, &[], &[], false).unwrap();
+        assert_eq!(summary.files_affected, 1);
+        assert_eq!(summary.bytes_reclaimed, 4);
+        assert!(kept.is_file() && !a.is_symlink());
+        assert_eq!(fs::read_link(&b).unwrap(), Path::new("../a.txt"));
+        assert_eq!(fs::read_to_string(&b).unwrap(), "same");
+
+        let entry: serde_json::Value =
+            serde_json::from_str(fs::read_to_string(&manifest).unwrap().trim()).unwrap();
+        assert_eq!(
+            entry["target"],
+            fs::canonicalize(&a).unwrap().display().to_string()
+        );
+    }
+
+    #[test]
+    fn test_reflink_clones_or_reports_unsupported() {
+        let dir = tempdir().unwrap();
+        let a = dir.path().join("a.txt");
+        let b = dir.path().join("b.txt");
+        fs::write(&a, "same").unwrap();
+        fs::write(&b, "same").unwrap();
+        let hashes = HashMap::from([("h".to_string(), vec![a.clone(), b.clone()])]);
+
+        // Whether this works depends on the filesystem running the tests.
+        match apply_action(
+            &hashes,
+            Action::Reflink,
+            KeepStrategy::First,
+            &[],
+            &[],
+            false,
+        ) {
+            Ok(summary) => {
+                assert_eq!(summary.files_affected, 1);
+                assert_ne!(file_id(&a), file_id(&b));
+                assert_eq!(fs::read_to_string(&b).unwrap(), "same");
+            }
+            Err(e) => {
+                assert!(e.to_string().contains("does not support reflinks"));
+                assert_eq!(fs::read_to_string(&b).unwrap(), "same");
+            }
+        }
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 2);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_plan_counts_only_bytes_actually_freed() {
+        let dir = tempdir().unwrap();
+        let path = |name: &str| dir.path().join(name);
+        fs::write(path("a"), "same").unwrap();
+        fs::write(path("b"), "same").unwrap();
+        fs::write(path("d"), "same").unwrap();
+        fs::hard_link(path("a"), path("c")).unwrap();
+        fs::hard_link(path("d"), path("e")).unwrap();
+        let files = ["a", "b", "c", "d", "e"].map(path).to_vec();
+        let hashes = HashMap::from([("h".to_string(), files)]);
+
+        // c shares its inode with the kept a; d and e go together, once.
+        let plan = plan_action(&hashes, &Action::Delete, KeepStrategy::First, &[], &[]);
+        let freed: Vec<u64> = plan.groups[0].remove.iter().map(|(_, b)| *b).collect();
+        assert_eq!(plan.groups[0].keep, path("a"));
+        assert_eq!(freed, vec![4, 0, 4, 0]);
+        assert_eq!(plan.bytes_freed(), 8);
+
+        let plan = plan_action(&hashes, &Action::Hardlink, KeepStrategy::First, &[], &[]);
+        assert_eq!(plan.files(), 3);
+        assert_eq!(plan.bytes_freed(), 8);
+    }
+
+    #[test]
+    fn test_protected_files_are_never_removed() {
+        let dir = tempdir().unwrap();
+        let originals = dir.path().join("originals");
+        fs::create_dir(&originals).unwrap();
+        let path = |name: &str| dir.path().join(name);
+        for name in [
+            "a",
+            "originals/b",
+            "originals/c",
+            "originals/d",
+            "originals/e",
+        ] {
+            fs::write(path(name), "same").unwrap();
+        }
+        let hashes = HashMap::from([
+            (
+                "h1".to_string(),
+                vec![path("a"), path("originals/b"), path("originals/c")],
+            ),
+            (
+                "h2".to_string(),
+                vec![path("originals/d"), path("originals/e")],
+            ),
+        ]);
+
+        let plan = plan_action(
+            &hashes,
+            &Action::Delete,
+            KeepStrategy::First,
+            &[],
+            &[originals],
+        );
+        assert_eq!(plan.groups.len(), 1);
+        let group = &plan.groups[0];
+        assert_eq!(group.keep, path("originals/b"));
+        let removed: Vec<_> = group.remove.iter().map(|(p, _)| p.clone()).collect();
+        assert_eq!(removed, vec![path("a")]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_symlinks_are_never_kept_or_acted_on() {
+        let dir = tempdir().unwrap();
+        let outside = tempdir().unwrap();
+        let links = dir.path().join("links");
+        fs::create_dir(&links).unwrap();
+        let (a, b) = (dir.path().join("a"), dir.path().join("b"));
+        fs::write(&a, "same").unwrap();
+        fs::write(&b, "same").unwrap();
+        fs::write(outside.path().join("c"), "same").unwrap();
+        let link = links.join("c");
+        std::os::unix::fs::symlink(outside.path().join("c"), &link).unwrap();
+
+        let hashes = HashMap::from([("h".to_string(), vec![link.clone(), a.clone(), b.clone()])]);
+        let prefer = [links];
+        for action in [Action::Delete, Action::Hardlink] {
+            let plan = plan_action(&hashes, &action, KeepStrategy::First, &prefer, &[]);
+            assert_eq!(plan.groups[0].keep, a);
+            assert_eq!(plan.groups[0].remove, vec![(b.clone(), 4)]);
+        }
+
+        let hashes = HashMap::from([("h".to_string(), vec![link, a])]);
+        let plan = plan_action(&hashes, &Action::Delete, KeepStrategy::First, &[], &[]);
+        assert!(plan.groups.is_empty());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_protect_matches_through_aliases() {
+        let dir = tempdir().unwrap();
+        let originals = dir.path().join("originals");
+        fs::create_dir(&originals).unwrap();
+        fs::write(originals.join("b"), "same").unwrap();
+        fs::write(dir.path().join("a"), "same").unwrap();
+        let alias = dir.path().join("alias");
+        std::os::unix::fs::symlink(&originals, &alias).unwrap();
+        let hashes = HashMap::from([(
+            "h".to_string(),
+            vec![dir.path().join("a"), originals.join("b")],
+        )]);
+
+        // Protected through a symlink, and through `..`.
+        for protect in [alias, originals.join("../originals")] {
+            let plan = plan_action(
+                &hashes,
+                &Action::Delete,
+                KeepStrategy::First,
+                &[],
+                &[protect],
+            );
+            assert_eq!(plan.groups[0].keep, originals.join("b"));
+        }
+
+        // Scanned through the alias, protected by the real path.
+        let hashes = HashMap::from([(
+            "h".to_string(),
+            vec![dir.path().join("a"), dir.path().join("alias/b")],
+        )]);
+        let plan = plan_action(
+            &hashes,
+            &Action::Delete,
+            KeepStrategy::First,
+            &[],
+            &[originals],
+        );
+        assert_eq!(plan.groups[0].keep, dir.path().join("alias/b"));
+    }
+
+    #[test]
+    fn test_confirm_needs_yes_when_quiet() {
+        let dir = tempdir().unwrap();
+        let (a, b) = (dir.path().join("a"), dir.path().join("b"));
+        fs::write(&a, "same").unwrap();
+        fs::write(&b, "same").unwrap();
+        let hashes = HashMap::from([("h".to_string(), vec![a, b])]);
+        let plan = plan_action(&hashes, &Action::Delete, KeepStrategy::First, &[], &[]);
+
+        assert!(confirm(&plan, true, true).unwrap());
+        assert!(confirm(&plan, false, true).is_err());
+        assert!(!confirm(&ActionPlan::default(), false, true).unwrap());
+    }
+
+    #[test]
+    fn test_summary_names_prefer_and_protect() {
+        let plan = ActionPlan {
+            strategy: KeepStrategy::Oldest,
+            ..ActionPlan::default()
+        };
+        let line = plan.summary(&Action::Delete);
+        assert!(line.ends_with("keeping the oldest copy of each (--keep oldest)"));
+
+        let plan = ActionPlan {
+            prefer: vec![PathBuf::from("/photos")],
+            protect: vec![PathBuf::from("/archive")],
+            ..plan
+        };
+        let line = plan.summary(&Action::Delete);
+        assert!(line.contains("(--keep oldest), preferring copies under /photos"));
+        assert!(line.contains("keeping a copy under /archive where there is one"));
+    }
+}
@@ -1,6 +1,35 @@
-use clap::Parser;
+use anyhow::Result;
+use clap::{CommandFactory, FromArgMatches, Parser, ValueEnum};
 use log::LevelFilter;
 use std::path::PathBuf;
+use std::time::SystemTime;
+
+use dupfind::HashAlgorithm;
+use dupfind::actions::{KeepStrategy, SYMLINK_MANIFEST_FILENAME};
+use dupfind::finder::MAX_THREADS;
+use dupfind::output::{DEFAULT_ZSTD_LEVEL, Hyperlinks, SortBy, Units};
+
+use crate::config::{self, Config};
+
+/// When to emit ANSI colors and progress bars.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum ColorChoice {
+    /// Only when writing to a terminal
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+/// How log lines are written to stderr.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum LogFormat {
+    /// Timestamped lines for people
+    #[default]
+    Text,
+    /// One JSON object per line, for log collectors
+    Json,
+}
 
 #[derive(Parser)]
 #[command(
@@ -9,25 +38,175 @@ use std::path::PathBuf;
     long_about = "Fast, parallel duplicate file finder with progress tracking"
 )]
 pub struct Args {
-    /// Directory to search for duplicates
-    pub path: String,
+    /// Directories to search for duplicates
+    #[arg(required_unless_present_any = ["from_file", "print_schema"], value_name = "PATH")]
+    pub paths: Vec<PathBuf>,
+
+    /// Read defaults from this config file instead of ~/.config/dupfind/config.toml
+    #[arg(long, value_name = "FILE")]
+    pub config: Option<PathBuf>,
+
+    /// Read candidate files from this list, one path per line ("-" for stdin), instead of scanning
+    #[arg(long, value_name = "FILE", conflicts_with = "paths")]
+    pub from_file: Option<PathBuf>,
+
+    /// With several paths, only report duplicates found under more than one of them
+    #[arg(long)]
+    pub cross_only: bool,
+
+    /// Only report copies within the same top-level subdirectory of a path, e.g. live/ or backup/
+    #[arg(long, conflicts_with_all = ["cross_only", "stream"])]
+    pub report_duplicates_only_within_root: bool,
+
+    /// Only report duplicates with copies on more than one device, e.g. an SSD and an HDD mount
+    #[arg(long)]
+    pub cross_device_only: bool,
+
+    /// Only report copies that share a file name as well as content
+    #[arg(long, conflicts_with = "stream")]
+    pub same_name: bool,
+
+    /// With --same-name, treat names differing only in case (e.g. Report.PDF, report.pdf) as the same
+    #[arg(long, requires = "same_name")]
+    pub case_insensitive_names: bool,
+
+    /// Only report groups with at least N copies
+    #[arg(long, value_name = "N", default_value_t = 2, value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(2..))]
+    pub min_duplicates: usize,
+
+    /// Report canonical absolute paths instead of paths as given on the command line
+    #[arg(long)]
+    pub absolute_paths: bool,
+
+    /// Only report duplicates that are new since an earlier `--output-json` result file
+    #[arg(long, value_name = "FILE", conflicts_with = "stream")]
+    pub baseline: Option<PathBuf>,
 
     /// Log level (off, error, warn, info, debug, trace)
     #[arg(short, long, default_value = "info")]
     pub log_level: LevelFilter,
 
-    /// Follow symbolic links
+    /// Log format: text, or json for one object per line (timestamp, level, target, message)
+    #[arg(long, value_enum, default_value_t = LogFormat::Text)]
+    pub log_format: LogFormat,
+
+    /// Never draw progress bars or prompt (actions then need --yes); log messages are unaffected
+    #[arg(short, long)]
+    pub quiet: bool,
+
+    /// Redraw progress bars at most every MS milliseconds; raise it on slow or remote terminals
+    #[arg(long, value_name = "MS", default_value = "100", value_parser = clap::value_parser!(u64).range(10..=1000))]
+    pub progress_interval: u64,
+
+    /// Print how long each phase (scan, group, hash, ...) took to stderr at the end
+    #[arg(long)]
+    pub profile: bool,
+
+    /// Log files found, candidates hashed and bytes read every SECS seconds
+    #[arg(long, value_name = "SECS", value_parser = clap::value_parser!(u64).range(1..))]
+    pub stats_interval: Option<u64>,
+
+    /// After the scan, keep watching the paths and report newly introduced duplicates on each change (Ctrl-C to stop)
+    #[arg(
+        long,
+        conflicts_with_all = ["from_file", "json_stdout", "stream", "interactive", "exec", "delete", "hardlink", "reflink", "move_to", "symlink"]
+    )]
+    pub watch: bool,
+
+    /// With --watch, rescan once changes have stopped for SECS seconds
+    #[arg(long, value_name = "SECS", default_value = "2", requires = "watch", value_parser = clap::value_parser!(u64).range(1..))]
+    pub watch_interval: u64,
+
+    /// Colorize output: auto (only on a terminal), always, or never
+    #[arg(long, value_enum, default_value_t = ColorChoice::Auto)]
+    pub color: ColorChoice,
+
+    /// Plain output without colors or hyperlinks, the same as --color never
+    #[arg(long, conflicts_with = "color")]
+    pub no_color: bool,
+
+    /// Make listed paths clickable: auto (when the terminal supports it), always, or never; --no-color turns them off
+    #[arg(long, value_enum, default_value_t = Hyperlinks::Auto)]
+    pub hyperlinks: Hyperlinks,
+
+    /// Print sizes in decimal (kB, MB, GB) or binary (KiB, MiB, GiB) units
+    #[arg(long, value_enum, default_value_t = Units::Decimal)]
+    pub units: Units,
+
+    /// Follow symbolic links inside the paths (a path that is itself a link is always followed)
     #[arg(short = 'L', long)]
     pub follow_links: bool,
 
-    /// Additional directories to ignore (can be used multiple times)
+    /// List symlinks skipped under -L because their target is missing
+    #[arg(long, requires = "follow_links")]
+    pub report_broken_links: bool,
+
+    /// Also read symlinks to files and list those pointing at duplicates (without -L)
+    #[arg(long, conflicts_with = "follow_links")]
+    pub report_symlinks: bool,
+
+    /// Stay on each path's filesystem, skipping other mounts (like du -x)
+    #[arg(short = 'x', long)]
+    pub one_filesystem: bool,
+
+    /// Additional directories to ignore: a name skips it anywhere, a path (may be a glob) skips just that path (repeatable)
     #[arg(short, long = "ignore", value_name = "DIR")]
     pub ignore: Vec<String>,
 
-    /// Include hidden files and directories (starting with '.')
+    /// Descend at most this many directories below each path (0 = only files directly in it)
+    #[arg(long, value_name = "N")]
+    pub max_depth: Option<usize>,
+
+    /// Skip files fewer than this many directories below each path
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    pub min_depth: usize,
+
+    /// Include hidden files and directories: both dotfiles and OS-hidden entries
     #[arg(short = 'H', long)]
     pub hidden: bool,
 
+    /// Include files and directories starting with '.'
+    #[arg(long)]
+    pub hidden_dotfiles: bool,
+
+    /// Include entries flagged hidden by the OS (macOS UF_HIDDEN, Windows hidden/system)
+    #[arg(long)]
+    pub hidden_system: bool,
+
+    /// Linux: also skip files with the immutable attribute (chattr +i), at the cost of opening every file
+    #[arg(long, conflicts_with_all = ["hidden", "hidden_system"])]
+    pub skip_immutable: bool,
+
+    /// Only scan files whose path relative to the root matches this glob (repeatable)
+    #[arg(long, value_name = "GLOB")]
+    pub include: Vec<String>,
+
+    /// Skip files whose path relative to the root matches this glob (repeatable, wins over --include)
+    #[arg(long, value_name = "GLOB")]
+    pub exclude: Vec<String>,
+
+    /// Skip files with this extension, e.g. lock or .log; case-insensitive (repeatable)
+    #[arg(long, value_name = "EXT")]
+    pub skip_ext: Vec<String>,
+
+    /// Do not honor .gitignore files (.ignore and .dupfindignore still apply)
+    #[arg(long)]
+    pub no_gitignore: bool,
+
+    /// Count hard links to the same file once instead of as duplicates (=false to report them)
+    #[arg(
+        long,
+        num_args = 0..=1,
+        default_value_t = true,
+        default_missing_value = "true",
+        value_name = "BOOL"
+    )]
+    pub ignore_hardlinks: bool,
+
+    /// Hash algorithm used for quick and full hashing
+    #[arg(short, long, value_enum, default_value_t = HashAlgorithm::Blake3)]
+    pub algorithm: HashAlgorithm,
+
     /// Quick hash sample size in bytes
     #[arg(long, default_value = "8192")]
     pub quick_hash_size: usize,
@@ -36,19 +215,373 @@ pub struct Args {
     #[arg(long, default_value = "64")]
     pub quick_buffer_size: usize,
 
+    /// Size in bytes of each head/middle/tail sample of the mid hash (0 = disabled)
+    #[arg(long, default_value = "65536")]
+    pub mid_hash_size: usize,
+
     /// Full hash buffer size in MB
     #[arg(long, default_value = "1")]
     pub full_buffer_size: usize,
 
-    /// Output results to JSON file
+    /// Memory-map files of 16 MiB or more for the full hash (BLAKE3 then hashes them in parallel)
+    #[arg(long)]
+    pub mmap: bool,
+
+    /// Stop hashing after N duplicate groups, largest files first, to bound memory on runaway trees; results are then partial
+    #[arg(long, value_name = "N", value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+    pub max_groups: Option<usize>,
+
+    /// Only hash the first BYTES of each candidate and report probable duplicates, without reading files in full
+    #[arg(
+        long,
+        value_name = "BYTES",
+        value_parser = parse_nonzero_size,
+        conflicts_with_all = ["verify", "sample_verify", "stream", "output_sums", "delete", "hardlink", "reflink", "move_to", "symlink", "interactive"]
+    )]
+    pub prefix_only: Option<usize>,
+
+    /// Print each duplicate group to stderr as soon as it is confirmed during hashing
+    #[arg(long)]
+    pub stream: bool,
+
+    /// Print only totals and the largest groups instead of every group
+    #[arg(long)]
+    pub summary: bool,
+
+    /// Order of duplicate groups in the listing
+    #[arg(long, value_enum, default_value_t = SortBy::Wasted)]
+    pub sort_by: SortBy,
+
+    /// Reverse the listing order
+    #[arg(long)]
+    pub reverse: bool,
+
+    /// Only list the first N groups after sorting; totals still count every group
+    #[arg(long, value_name = "N")]
+    pub top: Option<usize>,
+
+    /// Show permissions, owner and mtime of each file, in the listing and in JSON output
+    #[arg(long)]
+    pub show_metadata: bool,
+
+    /// Show the device ids each group's copies live on, in the listing and in JSON output
+    #[arg(long)]
+    pub show_devices: bool,
+
+    /// List groups clustered by the directories their copies live in, e.g. two mirror trees
+    #[arg(long, conflicts_with = "summary")]
+    pub group_by_dir: bool,
+
+    /// Also break down duplicates and wasted space by file extension
+    #[arg(long)]
+    pub by_extension: bool,
+
+    /// Also show how duplicates and wasted space spread over file size ranges
+    #[arg(long)]
+    pub size_histogram: bool,
+
+    /// Print results as JSON to stdout instead of the human-readable listing
+    #[arg(long, conflicts_with_all = ["summary", "interactive"])]
+    pub json_stdout: bool,
+
+    /// After the scan, print one stable line like `DUPFIND groups=3 files=7 wasted=1.2GB ...` to stdout
+    #[arg(long, conflicts_with = "json_stdout")]
+    pub summary_line: bool,
+
+    /// Output results to JSON file, zstd-compressed when the name ends in .zst
     #[arg(short, long)]
     pub output_json: Option<PathBuf>,
 
-    /// Skip files smaller than this size in bytes
-    #[arg(long, default_value = "0")]
+    /// zstd compression level (1-22) for an --output-json path ending in .zst
+    #[arg(long, value_name = "LEVEL", requires = "output_json", default_value_t = DEFAULT_ZSTD_LEVEL, value_parser = clap::value_parser!(i32).range(1..=22))]
+    pub zstd_level: i32,
+
+    /// Write one JSON object per duplicate group per line (NDJSON) as soon as hashing confirms it, like --stream
+    #[arg(
+        long,
+        value_name = "FILE",
+        conflicts_with_all = ["watch", "verify", "sample_verify", "cross_only", "cross_device_only", "report_duplicates_only_within_root", "same_name", "baseline"]
+    )]
+    pub output_ndjson: Option<PathBuf>,
+
+    /// Output one row per duplicate file to a CSV file
+    #[arg(long, value_name = "FILE")]
+    pub output_csv: Option<PathBuf>,
+
+    /// Output a self-contained HTML report with collapsible, sortable groups
+    #[arg(long, value_name = "FILE")]
+    pub output_html: Option<PathBuf>,
+
+    /// Append the scan to a SQLite database (scans, groups and files tables), created if absent
+    #[cfg(feature = "sqlite")]
+    #[arg(long, value_name = "FILE")]
+    pub output_sqlite: Option<PathBuf>,
+
+    /// Output a `HASH  PATH` line for every scanned file, as sha256sum/b3sum do
+    #[arg(long, value_name = "FILE")]
+    pub output_sums: Option<PathBuf>,
+
+    /// Report all empty files as one duplicate group (they are never hashed)
+    #[arg(long)]
+    pub include_empty: bool,
+
+    /// Also group visually similar images (resized or re-encoded copies) by perceptual hash
+    #[arg(long)]
+    pub similar_images: bool,
+
+    /// Maximum perceptual hash distance, out of 64 bits, for two images to count as similar
+    #[arg(long, default_value_t = dupfind::similar::DEFAULT_THRESHOLD, requires = "similar_images")]
+    pub image_threshold: u32,
+
+    /// Skip files smaller than this size (e.g. 4096, 500k, 10MB, 1.5GiB)
+    #[arg(long, default_value = "0", value_parser = parse_size)]
     pub min_size: u64,
 
-    /// Maximum number of threads (0 = auto)
-    #[arg(long, default_value = "0")]
+    /// Skip files larger than this size (same units as --min-size)
+    #[arg(long, value_parser = parse_size)]
+    pub max_size: Option<u64>,
+
+    /// Only consider files modified after this date (2024-01-31) or this long ago (7d, 12h)
+    #[arg(long, value_name = "WHEN", value_parser = parse_time)]
+    pub modified_after: Option<SystemTime>,
+
+    /// Only consider files modified before this date or this long ago
+    #[arg(long, value_name = "WHEN", value_parser = parse_time)]
+    pub modified_before: Option<SystemTime>,
+
+    /// Maximum number of threads (0 = one per CPU, at most 1024)
+    #[arg(long, default_value = "0", value_parser = parse_threads)]
     pub threads: usize,
+
+    /// Reuse full hashes from this cache file and update it after the scan
+    #[arg(long, value_name = "FILE")]
+    pub cache: Option<PathBuf>,
+
+    /// Save hashing progress every 30s so an interrupted scan can continue where it stopped
+    #[arg(long)]
+    pub resume: bool,
+
+    /// Compare duplicate candidates byte by byte before reporting them
+    #[arg(long)]
+    pub verify: bool,
+
+    /// Check COUNT random 4 KiB ranges of every duplicate instead of whole files: much faster on huge files
+    #[arg(long, value_name = "COUNT", conflicts_with = "verify", value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+    pub sample_verify: Option<usize>,
+
+    /// Seed for --sample-verify offsets, to reproduce a run (default: random, logged)
+    #[arg(long, requires = "sample_verify")]
+    pub seed: Option<u64>,
+
+    /// Delete redundant copies, keeping one file per group
+    #[arg(long, conflicts_with = "hardlink")]
+    pub delete: bool,
+
+    /// Replace redundant copies with hard links to the kept file
+    #[arg(long, conflicts_with = "delete")]
+    pub hardlink: bool,
+
+    /// Replace redundant copies with copy-on-write clones of the kept file (Btrfs, XFS, APFS)
+    #[arg(long, conflicts_with_all = ["delete", "hardlink"])]
+    pub reflink: bool,
+
+    /// Move redundant copies into this directory, keeping their original paths beneath it
+    #[arg(long, value_name = "DIR", conflicts_with_all = ["delete", "hardlink", "reflink"])]
+    pub move_to: Option<PathBuf>,
+
+    /// Replace redundant copies with relative symlinks to the kept file
+    #[arg(long, conflicts_with_all = ["delete", "hardlink", "reflink", "move_to"])]
+    pub symlink: bool,
+
+    /// With --symlink, point the links at absolute paths instead
+    #[arg(long, requires = "symlink")]
+    pub absolute_symlinks: bool,
+
+    /// Where --symlink records each replaced file, to undo it later
+    #[arg(long, value_name = "FILE", requires = "symlink", default_value = SYMLINK_MANIFEST_FILENAME)]
+    pub symlink_manifest: PathBuf,
+
+    /// Append a JSON line per group changed by --delete, --hardlink, --reflink, --move-to, --symlink or --interactive
+    #[arg(long, value_name = "FILE")]
+    pub audit_log: Option<PathBuf>,
+
+    /// Which file to keep in each group when acting on duplicates
+    #[arg(long, value_enum, default_value_t = KeepStrategy::First)]
+    pub keep: KeepStrategy,
+
+    /// Keep a copy under DIR if the group has one; repeat to rank several, earliest first
+    #[arg(long, value_name = "DIR")]
+    pub prefer: Vec<PathBuf>,
+
+    /// Never delete or replace files under PATH (also not in --interactive), keeping one of them in each group; repeatable
+    #[arg(long, value_name = "PATH")]
+    pub protect: Vec<PathBuf>,
+
+    /// Review each duplicate group in a terminal UI and pick copies to delete
+    #[arg(long, conflicts_with_all = ["delete", "hardlink", "reflink", "move_to", "symlink"])]
+    pub interactive: bool,
+
+    /// Run a command per duplicate file ({} is the path) or per group ({+} is all paths), before any other action
+    #[arg(long, value_name = "CMD")]
+    pub exec: Option<String>,
+
+    /// Show what --delete/--hardlink/--reflink/--move-to/--symlink/--interactive would do without touching the filesystem
+    #[arg(long)]
+    pub dry_run: bool,
+
+    /// Act on duplicates without asking for confirmation after the preview
+    #[arg(short = 'y', long)]
+    pub yes: bool,
+
+    /// Exit with status 0 even when duplicates are found
+    #[arg(long)]
+    pub exit_zero: bool,
+
+    /// Print the JSON Schema of the --output-json format and exit
+    #[arg(long, hide = true)]
+    pub print_schema: bool,
+}
+
+impl Args {
+    /// Parse the command line and fill unset flags from the config file.
+    /// A missing default config is fine; a missing `--config` file is an error.
+    pub fn parse_with_config() -> Result<Self> {
+        let matches = Self::command().get_matches();
+        let mut args = Self::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+
+        let config = match &args.config {
+            Some(path) => Some(Config::load(path)?),
+            None => match config::default_path() {
+                Some(path) if path.is_file() => Some(Config::load(&path)?),
+                _ => None,
+            },
+        };
+        if let Some(config) = config {
+            config.apply(&mut args, &matches)?;
+        }
+        Ok(args)
+    }
+}
+
+fn parse_threads(input: &str) -> Result<usize, String> {
+    let threads: usize = input
+        .parse()
+        .map_err(|_| format!("invalid thread count: '{}'", input))?;
+    if threads > MAX_THREADS {
+        return Err(format!("at most {} threads are supported", MAX_THREADS));
+    }
+    Ok(threads)
+}
+
+/// Parse a point in time: a UTC date (`2024-01-31`), a UTC timestamp
+/// (`2024-01-31 08:00:00`), or a duration before now (`7d`, `12h`, `2w`).
+pub fn parse_time(input: &str) -> Result<SystemTime, String> {
+    let input = input.trim();
+    if let Ok(ago) = humantime::parse_duration(input) {
+        return SystemTime::now()
+            .checked_sub(ago)
+            .ok_or_else(|| format!("duration out of range: '{}'", input));
+    }
+
+    let timestamp = if input.len() == 10 {
+        format!("{} 00:00:00", input)
+    } else {
+        input.to_string()
+    };
+    humantime::parse_rfc3339_weak(&timestamp)
+        .map_err(|_| format!("invalid date or duration: '{}'", input))
+}
+
+/// Parse a byte size with an optional unit suffix. Decimal units (k, KB, MB,
+/// GB, TB) are powers of 1000, binary units (KiB, MiB, GiB, TiB) powers of 1024.
+pub fn parse_size(input: &str) -> Result<u64, String> {
+    let input = input.trim();
+    let split = input
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(input.len());
+    let (number, unit) = input.split_at(split);
+
+    let value: f64 = number
+        .parse()
+        .map_err(|_| format!("invalid size: '{}'", input))?;
+    let multiplier: u64 = match unit.trim().to_ascii_lowercase().as_str() {
+        "" | "b" => 1,
+        "k" | "kb" => 1_000,
+        "m" | "mb" => 1_000_000,
+        "g" | "gb" => 1_000_000_000,
+        "t" | "tb" => 1_000_000_000_000,
+        "ki" | "kib" => 1 << 10,
+        "mi" | "mib" => 1 << 20,
+        "gi" | "gib" => 1 << 30,
+        "ti" | "tib" => 1 << 40,
+        other => return Err(format!("unknown size unit: '{}'", other)),
+    };
+
+    let bytes = value * multiplier as f64;
+    if !bytes.is_finite() || bytes > u64::MAX as f64 {
+        return Err(format!("size out of range: '{}'", input));
+    }
+    Ok(bytes.round() as u64)
+}
+
+/// `parse_size` for sizes held in memory: at least one byte, and within
+/// `usize` on this platform.
+fn parse_nonzero_size(input: &str) -> Result<usize, String> {
+    match parse_size(input)? {
+        0 => Err("size must be greater than zero".to_string()),
+        bytes => usize::try_from(bytes).map_err(|_| format!("size out of range: '{}'", input)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_size_units() {
+        assert_eq!(parse_size("4096"), Ok(4096));
+        assert_eq!(parse_size("500k"), Ok(500_000));
+        assert_eq!(parse_size("10MB"), Ok(10_000_000));
+        assert_eq!(parse_size("10 mb"), Ok(10_000_000));
+        assert_eq!(parse_size("1.5GiB"), Ok(1_610_612_736));
+        assert_eq!(parse_size("2KiB"), Ok(2048));
+    }
+
+    #[test]
+    fn test_parse_time() {
+        let date = parse_time("2024-01-31").unwrap();
+        let secs = date
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        assert_eq!(secs, 1_706_659_200);
+
+        let week_ago = parse_time("7d").unwrap();
+        let age = SystemTime::now().duration_since(week_ago).unwrap();
+        assert_eq!(age.as_secs() / 86_400, 7);
+
+        assert!(parse_time("last tuesday").is_err());
+    }
+
+    #[test]
+    fn test_parse_threads_bounds() {
+        assert_eq!(parse_threads("0"), Ok(0));
+        assert_eq!(parse_threads("16"), Ok(16));
+        assert!(parse_threads("1025").is_err());
+        assert!(parse_threads("-1").is_err());
+    }
+
+    #[test]
+    fn test_parse_size_rejects_garbage() {
+        assert!(parse_size("").is_err());
+        assert!(parse_size("ten").is_err());
+        assert!(parse_size("10XB").is_err());
+    }
+
+    #[test]
+    fn test_parse_nonzero_size() {
+        assert_eq!(parse_nonzero_size("4KiB"), Ok(4096));
+        assert!(parse_nonzero_size("0").is_err());
+        assert!(parse_nonzero_size("0.1").is_err());
+    }
 }
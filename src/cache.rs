@@ -0,0 +1,306 @@
+use anyhow::{Context, Result, bail};
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::fs::{self, File, TryLockError};
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant, UNIX_EPOCH};
+
+use crate::hasher::HashAlgorithm;
+use crate::utils::write_atomically;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct CacheEntry {
+    size: u64,
+    mtime_nanos: u128,
+    hash: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CacheFile {
+    algorithm: HashAlgorithm,
+    entries: HashMap<PathBuf, CacheEntry>,
+}
+
+/// Full-file hashes from previous runs, keyed by absolute path and validated
+/// against the file's current size and modification time.
+pub struct HashCache {
+    algorithm: HashAlgorithm,
+    entries: Mutex<HashMap<PathBuf, CacheEntry>>,
+    hits: AtomicU64,
+    /// Advisory lock on the cache file, held until the cache is dropped.
+    /// The OS releases it if the process exits or is interrupted.
+    _lock: Option<File>,
+    checkpoint: Option<Checkpoint>,
+}
+
+/// Periodic saves while hashing, so an interrupted run keeps its work.
+struct Checkpoint {
+    path: PathBuf,
+    every: Duration,
+    last: Mutex<Instant>,
+}
+
+impl HashCache {
+    pub fn new(algorithm: HashAlgorithm) -> Self {
+        Self {
+            algorithm,
+            entries: Mutex::new(HashMap::new()),
+            hits: AtomicU64::new(0),
+            _lock: None,
+            checkpoint: None,
+        }
+    }
+
+    /// Save the cache to `path` at most once per `every` as new hashes come in.
+    pub fn checkpoint_every(&mut self, path: impl Into<PathBuf>, every: Duration) {
+        self.checkpoint = Some(Checkpoint {
+            path: path.into(),
+            every,
+            last: Mutex::new(Instant::now()),
+        });
+    }
+
+    /// Write a checkpoint if one is due. Only one thread saves at a time;
+    /// the others carry on hashing.
+    fn maybe_checkpoint(&self) {
+        let Some(checkpoint) = &self.checkpoint else {
+            return;
+        };
+        let Ok(mut last) = checkpoint.last.try_lock() else {
+            return;
+        };
+        if last.elapsed() < checkpoint.every {
+            return;
+        }
+        match save_cache(&checkpoint.path, self) {
+            Ok(()) => debug!("Checkpointed {} hashes", self.len()),
+            Err(e) => warn!("Failed to checkpoint hash cache: {:#}", e),
+        }
+        *last = Instant::now();
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn hits(&self) -> u64 {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    /// Return the cached hash for `path` if its size and mtime are unchanged,
+    /// otherwise run `compute` and remember the result.
+    pub fn get_or_compute(
+        &self,
+        path: &Path,
+        compute: impl FnOnce() -> Result<String>,
+    ) -> Result<String> {
+        let metadata = fs::metadata(path)?;
+        let size = metadata.len();
+        let mtime_nanos = match metadata.modified() {
+            Ok(t) => t.duration_since(UNIX_EPOCH).map(|d| d.as_nanos()).ok(),
+            Err(_) => None,
+        };
+        // Without a usable mtime we cannot tell whether the file changed.
+        let Some(mtime_nanos) = mtime_nanos else {
+            return compute();
+        };
+        let key = std::path::absolute(path)?;
+
+        {
+            let mut entries = self.entries.lock().unwrap();
+            match entries.get(&key) {
+                Some(entry) if entry.size == size && entry.mtime_nanos == mtime_nanos => {
+                    self.hits.fetch_add(1, Ordering::Relaxed);
+                    return Ok(entry.hash.clone());
+                }
+                Some(_) => {
+                    entries.remove(&key);
+                }
+                None => {}
+            }
+        }
+
+        let hash = compute()?;
+        self.entries.lock().unwrap().insert(
+            key,
+            CacheEntry {
+                size,
+                mtime_nanos,
+                hash: hash.clone(),
+            },
+        );
+        self.maybe_checkpoint();
+        Ok(hash)
+    }
+}
+
+/// Take an exclusive lock on `<path>.lock`, failing if another run holds it.
+fn lock_cache(path: &Path) -> Result<File> {
+    let mut lock_path = OsString::from(path.as_os_str());
+    lock_path.push(".lock");
+    let lock_path = PathBuf::from(lock_path);
+
+    let file = File::create(&lock_path)
+        .with_context(|| format!("Failed to create lock file: {}", lock_path.display()))?;
+    match file.try_lock() {
+        Ok(()) => Ok(file),
+        Err(TryLockError::WouldBlock) => bail!(
+            "Another dupfind run is using the cache {}; try again once it finishes",
+            path.display()
+        ),
+        Err(TryLockError::Error(e)) => {
+            Err(e).with_context(|| format!("Failed to lock {}", lock_path.display()))
+        }
+    }
+}
+
+/// Lock and load a cache file. A missing, unreadable, or differently-hashed
+/// cache yields an empty cache rather than an error; a cache locked by
+/// another run is an error.
+pub fn load_cache(path: &Path, algorithm: HashAlgorithm) -> Result<HashCache> {
+    let mut cache = HashCache::new(algorithm);
+    cache._lock = Some(lock_cache(path)?);
+    if !path.exists() {
+        return Ok(cache);
+    }
+
+    let file = File::open(path)
+        .with_context(|| format!("Failed to open cache file: {}", path.display()))?;
+    let parsed: CacheFile = match serde_json::from_reader(BufReader::new(file)) {
+        Ok(c) => c,
+        Err(e) => {
+            warn!("Ignoring unreadable cache {}: {}", path.display(), e);
+            return Ok(cache);
+        }
+    };
+
+    if parsed.algorithm != algorithm {
+        info!(
+            "Cache {} was built with {}, starting fresh for {}",
+            path.display(),
+            parsed.algorithm,
+            algorithm
+        );
+        return Ok(cache);
+    }
+
+    *cache.entries.lock().unwrap() = parsed.entries;
+    Ok(cache)
+}
+
+/// Write the cache with `write_atomically`, so a run killed mid-save leaves
+/// the previous cache intact.
+pub fn save_cache(path: &Path, cache: &HashCache) -> Result<()> {
+    let data = CacheFile {
+        algorithm: cache.algorithm,
+        entries: cache.entries.lock().unwrap().clone(),
+    };
+
+    write_atomically(path, |writer| {
+        serde_json::to_writer(writer, &data).context("Failed to serialize hash cache")
+    })
+    .with_context(|| format!("Failed to save cache file: {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_cache_roundtrip_and_hit() {
+        let dir = tempdir().unwrap();
+        let file = dir.path().join("data.txt");
+        fs::write(&file, "content").unwrap();
+        let cache_path = dir.path().join("cache.json");
+
+        let cache = load_cache(&cache_path, HashAlgorithm::Blake3).unwrap();
+        let hash = cache
+            .get_or_compute(&file, || Ok("abc".to_string()))
+            .unwrap();
+        assert_eq!(hash, "abc");
+        save_cache(&cache_path, &cache).unwrap();
+        drop(cache);
+
+        let cache = load_cache(&cache_path, HashAlgorithm::Blake3).unwrap();
+        let hash = cache
+            .get_or_compute(&file, || panic!("should be served from cache"))
+            .unwrap();
+        assert_eq!(hash, "abc");
+        assert_eq!(cache.hits(), 1);
+    }
+
+    #[test]
+    fn test_cache_invalidated_on_size_change() {
+        let dir = tempdir().unwrap();
+        let file = dir.path().join("data.txt");
+        fs::write(&file, "content").unwrap();
+
+        let cache = HashCache::new(HashAlgorithm::Blake3);
+        cache
+            .get_or_compute(&file, || Ok("old".to_string()))
+            .unwrap();
+
+        fs::write(&file, "different content").unwrap();
+        let hash = cache
+            .get_or_compute(&file, || Ok("new".to_string()))
+            .unwrap();
+        assert_eq!(hash, "new");
+        assert_eq!(cache.hits(), 0);
+    }
+
+    #[test]
+    fn test_cache_discarded_for_other_algorithm() {
+        let dir = tempdir().unwrap();
+        let file = dir.path().join("data.txt");
+        fs::write(&file, "content").unwrap();
+        let cache_path = dir.path().join("cache.json");
+
+        let cache = HashCache::new(HashAlgorithm::Blake3);
+        cache
+            .get_or_compute(&file, || Ok("abc".to_string()))
+            .unwrap();
+        save_cache(&cache_path, &cache).unwrap();
+
+        let cache = load_cache(&cache_path, HashAlgorithm::Sha256).unwrap();
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn test_checkpoint_saves_while_hashing() {
+        let dir = tempdir().unwrap();
+        let file = dir.path().join("data.txt");
+        fs::write(&file, "content").unwrap();
+        let cache_path = dir.path().join("cache.json");
+
+        let mut cache = HashCache::new(HashAlgorithm::Blake3);
+        cache.checkpoint_every(&cache_path, Duration::ZERO);
+        cache
+            .get_or_compute(&file, || Ok("abc".to_string()))
+            .unwrap();
+        drop(cache);
+
+        let cache = load_cache(&cache_path, HashAlgorithm::Blake3).unwrap();
+        assert_eq!(cache.len(), 1);
+    }
+
+    #[test]
+    fn test_cache_locked_by_another_run() {
+        let dir = tempdir().unwrap();
+        let cache_path = dir.path().join("cache.json");
+
+        let first = load_cache(&cache_path, HashAlgorithm::Blake3).unwrap();
+        assert!(load_cache(&cache_path, HashAlgorithm::Blake3).is_err());
+        drop(first);
+        assert!(load_cache(&cache_path, HashAlgorithm::Blake3).is_ok());
+    }
+}
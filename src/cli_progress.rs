@@ -0,0 +1,182 @@
+use dupfind::output::format_bytes;
+use dupfind::{Phase, ProgressReporter};
+use indicatif::{HumanDuration, ProgressBar, ProgressDrawTarget, ProgressState, ProgressStyle};
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Terminal progress bars driven by the scan pipeline, one per phase.
+pub struct CliProgress {
+    current: Mutex<Option<(Phase, ProgressBar, u64)>>,
+    bytes_hashed: Arc<AtomicU64>,
+    files_scanned: AtomicU64,
+    /// Keep every bar hidden, for `--quiet` or when stderr is not a terminal.
+    quiet: bool,
+    /// Minimum time between redraws.
+    interval: Duration,
+}
+
+impl CliProgress {
+    pub fn new(quiet: bool, interval: Duration) -> Self {
+        Self {
+            current: Mutex::new(None),
+            bytes_hashed: Arc::new(AtomicU64::new(0)),
+            files_scanned: AtomicU64::new(0),
+            quiet,
+            interval,
+        }
+    }
+
+    /// Print a line to stderr above the running bar, if any.
+    pub fn println(&self, line: &str) {
+        self.suspend(|| eprintln!("{}", line));
+    }
+
+    /// Run `f` with the running bar cleared, so its output is not overdrawn.
+    pub fn suspend<R>(&self, f: impl FnOnce() -> R) -> R {
+        match self.current.lock().unwrap().as_ref() {
+            Some((_, bar, _)) => bar.suspend(f),
+            None => f(),
+        }
+    }
+
+    /// Where the scan stands, from the same counters the bars show.
+    pub fn stats_line(&self) -> String {
+        let scanned = self.files_scanned.load(Ordering::Relaxed);
+        let read = format_bytes(self.bytes_hashed.load(Ordering::Relaxed));
+        match self.current.lock().unwrap().as_ref() {
+            None => "starting".to_string(),
+            Some((Phase::Scan, _, _)) => format!("{} files found so far", scanned),
+            // The quick and mid hash tiers run after grouping and report nothing.
+            Some((Phase::Group, bar, done)) if Some(*done) == bar.length() => {
+                format!("{} files found, comparing quick hashes", scanned)
+            }
+            Some((Phase::Group, _, _)) => format!("{} files found, grouping by size", scanned),
+            Some((Phase::Hash, bar, done)) => format!(
+                "{} files found, {}/{} candidates hashed, {} read, ETA {}",
+                scanned,
+                done,
+                bar.length().unwrap_or(0),
+                read,
+                HumanDuration(bar.eta())
+            ),
+            Some((Phase::Images, bar, done)) => format!(
+                "{} files found, {} read, {}/{} images compared",
+                scanned,
+                read,
+                done,
+                bar.length().unwrap_or(0)
+            ),
+        }
+    }
+
+    /// Finish the bar of the phase that is still running, if any.
+    pub fn finish(&self) {
+        if let Some((phase, bar, done)) = self.current.lock().unwrap().take() {
+            finish_bar(phase, &bar, done);
+        }
+    }
+}
+
+fn start_bar(phase: Phase, total: Option<u64>, bytes_hashed: &Arc<AtomicU64>) -> ProgressBar {
+    match phase {
+        Phase::Scan => {
+            let bar = ProgressBar::new_spinner();
+            bar.set_style(
+                ProgressStyle::default_spinner()
+                    .template("{spinner:.green} {msg}")
+                    .unwrap(),
+            );
+            bar.set_message("Scanning files...");
+            bar
+        }
+        Phase::Group => {
+            let bar = ProgressBar::new(total.unwrap_or(0));
+            bar.set_style(
+                ProgressStyle::default_bar()
+                    .template("{bar:40.cyan/blue} {pos:>7}/{len:7} {msg}")
+                    .unwrap(),
+            );
+            bar.set_message("Grouping by size...");
+            bar
+        }
+        Phase::Hash => {
+            let bar = ProgressBar::new(total.unwrap_or(0));
+            let bytes_hashed = Arc::clone(bytes_hashed);
+            bar.set_style(
+                ProgressStyle::default_bar()
+                    .template(
+                        "{bar:40.green/yellow} {pos:>7}/{len:7} {percent:>3}% {throughput} ETA {eta} {msg}",
+                    )
+                    .unwrap()
+                    .with_key("throughput", move |state: &ProgressState, w: &mut dyn Write| {
+                        let secs = state.elapsed().as_secs_f64();
+                        let bytes = bytes_hashed.load(Ordering::Relaxed) as f64;
+                        let rate = if secs > 0.0 { bytes / secs } else { 0.0 };
+                        let _ = write!(w, "{}/s", format_bytes(rate as u64));
+                    }),
+            );
+            bar.set_message("Computing hashes...");
+            bar
+        }
+        Phase::Images => {
+            let bar = ProgressBar::new(total.unwrap_or(0));
+            bar.set_style(
+                ProgressStyle::default_bar()
+                    .template("{bar:40.magenta/blue} {pos:>7}/{len:7} {msg}")
+                    .unwrap(),
+            );
+            bar.set_message("Comparing images...");
+            bar
+        }
+    }
+}
+
+fn finish_bar(phase: Phase, bar: &ProgressBar, done: u64) {
+    let msg = match phase {
+        Phase::Scan => format!("Found {} files", done),
+        Phase::Group => format!("Grouped {} files by size", done),
+        Phase::Hash => "Hash computation completed".to_string(),
+        Phase::Images => format!("Compared {} images", done),
+    };
+    bar.finish_with_message(msg);
+}
+
+impl ProgressReporter for CliProgress {
+    fn on_progress(&self, phase: Phase, done: u64, total: Option<u64>) {
+        let mut current = self.current.lock().unwrap();
+        if current.as_ref().is_some_and(|(p, _, _)| *p != phase)
+            && let Some((prev, bar, prev_done)) = current.take()
+        {
+            finish_bar(prev, &bar, prev_done);
+        }
+
+        let (_, bar, last) = current.get_or_insert_with(|| {
+            let bar = start_bar(phase, total, &self.bytes_hashed);
+            if self.quiet {
+                bar.set_draw_target(ProgressDrawTarget::hidden());
+            } else {
+                // Redraws are throttled by time rather than by how often the
+                // pipeline reports, and the tick keeps ETA and throughput
+                // moving while a large file is hashed.
+                let hz = (1000 / self.interval.as_millis()).clamp(1, 100) as u8;
+                bar.set_draw_target(ProgressDrawTarget::stderr_with_hz(hz));
+                bar.enable_steady_tick(self.interval);
+            }
+            (phase, bar, 0)
+        });
+        *last = done;
+        if phase == Phase::Scan {
+            self.files_scanned.store(done, Ordering::Relaxed);
+        }
+        match phase {
+            Phase::Scan => bar.set_message(format!("Scanned {} files...", done)),
+            Phase::Group | Phase::Hash | Phase::Images => bar.set_position(done),
+        }
+    }
+
+    fn on_bytes_hashed(&self, bytes: u64) {
+        self.bytes_hashed.store(bytes, Ordering::Relaxed);
+    }
+}
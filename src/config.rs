@@ -0,0 +1,158 @@
+use anyhow::{Context, Result};
+use clap::ArgMatches;
+use clap::parser::ValueSource;
+use serde::Deserialize;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::args::{Args, parse_size};
+use dupfind::HashAlgorithm;
+
+/// A size given either as a number of bytes or with a unit, like `--min-size`.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Size {
+    Bytes(u64),
+    Text(String),
+}
+
+impl Size {
+    fn bytes(&self) -> Result<u64> {
+        match self {
+            Size::Bytes(b) => Ok(*b),
+            Size::Text(s) => parse_size(s).map_err(anyhow::Error::msg),
+        }
+    }
+}
+
+/// Defaults read from `config.toml`. Every field is optional and only fills
+/// in flags that were not given on the command line.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// Added to any `--ignore` directories from the command line
+    ignore: Option<Vec<String>>,
+    /// Added to any `--exclude` globs from the command line
+    exclude: Option<Vec<String>>,
+    min_size: Option<Size>,
+    max_size: Option<Size>,
+    algorithm: Option<HashAlgorithm>,
+    threads: Option<usize>,
+    hidden: Option<bool>,
+    #[serde(alias = "no_ignore")]
+    no_gitignore: Option<bool>,
+}
+
+/// `$XDG_CONFIG_HOME/dupfind/config.toml`, falling back to `~/.config`.
+pub fn default_path() -> Option<PathBuf> {
+    env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::home_dir().map(|home| home.join(".config")))
+        .map(|dir| dir.join("dupfind").join("config.toml"))
+}
+
+/// `$XDG_CACHE_HOME/dupfind/resume.json`, falling back to `~/.cache`.
+pub fn default_resume_path() -> Option<PathBuf> {
+    env::var_os("XDG_CACHE_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::home_dir().map(|home| home.join(".cache")))
+        .map(|dir| dir.join("dupfind").join("resume.json"))
+}
+
+impl Config {
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read config file: {}", path.display()))?;
+        toml::from_str(&text).with_context(|| format!("Invalid config file: {}", path.display()))
+    }
+
+    /// Fill in every setting the user did not pass explicitly.
+    pub fn apply(self, args: &mut Args, matches: &ArgMatches) -> Result<()> {
+        let from_cli = |id: &str| matches.value_source(id) == Some(ValueSource::CommandLine);
+
+        if let Some(mut ignore) = self.ignore {
+            ignore.append(&mut args.ignore);
+            args.ignore = ignore;
+        }
+        if let Some(mut exclude) = self.exclude {
+            exclude.append(&mut args.exclude);
+            args.exclude = exclude;
+        }
+        if let Some(size) = self.min_size
+            && !from_cli("min_size")
+        {
+            args.min_size = size.bytes().context("Invalid min_size in config")?;
+        }
+        if let Some(size) = self.max_size
+            && !from_cli("max_size")
+        {
+            args.max_size = Some(size.bytes().context("Invalid max_size in config")?);
+        }
+        if let Some(algorithm) = self.algorithm
+            && !from_cli("algorithm")
+        {
+            args.algorithm = algorithm;
+        }
+        if let Some(threads) = self.threads
+            && !from_cli("threads")
+        {
+            args.threads = threads;
+        }
+        if let Some(hidden) = self.hidden
+            && !from_cli("hidden")
+        {
+            args.hidden = hidden;
+        }
+        if let Some(no_gitignore) = self.no_gitignore
+            && !from_cli("no_gitignore")
+        {
+            args.no_gitignore = no_gitignore;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::{CommandFactory, FromArgMatches};
+
+    fn parse(cli: &[&str], config: &str) -> Args {
+        let matches = Args::command().get_matches_from(cli);
+        let mut args = Args::from_arg_matches(&matches).unwrap();
+        let config: Config = toml::from_str(config).unwrap();
+        config.apply(&mut args, &matches).unwrap();
+        args
+    }
+
+    #[test]
+    fn test_config_fills_defaults() {
+        let args = parse(
+            &["dupfind", "/data"],
+            "ignore = [\"node_modules\"]\nmin_size = \"1MB\"\nalgorithm = \"xxh3\"\nthreads = 4\n",
+        );
+        assert_eq!(args.ignore, vec!["node_modules"]);
+        assert_eq!(args.min_size, 1_000_000);
+        assert_eq!(args.algorithm, HashAlgorithm::Xxh3);
+        assert_eq!(args.threads, 4);
+    }
+
+    #[test]
+    fn test_command_line_wins() {
+        let args = parse(
+            &["dupfind", "/data", "--min-size", "10", "-i", "tmp"],
+            "ignore = [\"cache\"]\nmin_size = 4096\nno_ignore = true\n",
+        );
+        assert_eq!(args.min_size, 10);
+        assert_eq!(args.ignore, vec!["cache", "tmp"]);
+        assert!(args.no_gitignore);
+    }
+
+    #[test]
+    fn test_unknown_keys_rejected() {
+        assert!(toml::from_str::<Config>("min_sise = 10").is_err());
+    }
+}
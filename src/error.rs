@@ -0,0 +1,64 @@
+use std::io;
+use std::path::PathBuf;
+use thiserror::Error;
+
+/// Errors returned by the public API, so callers can tell an interrupted
+/// scan from a failed one without matching on messages.
+#[derive(Debug, Error)]
+pub enum DupFindError {
+    #[error("Path does not exist: {}", .0.display())]
+    PathNotFound(PathBuf),
+
+    #[error("Path is not a directory: {}", .0.display())]
+    NotADirectory(PathBuf),
+
+    #[error("Interrupted by user")]
+    Interrupted,
+
+    #[error("{}: {source}", path.display())]
+    Io {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+
+    #[error("Invalid results JSON: {0}")]
+    Serialize(#[from] serde_json::Error),
+
+    /// Anything else, with its context chain intact.
+    #[error(transparent)]
+    Other(anyhow::Error),
+}
+
+impl DupFindError {
+    pub fn io(path: impl Into<PathBuf>, source: io::Error) -> Self {
+        DupFindError::Io {
+            path: path.into(),
+            source,
+        }
+    }
+}
+
+impl From<anyhow::Error> for DupFindError {
+    /// Recover a typed error raised deep inside the scan, even under added context.
+    fn from(err: anyhow::Error) -> Self {
+        err.downcast().unwrap_or_else(DupFindError::Other)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Context;
+
+    #[test]
+    fn test_typed_error_survives_anyhow() {
+        let err = Err::<(), _>(DupFindError::Interrupted)
+            .context("Hashing failed")
+            .unwrap_err();
+        assert!(matches!(DupFindError::from(err), DupFindError::Interrupted));
+
+        let err = DupFindError::from(anyhow::anyhow!("something else"));
+        assert_eq!(err.to_string(), "something else");
+    }
+}
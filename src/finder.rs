@@ -0,0 +1,1124 @@
+use anyhow::{Context, Result, bail};
+use log::{debug, info, warn};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use std::sync::mpsc::Sender;
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::cache::{self, HashCache};
+use crate::error::DupFindError;
+use crate::hasher::{
+    self, EarlyQuickHashes, HashAlgorithm, HashCounters, HashOptions, KnownHashes, Verification,
+};
+use crate::output::build_results;
+use crate::progress::{NoProgress, ProgressReporter};
+use crate::scanner::{
+    HiddenPredicate, ScanOptions, ScannedFile, ScannedTree, collapse_hardlinks, group_by_size,
+    linked_duplicates, read_file_list, retain_cross_device, retain_cross_root, scan_files,
+    scan_files_with, set_aside_symlinks, split_by_name, split_by_subdir,
+};
+use crate::similar;
+use crate::statistics::{
+    DuplicateGroup, Duplicates, ErrorCollector, QuickHashStats, ScanResults, ScanStatistics,
+    average_size, calculate_statistics, retain_new_since,
+};
+use crate::utils::{canonical_or_original, dedupe_roots, validate_path};
+
+/// Wall-clock time per pipeline phase, for `Duplicates::timings`.
+struct PhaseClock {
+    timings: Vec<(&'static str, Duration)>,
+    since: Instant,
+}
+
+impl PhaseClock {
+    fn start() -> Self {
+        Self {
+            timings: Vec::new(),
+            since: Instant::now(),
+        }
+    }
+
+    /// Charge the time since the last lap to `phase`.
+    fn lap(&mut self, phase: &'static str) {
+        let now = Instant::now();
+        self.timings.push((phase, now - self.since));
+        self.since = now;
+    }
+}
+
+/// Most worker threads `DupFinder::threads` accepts; more only adds contention.
+pub const MAX_THREADS: usize = 1024;
+
+/// Configures and runs a duplicate scan without touching stdout.
+///
+/// ```no_run
+/// let results = dupfind::DupFinder::new("/data").min_size(1024).run()?;
+/// println!("{} duplicate groups", results.total_duplicate_groups);
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+pub struct DupFinder {
+    roots: Vec<PathBuf>,
+    file_list: Option<PathBuf>,
+    scan: ScanOptions,
+    hash: HashOptions,
+    threads: usize,
+    cache: Option<PathBuf>,
+    checkpoint: Option<Duration>,
+    verify: Option<Verification>,
+    cross_only: bool,
+    cross_device_only: bool,
+    within_subdir: bool,
+    same_name: bool,
+    min_duplicates: usize,
+    case_insensitive_names: bool,
+    absolute_paths: bool,
+    baseline: Option<ScanResults>,
+    checksums: bool,
+    ignore_hardlinks: bool,
+    include_empty: bool,
+    similar_images: Option<u32>,
+    stream: Option<Sender<DuplicateGroup>>,
+    progress: Arc<dyn ProgressReporter>,
+}
+
+impl DupFinder {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self::with_roots([root])
+    }
+
+    /// Scan several roots. A root repeating or inside another one is dropped,
+    /// so no file is listed twice and each belongs to exactly one root for
+    /// `cross_only` and `within_subdir`.
+    pub fn with_roots<P: Into<PathBuf>>(roots: impl IntoIterator<Item = P>) -> Self {
+        let roots: Vec<PathBuf> = roots.into_iter().map(Into::into).collect();
+        let (roots, overlapping) = dedupe_roots(&roots);
+        for root in &overlapping {
+            debug!("Skipping root {}: covered by another root", root.display());
+        }
+        Self {
+            roots,
+            file_list: None,
+            scan: ScanOptions::default(),
+            hash: HashOptions::default(),
+            threads: 0,
+            cache: None,
+            checkpoint: None,
+            verify: None,
+            cross_only: false,
+            cross_device_only: false,
+            within_subdir: false,
+            same_name: false,
+            min_duplicates: 2,
+            case_insensitive_names: false,
+            absolute_paths: false,
+            baseline: None,
+            checksums: false,
+            ignore_hardlinks: true,
+            include_empty: false,
+            similar_images: None,
+            stream: None,
+            progress: Arc::new(NoProgress),
+        }
+    }
+
+    /// Take candidate files from this newline-separated list (`-` for stdin)
+    /// instead of walking the roots.
+    pub fn file_list(mut self, path: impl Into<PathBuf>) -> Self {
+        self.file_list = Some(path.into());
+        self
+    }
+
+    pub fn follow_links(mut self, follow: bool) -> Self {
+        self.scan.follow_links = follow;
+        self
+    }
+
+    /// Without `follow_links`, also read symlinks to regular files and report
+    /// those pointing at duplicates in `Duplicates::symlinks`. A link and its
+    /// target are never counted as two copies.
+    pub fn report_symlinks(mut self, report: bool) -> Self {
+        self.scan.report_symlinks = report;
+        self
+    }
+
+    /// Stay on the filesystem of each root, like `find -xdev`.
+    pub fn one_filesystem(mut self, enabled: bool) -> Self {
+        self.scan.one_filesystem = enabled;
+        self
+    }
+
+    /// Skip files smaller than this many bytes.
+    pub fn min_size(mut self, bytes: u64) -> Self {
+        self.scan.min_size = bytes;
+        self
+    }
+
+    /// Skip files larger than this many bytes.
+    pub fn max_size(mut self, bytes: Option<u64>) -> Self {
+        self.scan.max_size = bytes;
+        self
+    }
+
+    /// Directory names to skip anywhere in the tree, or paths (possibly globs)
+    /// containing a separator to skip exactly.
+    pub fn ignore<S: Into<String>>(mut self, dirs: impl IntoIterator<Item = S>) -> Self {
+        self.scan.ignore.extend(dirs.into_iter().map(Into::into));
+        self
+    }
+
+    /// Only descend this many directories below each root; 0 scans just the root.
+    pub fn max_depth(mut self, depth: Option<usize>) -> Self {
+        self.scan.max_depth = depth;
+        self
+    }
+
+    /// Skip files fewer than this many directories below each root.
+    pub fn min_depth(mut self, depth: usize) -> Self {
+        self.scan.min_depth = depth;
+        self
+    }
+
+    /// Skip files last modified before this time.
+    pub fn modified_after(mut self, time: Option<SystemTime>) -> Self {
+        self.scan.modified_after = time;
+        self
+    }
+
+    /// Skip files last modified after this time.
+    pub fn modified_before(mut self, time: Option<SystemTime>) -> Self {
+        self.scan.modified_before = time;
+        self
+    }
+
+    /// Scan both dotfiles and OS-hidden entries.
+    pub fn include_hidden(mut self, include: bool) -> Self {
+        self.scan.include_dotfiles = include;
+        self.scan.include_system_hidden = include;
+        self
+    }
+
+    /// Scan files and directories whose name starts with '.'.
+    pub fn include_dotfiles(mut self, include: bool) -> Self {
+        self.scan.include_dotfiles = include;
+        self
+    }
+
+    /// Scan entries the OS flags as hidden (macOS `UF_HIDDEN`, Windows
+    /// hidden/system attributes).
+    pub fn include_system_hidden(mut self, include: bool) -> Self {
+        self.scan.include_system_hidden = include;
+        self
+    }
+
+    /// Also skip entries `predicate` matches, unless system-hidden entries are included.
+    pub fn hidden_predicate(mut self, predicate: HiddenPredicate) -> Self {
+        self.scan.hidden_predicate = Some(predicate);
+        self
+    }
+
+    /// Only scan files whose root-relative path matches one of these globs.
+    pub fn include<S: Into<String>>(mut self, globs: impl IntoIterator<Item = S>) -> Self {
+        self.scan.include.extend(globs.into_iter().map(Into::into));
+        self
+    }
+
+    /// Skip files whose root-relative path matches one of these globs.
+    pub fn exclude<S: Into<String>>(mut self, globs: impl IntoIterator<Item = S>) -> Self {
+        self.scan.exclude.extend(globs.into_iter().map(Into::into));
+        self
+    }
+
+    /// Skip files with these extensions (with or without the dot, any case).
+    pub fn skip_extensions<S: AsRef<str>>(mut self, exts: impl IntoIterator<Item = S>) -> Self {
+        self.scan.skip_extensions.extend(
+            exts.into_iter()
+                .map(|ext| ext.as_ref().trim_start_matches('.').to_lowercase()),
+        );
+        self
+    }
+
+    /// Skip reading `.gitignore` files; `.ignore` and `.dupfindignore` still apply.
+    pub fn no_gitignore(mut self, disable: bool) -> Self {
+        self.scan.no_gitignore = disable;
+        self
+    }
+
+    pub fn algorithm(mut self, algorithm: HashAlgorithm) -> Self {
+        self.hash.algorithm = algorithm;
+        self
+    }
+
+    /// Quick hash sample size in bytes.
+    pub fn quick_hash_size(mut self, bytes: usize) -> Self {
+        self.hash.quick_hash_size = bytes;
+        self
+    }
+
+    /// Quick hash buffer size in KB.
+    pub fn quick_buffer_size(mut self, kb: usize) -> Self {
+        self.hash.quick_buffer_size = kb;
+        self
+    }
+
+    /// Size in bytes of each head/middle/tail sample of the mid hash; 0 disables it.
+    pub fn mid_hash_size(mut self, bytes: usize) -> Self {
+        self.hash.mid_hash_size = bytes;
+        self
+    }
+
+    /// Full hash buffer size in MB.
+    pub fn full_buffer_size(mut self, mb: usize) -> Self {
+        self.hash.full_buffer_size = mb;
+        self
+    }
+
+    /// Stop hashing once `max` duplicate groups are found, to bound memory on
+    /// runaway trees. Candidates are settled largest size first, so the same
+    /// groups are kept every run; the results are then incomplete.
+    pub fn max_groups(mut self, max: Option<usize>) -> Self {
+        self.hash.max_groups = max;
+        self
+    }
+
+    /// Only hash the first `bytes` of each candidate and report files sharing
+    /// size and prefix as probable duplicates, without reading them in full.
+    pub fn prefix_only(mut self, bytes: Option<usize>) -> Self {
+        if let Some(bytes) = bytes {
+            self.hash.quick_hash_size = bytes;
+        }
+        self.hash.prefix_only = bytes.is_some();
+        self
+    }
+
+    /// Memory-map large files for the full hash.
+    pub fn mmap(mut self, mmap: bool) -> Self {
+        self.hash.mmap = mmap;
+        self
+    }
+
+    /// Worker threads for hashing; 0 uses rayon's global pool.
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.threads = threads;
+        self
+    }
+
+    /// Load and update a persistent hash cache at this path.
+    pub fn cache(mut self, path: impl Into<PathBuf>) -> Self {
+        self.cache = Some(path.into());
+        self
+    }
+
+    /// Also save the cache this often while hashing, so an interrupted scan
+    /// can pick up where it stopped. Needs `cache`.
+    pub fn checkpoint_interval(mut self, every: Option<Duration>) -> Self {
+        self.checkpoint = every;
+        self
+    }
+
+    /// Compare duplicate candidates byte by byte before reporting them.
+    pub fn verify(mut self, verify: bool) -> Self {
+        self.verify = verify.then_some(Verification::Full);
+        self
+    }
+
+    /// Instead of a full comparison, check `ranges` random byte ranges of
+    /// every duplicate, at offsets drawn from `seed`.
+    pub fn sample_verify(mut self, ranges: usize, seed: u64) -> Self {
+        self.verify = Some(Verification::Sample { ranges, seed });
+        self
+    }
+
+    /// Only keep groups whose files were found under more than one root.
+    pub fn cross_only(mut self, cross_only: bool) -> Self {
+        self.cross_only = cross_only;
+        self
+    }
+
+    /// Only keep groups with copies on more than one device (mount).
+    pub fn cross_device_only(mut self, cross_device_only: bool) -> Self {
+        self.cross_device_only = cross_device_only;
+        self
+    }
+
+    /// Only keep copies that sit in the same first-level directory under their
+    /// root, splitting groups that span several.
+    pub fn within_subdir(mut self, within_subdir: bool) -> Self {
+        self.within_subdir = within_subdir;
+        self
+    }
+
+    /// Only report copies that also share a file name.
+    pub fn same_name(mut self, same_name: bool) -> Self {
+        self.same_name = same_name;
+        self
+    }
+
+    /// Only report groups with at least this many copies (2 reports every group).
+    pub fn min_duplicates(mut self, copies: usize) -> Self {
+        self.min_duplicates = copies;
+        self
+    }
+
+    /// With `same_name`, compare names ignoring case (Unicode case folding).
+    pub fn case_insensitive_names(mut self, ignore_case: bool) -> Self {
+        self.case_insensitive_names = ignore_case;
+        self
+    }
+
+    /// Report canonical absolute paths instead of paths as walked.
+    pub fn absolute_paths(mut self, absolute: bool) -> Self {
+        self.absolute_paths = absolute;
+        self
+    }
+
+    /// Only report groups that are new or have gained copies since these
+    /// earlier results. Both scans must use the same hash algorithm.
+    pub fn baseline(mut self, baseline: ScanResults) -> Self {
+        self.baseline = Some(baseline);
+        self
+    }
+
+    /// Also full-hash every scanned file, not just duplicate candidates, and
+    /// return the hashes in `Duplicates::checksums`.
+    pub fn checksums(mut self, enabled: bool) -> Self {
+        self.checksums = enabled;
+        self
+    }
+
+    /// Treat hard links to the same file as one file rather than duplicates.
+    pub fn ignore_hardlinks(mut self, ignore: bool) -> Self {
+        self.ignore_hardlinks = ignore;
+        self
+    }
+
+    /// Report all empty files as one duplicate group instead of skipping them.
+    pub fn include_empty(mut self, include: bool) -> Self {
+        self.include_empty = include;
+        self
+    }
+
+    /// Also group images whose perceptual hashes differ by at most
+    /// `threshold` bits. `None` disables the image pass.
+    pub fn similar_images(mut self, threshold: Option<u32>) -> Self {
+        self.similar_images = threshold;
+        self
+    }
+
+    /// Send each duplicate group here as soon as its files are fully hashed,
+    /// before verification and the final statistics.
+    pub fn stream(mut self, sender: Sender<DuplicateGroup>) -> Self {
+        self.stream = Some(sender);
+        self
+    }
+
+    pub fn progress(mut self, reporter: Arc<dyn ProgressReporter>) -> Self {
+        self.progress = reporter;
+        self
+    }
+
+    pub fn roots(&self) -> &[PathBuf] {
+        &self.roots
+    }
+
+    /// Whether to quick-hash files while the walk runs. Not when the walk may
+    /// hand over files a later step drops (links reached twice with
+    /// `follow_links`, links set aside with `report_symlinks`), nor with
+    /// `max_groups`, which should leave most candidates unread.
+    fn hash_during_walk(&self) -> bool {
+        !self.scan.follow_links && !self.scan.report_symlinks && self.hash.max_groups.is_none()
+    }
+
+    /// Run the scan and return serializable results.
+    pub fn run(&self) -> Result<ScanResults, DupFindError> {
+        Ok(build_results(&self.find()?))
+    }
+
+    /// Run the scan and return the raw duplicate groups.
+    pub fn find(&self) -> Result<Duplicates, DupFindError> {
+        if self.threads > MAX_THREADS {
+            return Err(anyhow::anyhow!(
+                "{} threads requested, at most {} are supported",
+                self.threads,
+                MAX_THREADS
+            )
+            .into());
+        }
+        let found = if self.threads > 0 {
+            let pool = rayon::ThreadPoolBuilder::new()
+                .num_threads(self.threads)
+                .build()
+                .with_context(|| {
+                    format!(
+                        "Failed to start {} worker threads, try fewer with --threads or check the process limit (ulimit -u)",
+                        self.threads
+                    )
+                })?;
+            pool.install(|| self.find_in_pool())
+        } else {
+            self.find_in_pool()
+        };
+        Ok(found?)
+    }
+
+    fn find_in_pool(&self) -> Result<Duplicates> {
+        let start_time = Instant::now();
+        // Checked again by compute_hashes, but failing here skips a wasted scan.
+        self.hash.validate()?;
+        if let Some(baseline) = &self.baseline
+            && baseline.algorithm != self.hash.algorithm
+        {
+            bail!(
+                "The baseline was hashed with {}, rerun with --algorithm {}",
+                baseline.algorithm,
+                baseline.algorithm
+            );
+        }
+        for dir in &self.roots {
+            validate_path(dir)?;
+        }
+        if self.cross_only && self.roots.len() < 2 {
+            warn!("--cross-only has no effect with a single path, nothing will be reported");
+        }
+
+        match &self.file_list {
+            Some(list) => info!("Reading candidate files from {}", list.display()),
+            None => {
+                let dirs: Vec<_> = self.roots.iter().map(|p| p.display().to_string()).collect();
+                info!("Starting duplicate file scan in {}", dirs.join(", "));
+            }
+        }
+        let hash = &self.hash;
+        info!(
+            "Configuration: algorithm={}, quick_hash={}B, quick_buf={}KB, mid_hash={}B, full_buf={}MB, threads={}",
+            hash.algorithm,
+            hash.quick_hash_size,
+            hash.quick_buffer_size,
+            hash.mid_hash_size,
+            hash.full_buffer_size,
+            rayon::current_num_threads()
+        );
+
+        let progress = self.progress.as_ref();
+        let mut clock = PhaseClock::start();
+        // Quick hashing starts during the walk where allowed; the scope waits
+        // for it.
+        let early = EarlyQuickHashes::default();
+        let ScannedTree {
+            mut files,
+            broken_links,
+        } = match &self.file_list {
+            Some(list) => ScannedTree {
+                files: read_file_list(list, &self.scan)?,
+                broken_links: Vec::new(),
+            },
+            None if self.hash_during_walk() => rayon::in_place_scope(|scope| {
+                scan_files_with(&self.roots, &self.scan, progress, &|file| {
+                    early.feed(file, &self.hash, scope)
+                })
+            })?,
+            None => scan_files(&self.roots, &self.scan, progress)?,
+        };
+        let (early_quick, early_bytes) = early.into_parts();
+        let links = if self.scan.report_symlinks {
+            set_aside_symlinks(&mut files)
+        } else {
+            Vec::new()
+        };
+
+        clock.lap("scan");
+        if files.is_empty() {
+            info!("No files found to process");
+            return Ok(self.empty(&files, broken_links, start_time, clock));
+        }
+
+        let mut groups = group_by_size(&files, self.include_empty, progress)?;
+        if self.ignore_hardlinks {
+            let collapsed = collapse_hardlinks(&mut groups);
+            if collapsed > 0 {
+                info!("Ignored {} hard links to already listed files", collapsed);
+            }
+        }
+        let num_size_groups = groups.len();
+        clock.lap("group");
+
+        // Checksums still need every file hashed, so carry on with no groups.
+        if groups.is_empty() && !self.checksums {
+            info!("No potential duplicates found");
+            return Ok(self.empty(&files, broken_links, start_time, clock));
+        }
+
+        let cache = match &self.cache {
+            Some(path) => {
+                let mut cache = cache::load_cache(path, self.hash.algorithm)?;
+                if let Some(every) = self.checkpoint {
+                    cache.checkpoint_every(path, every);
+                }
+                info!(
+                    "Loaded {} cached hashes from {}",
+                    cache.len(),
+                    path.display()
+                );
+                Some(cache)
+            }
+            None => None,
+        };
+
+        // Empty files are identical by definition, so they skip hashing.
+        let empty_files = groups.remove(&0);
+
+        let errors = ErrorCollector::default();
+        let counters = HashCounters::default();
+        counters.bytes_read.store(early_bytes, Ordering::Relaxed);
+        let known = KnownHashes {
+            cache: cache.as_ref(),
+            quick: Some(&early_quick),
+        };
+        let hashes = hasher::compute_hashes_with(
+            groups,
+            &self.hash,
+            known,
+            &errors,
+            self.stream.as_ref(),
+            progress,
+            &counters,
+        );
+        let mut checksums = match &hashes {
+            Ok(hashes) if self.checksums => {
+                info!("Hashing all {} files for checksums", files.len());
+                hasher::checksum_files(&files, hashes, &self.hash, cache.as_ref(), &errors)
+            }
+            _ => Vec::new(),
+        };
+        // Saved even if hashing was interrupted, so a rerun can resume.
+        if let (Some(path), Some(cache)) = (&self.cache, &cache) {
+            self.save_cache(path, cache)?;
+        }
+        let mut hashes = hashes?;
+        clock.lap("hash");
+        // The empty files are the smallest group, so the first a cap leaves out.
+        let capped = self.hash.max_groups.is_some_and(|max| hashes.len() >= max);
+        if let Some(files) = empty_files.as_ref().filter(|_| capped) {
+            info!(
+                "Leaving out the {} empty files, --max-groups was reached",
+                files.len()
+            );
+        } else if let Some(files) = empty_files {
+            let hash = hasher::empty_hash(self.hash.algorithm);
+            if let Some(sender) = &self.stream {
+                let _ = sender.send(DuplicateGroup {
+                    hash: hash.clone(),
+                    size: 0,
+                    files: files
+                        .iter()
+                        .map(|p| p.to_string_lossy().into_owned())
+                        .collect(),
+                    metadata: Vec::new(),
+                    devices: Vec::new(),
+                    prefix_only: None,
+                });
+            }
+            hashes.insert(hash, files);
+        }
+
+        let mut hashes = match self.verify {
+            Some(how) => {
+                match how {
+                    Verification::Full => {
+                        info!("Verifying {} duplicate groups byte by byte", hashes.len())
+                    }
+                    Verification::Sample { ranges, seed } => info!(
+                        "Verifying {} duplicate groups at {} sampled ranges (seed {})",
+                        hashes.len(),
+                        ranges,
+                        seed
+                    ),
+                }
+                let verified = hasher::verify_hashes(hashes, how, &errors)?;
+                clock.lap("verify");
+                verified
+            }
+            None => hashes,
+        };
+
+        if self.cross_only {
+            retain_cross_root(&mut hashes, &self.roots);
+        }
+        if self.cross_device_only {
+            retain_cross_device(&mut hashes);
+        }
+        if self.within_subdir {
+            split_by_subdir(&mut hashes, &self.roots);
+        }
+
+        let mut similar = self.find_similar(&files, &hashes);
+        if self.similar_images.is_some() {
+            clock.lap("similar");
+        }
+        if let Some(baseline) = &self.baseline {
+            retain_new_since(&mut hashes, baseline);
+        }
+        if self.same_name {
+            split_by_name(&mut hashes, self.case_insensitive_names);
+        }
+        if self.min_duplicates > 2 {
+            hashes.retain(|_, files| files.len() >= self.min_duplicates);
+        }
+        let mut symlinks = linked_duplicates(&hashes, &links);
+        if self.absolute_paths {
+            // Canonicalizing a link would resolve it, so only its directory is.
+            for (link, target) in symlinks.iter_mut() {
+                if let (Some(dir), Some(name)) = (link.parent(), link.file_name()) {
+                    *link = canonical_or_original(dir).join(name);
+                }
+                *target = canonical_or_original(target);
+            }
+            for files in hashes.values_mut().chain(similar.iter_mut()) {
+                for path in files.iter_mut() {
+                    *path = canonical_or_original(path);
+                }
+            }
+            for (path, _) in checksums.iter_mut() {
+                *path = canonical_or_original(path);
+            }
+        }
+        let total_bytes = files.iter().map(|f| f.size).sum();
+        let mut stats = calculate_statistics(&hashes, files.len(), total_bytes, num_size_groups)?;
+        stats.total_bytes_read = counters.bytes_read.into_inner();
+        stats.quick_hash = QuickHashStats {
+            files: counters.quick_hashed.into_inner(),
+            collisions: counters.quick_collisions.into_inner(),
+            full_reads_avoided: counters.full_reads_avoided.into_inner(),
+        };
+        debug!("Quick hash: {:?}", stats.quick_hash);
+        clock.lap("statistics");
+        let vanished = errors.vanished();
+        if vanished > 0 {
+            info!("{} files removed during scan", vanished);
+        }
+        let errors = errors.into_errors();
+        if !errors.is_empty() {
+            warn!("{} files could not be read", errors.len());
+        }
+        Ok(Duplicates {
+            hashes,
+            similar,
+            symlinks,
+            broken_links,
+            checksums,
+            stats,
+            algorithm: self.hash.algorithm,
+            duration: start_time.elapsed().as_secs_f64(),
+            errors,
+            vanished,
+            timings: clock.timings,
+            prefix_only: self.hash.prefix_only.then_some(self.hash.quick_hash_size),
+        })
+    }
+
+    fn save_cache(&self, path: &std::path::Path, cache: &HashCache) -> Result<()> {
+        cache::save_cache(path, cache)?;
+        info!(
+            "Hash cache: {} hits, {} entries saved to {}",
+            cache.hits(),
+            cache.len(),
+            path.display()
+        );
+        Ok(())
+    }
+
+    /// Run the perceptual image pass, leaving out groups of exact copies.
+    fn find_similar(
+        &self,
+        files: &[ScannedFile],
+        hashes: &HashMap<String, Vec<PathBuf>>,
+    ) -> Vec<Vec<PathBuf>> {
+        let Some(threshold) = self.similar_images else {
+            return Vec::new();
+        };
+        let mut groups = similar::group_similar(files, threshold, self.progress.as_ref());
+        similar::retain_not_identical(&mut groups, hashes);
+        info!("Found {} groups of similar images", groups.len());
+        groups
+    }
+
+    fn empty(
+        &self,
+        files: &[ScannedFile],
+        broken_links: Vec<PathBuf>,
+        start_time: Instant,
+        mut clock: PhaseClock,
+    ) -> Duplicates {
+        let hashes = HashMap::new();
+        let similar = self.find_similar(files, &hashes);
+        if self.similar_images.is_some() {
+            clock.lap("similar");
+        }
+        let total_bytes = files.iter().map(|f| f.size).sum();
+        let stats = ScanStatistics {
+            total_files_scanned: files.len(),
+            total_bytes_scanned: total_bytes,
+            average_file_size: average_size(total_bytes, files.len()),
+            total_size_groups: 0,
+            total_duplicate_groups: 0,
+            total_duplicate_files: 0,
+            total_wasted_space: 0,
+            total_bytes_read: 0,
+            quick_hash: QuickHashStats::default(),
+        };
+        Duplicates {
+            hashes,
+            similar,
+            symlinks: Vec::new(),
+            broken_links,
+            checksums: Vec::new(),
+            stats,
+            algorithm: self.hash.algorithm,
+            duration: start_time.elapsed().as_secs_f64(),
+            errors: Vec::new(),
+            vanished: 0,
+            timings: clock.timings,
+            prefix_only: self.hash.prefix_only.then_some(self.hash.quick_hash_size),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::statistics::SCHEMA_VERSION;
+    use crate::utils::scan_tempdir;
+    use std::fs;
+    use std::path::Path;
+
+    #[test]
+    fn test_finder_reports_duplicates() {
+        let dir = scan_tempdir();
+        fs::write(dir.path().join("a.txt"), "duplicate").unwrap();
+        fs::write(dir.path().join("b.txt"), "duplicate").unwrap();
+        fs::write(dir.path().join("c.txt"), "different").unwrap();
+
+        let results = DupFinder::new(dir.path()).run().unwrap();
+        assert_eq!(results.total_files_scanned, 3);
+        assert_eq!(results.total_bytes_scanned, 27);
+        assert_eq!(results.average_file_size, 9);
+        // Quick hash of all three same-size files, then full hash of the pair.
+        assert_eq!(results.total_bytes_read, 27 + 18);
+        assert_eq!(results.total_duplicate_groups, 1);
+        assert_eq!(results.groups[0].files.len(), 2);
+        assert_eq!(results.total_wasted_space, 9);
+        // Parsers branch on the version before reading anything else.
+        let json = serde_json::to_string(&results).unwrap();
+        assert!(json.starts_with(&format!("{{\"schema_version\":{}", SCHEMA_VERSION)));
+    }
+
+    #[test]
+    fn test_paths_past_windows_max_path() {
+        let dir = scan_tempdir();
+        let mut deep = dir.path().to_path_buf();
+        for i in 0..30 {
+            deep.push(format!("node_modules{i:02}"));
+        }
+        fs::create_dir_all(&deep).unwrap();
+        fs::write(deep.join("a.txt"), "duplicate").unwrap();
+        fs::write(deep.join("b.txt"), "duplicate").unwrap();
+        assert!(deep.as_os_str().len() > 260);
+
+        let results = DupFinder::new(dir.path()).run().unwrap();
+        assert_eq!(results.total_duplicate_groups, 1);
+        assert!(results.errors.is_empty());
+    }
+
+    #[test]
+    fn test_include_empty_groups_zero_byte_files() {
+        let dir = scan_tempdir();
+        fs::write(dir.path().join("a.txt"), "").unwrap();
+        fs::write(dir.path().join("b.txt"), "").unwrap();
+
+        let results = DupFinder::new(dir.path()).run().unwrap();
+        assert_eq!(results.total_duplicate_groups, 0);
+
+        let results = DupFinder::new(dir.path())
+            .include_empty(true)
+            .run()
+            .unwrap();
+        assert_eq!(results.total_duplicate_groups, 1);
+        assert_eq!(results.groups[0].size, 0);
+        assert_eq!(results.groups[0].files.len(), 2);
+
+        // Streamed too, though never hashed.
+        let (sender, receiver) = std::sync::mpsc::channel();
+        DupFinder::new(dir.path())
+            .include_empty(true)
+            .stream(sender)
+            .run()
+            .unwrap();
+        let streamed: Vec<_> = receiver.iter().collect();
+        assert_eq!(streamed.len(), 1);
+        assert_eq!(streamed[0].size, 0);
+
+        // Counted against --max-groups, after the groups that were hashed.
+        fs::write(dir.path().join("c.txt"), "duplicate").unwrap();
+        fs::write(dir.path().join("d.txt"), "duplicate").unwrap();
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let results = DupFinder::new(dir.path())
+            .include_empty(true)
+            .max_groups(Some(1))
+            .stream(sender)
+            .run()
+            .unwrap();
+        assert_eq!(results.total_duplicate_groups, 1);
+        assert_eq!(results.groups[0].size, 9);
+        assert_eq!(receiver.iter().count(), 1);
+    }
+
+    #[test]
+    fn test_absolute_paths_are_canonical() {
+        let dir = scan_tempdir();
+        fs::create_dir(dir.path().join("sub")).unwrap();
+        fs::write(dir.path().join("a.txt"), "duplicate").unwrap();
+        fs::write(dir.path().join("b.txt"), "duplicate").unwrap();
+
+        let results = DupFinder::new(dir.path().join("sub/.."))
+            .absolute_paths(true)
+            .run()
+            .unwrap();
+        let root = fs::canonicalize(dir.path()).unwrap();
+        for file in &results.groups[0].files {
+            assert_eq!(Path::new(file).parent(), Some(root.as_path()));
+        }
+    }
+
+    #[test]
+    fn test_baseline_reports_only_new_duplicates() {
+        let dir = scan_tempdir();
+        fs::write(dir.path().join("a.txt"), "old").unwrap();
+        fs::write(dir.path().join("b.txt"), "old").unwrap();
+        // Round-trip through JSON like a saved `-o` file.
+        let saved = serde_json::to_string(&DupFinder::new(dir.path()).run().unwrap()).unwrap();
+        let baseline = || -> ScanResults { serde_json::from_str(&saved).unwrap() };
+
+        // A rename is not new; a third copy or new content is.
+        fs::rename(dir.path().join("b.txt"), dir.path().join("moved.txt")).unwrap();
+        fs::write(dir.path().join("c.txt"), "new").unwrap();
+        fs::write(dir.path().join("d.txt"), "new").unwrap();
+        let results = DupFinder::new(dir.path())
+            .baseline(baseline())
+            .run()
+            .unwrap();
+        assert_eq!(results.total_duplicate_groups, 1);
+        assert!(results.groups[0].files[0].ends_with("c.txt"));
+
+        fs::write(dir.path().join("e.txt"), "old").unwrap();
+        let results = DupFinder::new(dir.path())
+            .baseline(baseline())
+            .run()
+            .unwrap();
+        assert_eq!(results.total_duplicate_groups, 2);
+    }
+
+    #[test]
+    fn test_split_groups_keep_their_content_hash() {
+        let dir = scan_tempdir();
+        for sub in ["x", "y"] {
+            fs::create_dir(dir.path().join(sub)).unwrap();
+            fs::write(dir.path().join(sub).join("report.txt"), "same").unwrap();
+            fs::write(dir.path().join(sub).join("final.txt"), "same").unwrap();
+        }
+        let finder = || DupFinder::new(dir.path()).same_name(true);
+        let results = finder().run().unwrap();
+        assert_eq!(results.total_duplicate_groups, 2);
+        assert_eq!(results.groups[0].hash, results.groups[1].hash);
+        assert!(!results.groups[0].hash.contains('#'));
+
+        // Both split groups match the baseline by their real hash.
+        let saved = serde_json::to_string(&results).unwrap();
+        let baseline: ScanResults = serde_json::from_str(&saved).unwrap();
+        let results = finder().baseline(baseline).run().unwrap();
+        assert_eq!(results.total_duplicate_groups, 0);
+    }
+
+    #[test]
+    fn test_checksums_cover_every_file() {
+        let dir = scan_tempdir();
+        fs::write(dir.path().join("a.txt"), "duplicate").unwrap();
+        fs::write(dir.path().join("b.txt"), "duplicate").unwrap();
+        fs::write(dir.path().join("unique.txt"), "only one of these").unwrap();
+
+        let found = DupFinder::new(dir.path()).checksums(true).find().unwrap();
+        let algorithm = found.algorithm;
+        assert_eq!(found.checksums.len(), 3);
+        for (path, hash) in &found.checksums {
+            assert_eq!(*hash, hasher::full_hash_file(path, 1, algorithm).unwrap());
+        }
+        assert!(
+            DupFinder::new(dir.path())
+                .find()
+                .unwrap()
+                .checksums
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn test_custom_progress_reporter_sees_every_phase() {
+        use crate::progress::{Phase, ProgressReporter};
+        use std::sync::Mutex;
+
+        #[derive(Default)]
+        struct Recorder(Mutex<Vec<Phase>>);
+        impl ProgressReporter for Recorder {
+            fn on_progress(&self, phase: Phase, _done: u64, _total: Option<u64>) {
+                let mut phases = self.0.lock().unwrap();
+                if phases.last() != Some(&phase) {
+                    phases.push(phase);
+                }
+            }
+        }
+
+        let dir = scan_tempdir();
+        fs::write(dir.path().join("a.txt"), "duplicate").unwrap();
+        fs::write(dir.path().join("b.txt"), "duplicate").unwrap();
+
+        let recorder = Arc::new(Recorder::default());
+        DupFinder::new(dir.path())
+            .progress(recorder.clone())
+            .run()
+            .unwrap();
+        assert_eq!(
+            *recorder.0.lock().unwrap(),
+            vec![Phase::Scan, Phase::Group, Phase::Hash]
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_report_symlinks_counts_link_and_target_once() {
+        let dir = scan_tempdir();
+        let outside = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::write(root.join("a.txt"), "duplicate").unwrap();
+        fs::write(root.join("b.txt"), "duplicate").unwrap();
+        fs::write(outside.path().join("c.txt"), "duplicate").unwrap();
+        std::os::unix::fs::symlink(root.join("a.txt"), root.join("to_a")).unwrap();
+        std::os::unix::fs::symlink(outside.path().join("c.txt"), root.join("to_c")).unwrap();
+
+        let found = DupFinder::new(root).find().unwrap();
+        assert_eq!(found.stats.total_duplicate_files, 2);
+        assert!(found.symlinks.is_empty());
+
+        // The link to a.txt is not a third copy; the link to c.txt stands in
+        // for a file outside the scan.
+        let found = DupFinder::new(root).report_symlinks(true).find().unwrap();
+        assert_eq!(found.stats.total_duplicate_files, 3);
+        let links: Vec<_> = found
+            .symlinks
+            .iter()
+            .map(|(link, _)| link.file_name().unwrap().to_str().unwrap())
+            .collect();
+        assert_eq!(links, vec!["to_a", "to_c"]);
+        assert_eq!(found.symlinks[0].1, root.join("a.txt"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_follow_links_sets_aside_broken_links() {
+        let dir = scan_tempdir();
+        let root = dir.path();
+        fs::write(root.join("a.txt"), "duplicate").unwrap();
+        fs::write(root.join("b.txt"), "duplicate").unwrap();
+        std::os::unix::fs::symlink(root.join("missing"), root.join("dangling")).unwrap();
+
+        let found = DupFinder::new(root).follow_links(true).find().unwrap();
+        assert_eq!(found.broken_links, vec![root.join("dangling")]);
+        assert_eq!(found.hashes.len(), 1);
+        assert!(found.errors.is_empty());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_follow_links_reports_hard_links_when_asked() {
+        let dir = scan_tempdir();
+        let root = dir.path();
+        fs::create_dir(root.join("real")).unwrap();
+        fs::write(root.join("real/a.txt"), "duplicate").unwrap();
+        fs::hard_link(root.join("real/a.txt"), root.join("real/b.txt")).unwrap();
+        std::os::unix::fs::symlink(root.join("real"), root.join("alias")).unwrap();
+
+        let found = DupFinder::new(root)
+            .follow_links(true)
+            .ignore_hardlinks(false)
+            .find()
+            .unwrap();
+        let files: Vec<_> = found.hashes.values().flatten().collect();
+        assert_eq!(files.len(), 2);
+
+        let found = DupFinder::new(root).follow_links(true).find().unwrap();
+        assert!(found.hashes.is_empty());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_symlinked_root_is_walked_without_follow_links() {
+        let dir = scan_tempdir();
+        let outside = scan_tempdir();
+        let data = dir.path().join("data");
+        fs::create_dir(&data).unwrap();
+        fs::write(data.join("a.txt"), "duplicate").unwrap();
+        fs::write(data.join("b.txt"), "duplicate").unwrap();
+        fs::write(outside.path().join("c.txt"), "duplicate").unwrap();
+        std::os::unix::fs::symlink(outside.path(), data.join("elsewhere")).unwrap();
+        let root = dir.path().join("link");
+        std::os::unix::fs::symlink(&data, &root).unwrap();
+
+        let found = DupFinder::new(&root).find().unwrap();
+        let mut files = found.hashes.into_values().next().unwrap();
+        files.sort();
+        assert_eq!(files, vec![root.join("a.txt"), root.join("b.txt")]);
+    }
+
+    #[test]
+    fn test_nested_roots_are_attributed_to_the_outer_root() {
+        let dir = scan_tempdir();
+        let (outer, other) = (dir.path().join("outer"), dir.path().join("other"));
+        let inner = outer.join("inner");
+        fs::create_dir_all(&inner).unwrap();
+        fs::create_dir(&other).unwrap();
+        fs::write(outer.join("a.txt"), "duplicate").unwrap();
+        fs::write(inner.join("b.txt"), "duplicate").unwrap();
+
+        // Both copies sit under `outer`, so neither counts as crossing roots,
+        // whichever order the nested root is given in.
+        for roots in [[&inner, &outer, &other], [&outer, &inner, &other]] {
+            let found = DupFinder::with_roots(roots)
+                .cross_only(true)
+                .find()
+                .unwrap();
+            assert!(found.hashes.is_empty());
+        }
+
+        fs::write(other.join("c.txt"), "duplicate").unwrap();
+        let found = DupFinder::with_roots([&inner, &outer, &other])
+            .cross_only(true)
+            .find()
+            .unwrap();
+        assert_eq!(found.hashes.values().next().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn test_min_duplicates_drops_small_groups() {
+        let dir = scan_tempdir();
+        for name in ["a1", "a2", "a3"] {
+            fs::write(dir.path().join(name), "three copies").unwrap();
+        }
+        for name in ["b1", "b2"] {
+            fs::write(dir.path().join(name), "pair").unwrap();
+        }
+
+        let results = DupFinder::new(dir.path()).min_duplicates(3).run().unwrap();
+        assert_eq!(results.total_duplicate_groups, 1);
+        assert_eq!(results.groups[0].files.len(), 3);
+        assert_eq!(results.total_wasted_space, 24);
+    }
+}
@@ -1,95 +1,1237 @@
-use anyhow::Result;
-use blake3::Hasher;
-use indicatif::ProgressBar;
+use anyhow::{Result, bail};
+use clap::ValueEnum;
+use log::{debug, warn};
+use md5::Md5;
 use rayon::prelude::*;
-use std::collections::HashMap;
-use std::fs::File;
-use std::io::{self, BufReader, Read};
-use std::path::Path;
-use std::sync::Arc;
-use std::sync::atomic::{AtomicU64, Ordering};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+use xxhash_rust::xxh3::Xxh3;
 
-use crate::utils::INTERRUPTED;
+use crate::cache::HashCache;
+use crate::error::DupFindError;
+use crate::mapped::GuardedMap;
+use crate::progress::{Phase, ProgressReporter};
+use crate::scanner::ScannedFile;
+use crate::statistics::{DuplicateGroup, ErrorCollector, split_group_id};
+use crate::utils::{INTERRUPTED, link_count};
 
-pub fn quick_hash_file(path: &Path, sample_size: usize, buffer_size: usize) -> Result<String> {
-    let mut file = BufReader::with_capacity(buffer_size * 1024, File::open(path)?);
-    let mut buffer = vec![0u8; sample_size];
-    let bytes_read = file.read(&mut buffer)?;
+/// Hash function used for both the quick and the full hashing pass.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum, Serialize, Deserialize, JsonSchema,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum HashAlgorithm {
+    #[default]
+    Blake3,
+    Xxh3,
+    Sha256,
+    Md5,
+}
+
+impl fmt::Display for HashAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            HashAlgorithm::Blake3 => "blake3",
+            HashAlgorithm::Xxh3 => "xxh3",
+            HashAlgorithm::Sha256 => "sha256",
+            HashAlgorithm::Md5 => "md5",
+        };
+        f.write_str(name)
+    }
+}
 
-    let mut hasher = Hasher::new();
-    hasher.update(&buffer[..bytes_read]);
-    Ok(hasher.finalize().to_string())
+/// Runtime-selected hasher state, fed through `io::Write` so it works with `io::copy`.
+enum FileHasher {
+    Blake3(Box<blake3::Hasher>),
+    Xxh3(Box<Xxh3>),
+    Sha256(Sha256),
+    Md5(Md5),
 }
 
-pub fn full_hash_file(path: &Path, buffer_size: usize) -> Result<String> {
-    let mut file = BufReader::with_capacity(buffer_size * 1024 * 1024, File::open(path)?);
-    let mut hasher = Hasher::new();
+impl FileHasher {
+    fn new(algorithm: HashAlgorithm) -> Self {
+        match algorithm {
+            HashAlgorithm::Blake3 => FileHasher::Blake3(Box::new(blake3::Hasher::new())),
+            HashAlgorithm::Xxh3 => FileHasher::Xxh3(Box::new(Xxh3::new())),
+            HashAlgorithm::Sha256 => FileHasher::Sha256(Sha256::new()),
+            HashAlgorithm::Md5 => FileHasher::Md5(Md5::new()),
+        }
+    }
+
+    /// Like `update`, but lets BLAKE3 spread a large in-memory buffer across
+    /// the rayon pool. Other algorithms are sequential.
+    fn update_parallel(&mut self, data: &[u8]) {
+        match self {
+            FileHasher::Blake3(h) => {
+                h.update_rayon(data);
+            }
+            _ => self.update(data),
+        }
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        match self {
+            FileHasher::Blake3(h) => {
+                h.update(data);
+            }
+            FileHasher::Xxh3(h) => h.update(data),
+            FileHasher::Sha256(h) => h.update(data),
+            FileHasher::Md5(h) => h.update(data),
+        }
+    }
+
+    /// Lowercase hex digest, matching the output of `b3sum`, `xxhsum -H3`, `sha256sum` and `md5sum`.
+    fn finalize_hex(self) -> String {
+        match self {
+            FileHasher::Blake3(h) => h.finalize().to_hex().to_string(),
+            FileHasher::Xxh3(h) => format!("{:016x}", h.digest()),
+            FileHasher::Sha256(h) => to_hex(&h.finalize()),
+            FileHasher::Md5(h) => to_hex(&h.finalize()),
+        }
+    }
+}
+
+impl Write for FileHasher {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Open a file for hashing, refusing anything but a regular file. Opening a
+/// FIFO blocks until something writes to it and a device may never reach
+/// end of file, so these would hang the scan. The check runs on the open
+/// handle, so a file swapped for a FIFO after the walk is caught too; the
+/// caller records the error.
+fn open_regular(path: &Path) -> Result<File> {
+    let file = open_nonblocking(path)?;
+    if !file.metadata()?.is_file() {
+        bail!("not a regular file");
+    }
+    Ok(file)
+}
+
+/// Open without waiting for a writer on a FIFO. Reads from a regular file
+/// are unaffected by `O_NONBLOCK`.
+#[cfg(any(target_os = "macos", target_os = "linux"))]
+fn open_nonblocking(path: &Path) -> io::Result<File> {
+    use std::os::unix::fs::OpenOptionsExt;
+
+    fs::OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_NONBLOCK | libc::O_NOCTTY)
+        .open(path)
+}
+
+/// Without a non-blocking open, check the path first; `open_regular` still
+/// checks the handle afterwards.
+#[cfg(not(any(target_os = "macos", target_os = "linux")))]
+fn open_nonblocking(path: &Path) -> io::Result<File> {
+    if !fs::metadata(path)?.is_file() {
+        return Err(io::Error::other("not a regular file"));
+    }
+    File::open(path)
+}
+
+pub fn quick_hash_file(
+    path: &Path,
+    sample_size: usize,
+    buffer_size: usize,
+    algorithm: HashAlgorithm,
+) -> Result<String> {
+    let mut file = open_regular(path)?;
+    let sample = (sample_size as u64).min(file.metadata()?.len());
+    let mut hasher = FileHasher::new(algorithm);
+    hash_range(&mut file, sample, buffer_size * 1024, &mut hasher)?;
+    Ok(hasher.finalize_hex())
+}
+
+/// Chunk size for the head, middle and tail samples of the mid hash.
+const MID_CHUNK: usize = 64 * 1024;
+
+/// Largest sample buffer a thread keeps between files. A larger
+/// `--quick-buffer-size` is allocated per file instead, so one big file does
+/// not leave every worker holding that much memory for the rest of the run.
+const KEPT_BUFFER_BYTES: usize = 1024 * 1024;
+
+thread_local! {
+    /// Sample buffer reused by every quick and mid hash on this thread.
+    static SAMPLE_BUFFER: RefCell<Vec<u8>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Feed the next `len` bytes of `file` to `hasher`, fewer at end of file,
+/// `chunk` bytes at a time through this thread's sample buffer. The buffer is
+/// capped at `len`, so a large chunk setting does not allocate more than a
+/// small file holds.
+fn hash_range(file: &mut File, len: u64, chunk: usize, hasher: &mut FileHasher) -> io::Result<()> {
+    let chunk = chunk.min(len.try_into().unwrap_or(usize::MAX)).max(1);
+    let mut range = Read::take(file, len);
+    SAMPLE_BUFFER.with_borrow_mut(|buffer| {
+        if buffer.len() < chunk {
+            buffer.resize(chunk, 0);
+        }
+        let result = loop {
+            match read_chunk(&mut range, &mut buffer[..chunk]) {
+                Ok(0) => break Ok(()),
+                Ok(n) => hasher.update(&buffer[..n]),
+                Err(e) => break Err(e),
+            }
+        };
+        if buffer.len() > KEPT_BUFFER_BYTES {
+            *buffer = Vec::new();
+        }
+        result
+    })
+}
+
+pub fn full_hash_file(path: &Path, buffer_size: usize, algorithm: HashAlgorithm) -> Result<String> {
+    let mut file = BufReader::with_capacity(buffer_size * 1024 * 1024, open_regular(path)?);
+    let mut hasher = FileHasher::new(algorithm);
 
     io::copy(&mut file, &mut hasher)?;
-    Ok(hasher.finalize().to_string())
+    Ok(hasher.finalize_hex())
+}
+
+/// Digest of zero bytes, shared by every empty file.
+pub fn empty_hash(algorithm: HashAlgorithm) -> String {
+    FileHasher::new(algorithm).finalize_hex()
 }
 
+/// Files at least this large are memory-mapped for full hashing with `--mmap`.
+pub const MMAP_MIN_SIZE: u64 = 16 * 1024 * 1024;
+
+/// Full hash over a memory map of the file, or `None` when it cannot be mapped
+/// safely and should be read instead.
+///
+/// A file truncated while mapped is caught rather than crashing the process
+/// (see `mapped`), and size and mtime are compared afterwards; a file that
+/// changed underneath is reported as an error instead of returning a hash of
+/// mixed contents.
+pub fn mmap_hash_file(path: &Path, algorithm: HashAlgorithm) -> Result<Option<String>> {
+    let file = open_regular(path)?;
+    let before = file.metadata()?;
+    if before.len() == 0 {
+        return Ok(Some(empty_hash(algorithm)));
+    }
+
+    let map = match GuardedMap::new(&file) {
+        Ok(Some(map)) => map,
+        Ok(None) => return Ok(None),
+        Err(e) => {
+            debug!("Cannot map {}, reading instead: {}", path.display(), e);
+            return Ok(None);
+        }
+    };
+    let mut hasher = FileHasher::new(algorithm);
+    hasher.update_parallel(map.bytes());
+    let truncated = map.truncated();
+    drop(map);
+    if truncated {
+        bail!("file was truncated while hashing");
+    }
+
+    ensure_unchanged(path, &before)?;
+    Ok(Some(hasher.finalize_hex()))
+}
+
+/// Sampling hash over the head, middle and tail of a file, each `sample_size`
+/// bytes long. Catches large files that share a prefix but differ later on.
+pub fn mid_hash_file(
+    path: &Path,
+    file_size: u64,
+    sample_size: usize,
+    algorithm: HashAlgorithm,
+) -> Result<String> {
+    let mut file = open_regular(path)?;
+    let sample = (sample_size as u64).min(file_size);
+    let mut hasher = FileHasher::new(algorithm);
+
+    let offsets = [
+        0,
+        file_size.saturating_sub(sample) / 2,
+        file_size.saturating_sub(sample),
+    ];
+    for offset in offsets {
+        file.seek(SeekFrom::Start(offset))?;
+        hash_range(&mut file, sample, MID_CHUNK, &mut hasher)?;
+    }
+    Ok(hasher.finalize_hex())
+}
+
+/// Tunables for the hashing pipeline.
+#[derive(Debug, Clone)]
+pub struct HashOptions {
+    pub algorithm: HashAlgorithm,
+    /// Quick hash sample size in bytes
+    pub quick_hash_size: usize,
+    /// Quick hash buffer size in KB
+    pub quick_buffer_size: usize,
+    /// Size in bytes of each head/middle/tail sample of the mid hash; 0 disables the tier
+    pub mid_hash_size: usize,
+    /// Full hash buffer size in MB
+    pub full_buffer_size: usize,
+    /// Memory-map files of at least `MMAP_MIN_SIZE` bytes for the full hash
+    pub mmap: bool,
+    /// Stop hashing once this many duplicate groups are found
+    pub max_groups: Option<usize>,
+    /// Stop after the quick hash, grouping files by size and their first
+    /// `quick_hash_size` bytes only: probable duplicates, never fully read
+    pub prefix_only: bool,
+}
+
+impl Default for HashOptions {
+    fn default() -> Self {
+        Self {
+            algorithm: HashAlgorithm::default(),
+            quick_hash_size: 8192,
+            quick_buffer_size: 64,
+            mid_hash_size: 65536,
+            full_buffer_size: 1,
+            mmap: false,
+            max_groups: None,
+            prefix_only: false,
+        }
+    }
+}
+
+/// Tallies from one `compute_hashes` run, for statistics and tuning.
+#[derive(Debug, Default)]
+pub struct HashCounters {
+    /// Bytes read by every tier, updated while hashing for progress
+    pub bytes_read: AtomicU64,
+    /// Files compared by quick hash
+    pub quick_hashed: AtomicU64,
+    /// Files that matched another by quick hash, then turned out unique
+    pub quick_collisions: AtomicU64,
+    /// Quick-hashed files that never needed a full read
+    pub full_reads_avoided: AtomicU64,
+}
+
+/// Size counters are split over this many locks, so walker threads rarely
+/// wait on each other.
+const SIZE_SHARDS: usize = 64;
+
+/// Most quick hashes kept from the walk. Files past this are left to the
+/// quick tier, so memory stays bounded on any tree.
+pub const EARLY_HASH_LIMIT: usize = 100_000;
+
+/// Quick hashes computed while the walk is still running, so hashing overlaps
+/// it rather than waiting for the last directory to be listed.
+///
+/// Only a count is kept per size, never a path, so a file of a unique size
+/// costs no more than it does in `group_by_size`. The first two files of a
+/// size are left to the tiers: a size that stays a pair is compared directly
+/// in one read, which an early quick hash would only add to. From the third
+/// file on, each is hashed as it arrives.
+pub struct EarlyQuickHashes {
+    sizes: [Mutex<HashMap<u64, u32>>; SIZE_SHARDS],
+    hashes: Mutex<HashMap<PathBuf, String>>,
+    /// Hashes started so far, up to `EARLY_HASH_LIMIT`
+    started: AtomicUsize,
+    bytes_read: AtomicU64,
+}
+
+impl Default for EarlyQuickHashes {
+    fn default() -> Self {
+        Self {
+            sizes: std::array::from_fn(|_| Mutex::default()),
+            hashes: Mutex::default(),
+            started: AtomicUsize::new(0),
+            bytes_read: AtomicU64::new(0),
+        }
+    }
+}
+
+impl EarlyQuickHashes {
+    /// Take a file from the walk, and from the third file of its size on,
+    /// quick-hash it on `scope`. Hard-linked files are skipped, since the
+    /// grouping may yet collapse them into one.
+    pub fn feed<'s>(
+        &'s self,
+        file: &ScannedFile,
+        options: &'s HashOptions,
+        scope: &rayon::Scope<'s>,
+    ) {
+        // Empty files are never hashed.
+        if file.size == 0 {
+            return;
+        }
+        // Sizes are often multiples of a block size, so spread them first.
+        let shard = (file.size.wrapping_mul(0x9e3779b97f4a7c15) >> 58) as usize;
+        let seen = {
+            let mut sizes = self.sizes[shard].lock().unwrap();
+            let count = sizes.entry(file.size).or_default();
+            *count = count.saturating_add(1);
+            *count
+        };
+        if seen < 3
+            || self.started.fetch_add(1, Ordering::Relaxed) >= EARLY_HASH_LIMIT
+            || link_count(&file.path) > 1
+        {
+            return;
+        }
+        let path = file.path.clone();
+        let size = file.size;
+        scope.spawn(move |_| {
+            if INTERRUPTED.load(Ordering::Relaxed) {
+                return;
+            }
+            // Failures are left to the quick tier, which reports them.
+            if let Ok(hash) = quick_hash_file(
+                &path,
+                options.quick_hash_size,
+                options.quick_buffer_size,
+                options.algorithm,
+            ) {
+                let sample = size.min(options.quick_hash_size as u64);
+                self.bytes_read.fetch_add(sample, Ordering::Relaxed);
+                self.hashes.lock().unwrap().insert(path, hash);
+            }
+        });
+    }
+
+    /// The quick hashes by path, and the bytes read for them.
+    pub fn into_parts(self) -> (HashMap<PathBuf, String>, u64) {
+        (
+            self.hashes.into_inner().unwrap(),
+            self.bytes_read.into_inner(),
+        )
+    }
+}
+
+/// Hashes `compute_hashes_with` takes instead of reading the files again.
+#[derive(Clone, Copy, Default)]
+pub struct KnownHashes<'a> {
+    /// Full hashes from earlier runs
+    pub cache: Option<&'a HashCache>,
+    /// Quick hashes from `EarlyQuickHashes`, for the same `HashOptions`
+    pub quick: Option<&'a HashMap<PathBuf, String>>,
+}
+
+/// Upper bound for any sample or buffer (1 GiB), so a typo cannot exhaust memory.
+pub const MAX_BUFFER_BYTES: usize = 1 << 30;
+
+impl HashOptions {
+    /// Reject zero-sized samples and buffers, which would make every file hash
+    /// the same, and sizes above `MAX_BUFFER_BYTES`.
+    pub fn validate(&self) -> Result<()> {
+        let sizes = [
+            ("quick hash size", self.quick_hash_size, 1, true),
+            ("quick buffer size", self.quick_buffer_size, 1024, true),
+            ("mid hash size", self.mid_hash_size, 1, false),
+            ("full buffer size", self.full_buffer_size, 1024 * 1024, true),
+        ];
+        for (name, value, unit, nonzero) in sizes {
+            if nonzero && value == 0 {
+                bail!("The {} must be greater than zero", name);
+            }
+            if value.saturating_mul(unit) > MAX_BUFFER_BYTES {
+                bail!("The {} must be at most 1 GiB", name);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Same-size candidate files that have matched on every hash tier so far.
+type Candidates = Vec<(u64, Vec<PathBuf>)>;
+
+/// Hash the files of every group with `hash_fn` in one flat parallel pass, so
+/// a single huge group cannot leave the other workers idle, then split each
+/// group by the new hash. Only subgroups of two or more files are returned;
+/// unreadable files are recorded in `errors` and dropped.
+fn refine<F>(groups: Candidates, errors: &ErrorCollector, hash_fn: F) -> Candidates
+where
+    F: Fn(u64, &Path) -> Result<String> + Sync,
+{
+    refine_keyed(groups, errors, hash_fn)
+        .into_iter()
+        .map(|(_, size, files)| (size, files))
+        .collect()
+}
+
+/// `refine`, keeping the hash each subgroup was split by.
+fn refine_keyed<F>(
+    groups: Candidates,
+    errors: &ErrorCollector,
+    hash_fn: F,
+) -> Vec<(String, u64, Vec<PathBuf>)>
+where
+    F: Fn(u64, &Path) -> Result<String> + Sync,
+{
+    let jobs: Vec<_> = groups
+        .into_iter()
+        .enumerate()
+        .flat_map(|(idx, (size, files))| files.into_iter().map(move |path| (idx, size, path)))
+        .collect();
+
+    let hashed: Vec<_> = jobs
+        .into_par_iter()
+        .filter_map(|(idx, size, path)| {
+            if INTERRUPTED.load(Ordering::Relaxed) {
+                return None;
+            }
+            match hash_fn(size, &path) {
+                Ok(hash) => Some(((idx, hash), size, path)),
+                Err(e) => {
+                    errors.record(&path, &e);
+                    None
+                }
+            }
+        })
+        .collect();
+
+    let mut split: HashMap<(usize, String), (u64, Vec<PathBuf>)> = HashMap::new();
+    for (key, size, path) in hashed {
+        split.entry(key).or_insert((size, Vec::new())).1.push(path);
+    }
+    split
+        .into_iter()
+        .filter(|(_, (_, files))| files.len() >= 2)
+        .map(|((_, hash), (size, files))| (hash, size, files))
+        .collect()
+}
+
+/// Settle two-file groups by comparing the files directly. Sampling a pair
+/// first saves nothing: the side-by-side read stops at the first difference
+/// just as early, and a match needs the full read anyway. Returns the
+/// matching pairs by hash, and the pairs that could not be read, which go
+/// through the regular tiers so their errors are reported per file.
+fn settle_pairs(
+    pairs: Candidates,
+    options: &HashOptions,
+    bytes_hashed: &AtomicU64,
+    processed: &AtomicU64,
+) -> (Vec<(String, u64, Vec<PathBuf>)>, Candidates) {
+    let results: Vec<_> = pairs
+        .into_par_iter()
+        .filter_map(|(size, files)| {
+            if INTERRUPTED.load(Ordering::Relaxed) {
+                return None;
+            }
+            let result = compare_and_hash(&files[0], &files[1], options);
+            processed.fetch_add(2, Ordering::Relaxed);
+            match result {
+                Ok((hash, read)) => {
+                    bytes_hashed.fetch_add(read, Ordering::Relaxed);
+                    hash.map(|hash| Ok((hash, size, files)))
+                }
+                Err(e) => {
+                    debug!("Comparing {} directly failed: {:#}", files[0].display(), e);
+                    Some(Err((size, files)))
+                }
+            }
+        })
+        .collect();
+
+    let mut matched = Vec::new();
+    let mut unreadable = Vec::new();
+    for result in results {
+        match result {
+            Ok(pair) => matched.push(pair),
+            Err(pair) => unreadable.push(pair),
+        }
+    }
+    (matched, unreadable)
+}
+
+type HashedFiles = Mutex<Vec<(String, PathBuf)>>;
+
+/// Collects full-hash results per candidate group and sends the group's
+/// duplicates as soon as its last file has been hashed, up to `limit` groups.
+struct GroupTracker<'a> {
+    sender: &'a Sender<DuplicateGroup>,
+    /// Size, files still to hash, and hashes so far of each candidate group
+    groups: Vec<(u64, AtomicUsize, HashedFiles)>,
+    sent: AtomicUsize,
+    limit: usize,
+}
+
+impl<'a> GroupTracker<'a> {
+    fn new(
+        sender: &'a Sender<DuplicateGroup>,
+        candidates: &Candidates,
+        limit: Option<usize>,
+    ) -> Self {
+        let groups = candidates
+            .iter()
+            .map(|(size, files)| (*size, AtomicUsize::new(files.len()), Mutex::default()))
+            .collect();
+        Self {
+            sender,
+            groups,
+            sent: AtomicUsize::new(0),
+            limit: limit.unwrap_or(usize::MAX),
+        }
+    }
+
+    /// Record one finished file of group `idx`; `None` if it could not be hashed.
+    fn finish(&self, idx: usize, result: Option<&(String, PathBuf)>) {
+        let (size, remaining, done) = &self.groups[idx];
+        if let Some(entry) = result {
+            done.lock().unwrap().push(entry.clone());
+        }
+        if remaining.fetch_sub(1, Ordering::AcqRel) != 1 {
+            return;
+        }
+
+        let mut by_hash: HashMap<String, Vec<String>> = HashMap::new();
+        for (hash, path) in std::mem::take(&mut *done.lock().unwrap()) {
+            by_hash
+                .entry(hash)
+                .or_default()
+                .push(path.to_string_lossy().into_owned());
+        }
+        for (hash, files) in by_hash {
+            if files.len() > 1 && self.sent.fetch_add(1, Ordering::Relaxed) < self.limit {
+                // The receiver may have gone away; the scan itself still completes.
+                let _ = self.sender.send(DuplicateGroup {
+                    hash,
+                    size: *size,
+                    files,
+                    metadata: Vec::new(),
+                    devices: Vec::new(),
+                    prefix_only: None,
+                });
+            }
+        }
+    }
+}
+
+/// Group same-size files by content. Candidates cascade through a quick hash of
+/// the head, an optional mid hash of head/middle/tail samples, and finally a
+/// full hash; singletons are dropped after every tier. Each tier runs as one
+/// flat parallel pass over all remaining files. If `confirmed` is given, each
+/// duplicate group is also sent there as soon as all its candidates are hashed.
+/// Bytes read by every tier and how well the quick hash filtered are tallied
+/// in `counters`.
 pub fn compute_hashes(
-    groups: HashMap<u64, Vec<std::path::PathBuf>>,
-    quick_hash_size: usize,
-    quick_buffer_size: usize,
-    full_buffer_size: usize,
-    progress: &ProgressBar,
-) -> Result<HashMap<String, Vec<std::path::PathBuf>>> {
+    groups: HashMap<u64, Vec<PathBuf>>,
+    options: &HashOptions,
+    cache: Option<&HashCache>,
+    errors: &ErrorCollector,
+    confirmed: Option<&Sender<DuplicateGroup>>,
+    progress: &dyn ProgressReporter,
+    counters: &HashCounters,
+) -> Result<HashMap<String, Vec<PathBuf>>> {
+    let known = KnownHashes { cache, quick: None };
+    compute_hashes_with(
+        groups, options, known, errors, confirmed, progress, counters,
+    )
+}
+
+/// `compute_hashes`, skipping the reads `known` already answers. Two-file
+/// groups whose quick hashes are known to differ are dropped unread.
+pub fn compute_hashes_with(
+    groups: HashMap<u64, Vec<PathBuf>>,
+    options: &HashOptions,
+    known: KnownHashes,
+    errors: &ErrorCollector,
+    confirmed: Option<&Sender<DuplicateGroup>>,
+    progress: &dyn ProgressReporter,
+    counters: &HashCounters,
+) -> Result<HashMap<String, Vec<PathBuf>>> {
+    options.validate()?;
+    let cache = known.cache;
+    let known_quick = |path: &Path| known.quick.and_then(|quick| quick.get(path));
+    let bytes_hashed = &counters.bytes_read;
     let processed = Arc::new(AtomicU64::new(0));
     let total: u64 = groups.values().map(|files| files.len() as u64).sum();
+    let algorithm = options.algorithm;
+
+    let mut ruled_out = 0;
+    let groups: Candidates = groups
+        .into_iter()
+        .filter(|(_, files)| {
+            let differ = files.len() == 2
+                && matches!(
+                    (known_quick(&files[0]), known_quick(&files[1])),
+                    (Some(a), Some(b)) if a != b
+                );
+            ruled_out += if differ { 2 } else { 0 };
+            files.len() >= 2 && !differ
+        })
+        .collect();
+    processed.fetch_add(ruled_out, Ordering::Relaxed);
+    let quick_hash = |size: u64, path: &Path| {
+        if let Some(hash) = known_quick(path) {
+            return Ok(hash.clone());
+        }
+        let sample = size.min(options.quick_hash_size as u64);
+        bytes_hashed.fetch_add(sample, Ordering::Relaxed);
+        quick_hash_file(
+            path,
+            options.quick_hash_size,
+            options.quick_buffer_size,
+            algorithm,
+        )
+    };
 
-    let hash_results: Vec<_> = groups
+    if options.prefix_only {
+        let (found, truncated) = settle_capped(in_order(groups), options.max_groups, |batch| {
+            let mut found = refine_keyed(batch, errors, quick_hash);
+            found.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.2.cmp(&b.2)));
+            found
+        });
+        progress.on_bytes_hashed(bytes_hashed.load(Ordering::Relaxed));
+        progress.on_progress(Phase::Hash, total, Some(total));
+        if truncated {
+            warn!(
+                "Stopped collecting duplicate groups after {}, results are incomplete",
+                options.max_groups.unwrap_or_default()
+            );
+        }
+        if INTERRUPTED.load(Ordering::Relaxed) {
+            return Err(DupFindError::Interrupted.into());
+        }
+        if let Some(sender) = confirmed {
+            for (hash, size, files) in &found {
+                let mut group = confirmed_group(hash, *size, files);
+                group.prefix_only = Some(options.quick_hash_size);
+                let _ = sender.send(group);
+            }
+        }
+        // Same prefix but different sizes are different groups.
+        return Ok(found
+            .into_iter()
+            .map(|(hash, size, files)| (split_group_id(&hash, size), files))
+            .collect());
+    }
+
+    // With a cache, a pair may need no reading at all, so leave it to the tiers.
+    let (pairs, mut groups): (Candidates, Candidates) = in_order(groups)
+        .into_iter()
+        .partition(|(_, files)| cache.is_none() && files.len() == 2);
+    let mut unreadable = Vec::new();
+    let (matched_pairs, mut truncated) = settle_capped(pairs, options.max_groups, |batch| {
+        let (matched, failed) = settle_pairs(batch, options, bytes_hashed, &processed);
+        unreadable.extend(failed);
+        matched
+    });
+    groups.extend(unreadable);
+    let remaining = options.max_groups.map(|max| max - matched_pairs.len());
+    if remaining == Some(0) {
+        truncated |= !groups.is_empty();
+        groups.clear();
+    }
+    if let Some(sender) = confirmed {
+        for (hash, size, files) in &matched_pairs {
+            let _ = sender.send(confirmed_group(hash, *size, files));
+        }
+    }
+
+    let quick_hashed = file_count(&groups) + ruled_out;
+    let candidates = refine(groups, errors, quick_hash);
+    let quick_passed = file_count(&candidates);
+
+    // The mid tier only pays off when its samples cover a small part of the file
+    let mid = options.mid_hash_size;
+    let (mid_groups, mut candidates): (Candidates, Candidates) =
+        candidates.into_iter().partition(|(size, _)| {
+            mid > 0 && *size > 3 * mid as u64 && *size > options.quick_hash_size as u64
+        });
+    candidates.extend(refine(mid_groups, errors, |size, path| {
+        bytes_hashed.fetch_add(3 * mid as u64, Ordering::Relaxed);
+        mid_hash_file(path, size, mid, algorithm)
+    }));
+    let passed_mid = file_count(&candidates);
+
+    let candidates = in_order(candidates);
+    let tracker = confirmed.map(|sender| GroupTracker::new(sender, &candidates, remaining));
+    let mut fully_read = 0;
+    let mut unique_at_full = 0;
+    let candidates: Vec<_> = candidates.into_iter().enumerate().collect();
+    let (found, cut_short) = settle_capped(candidates, remaining, |batch| {
+        let jobs: Vec<_> = batch
+            .into_iter()
+            .flat_map(|(idx, (size, files))| files.into_iter().map(move |path| (idx, size, path)))
+            .collect();
+        fully_read += jobs.len() as u64;
+
+        let hash_results: Vec<_> = jobs
+            .into_par_iter()
+            .filter_map(|(idx, size, path)| {
+                if INTERRUPTED.load(Ordering::Relaxed) {
+                    return None;
+                }
+
+                let hash_file = || {
+                    bytes_hashed.fetch_add(size, Ordering::Relaxed);
+                    if options.mmap
+                        && size >= MMAP_MIN_SIZE
+                        && let Some(hash) = mmap_hash_file(&path, algorithm)?
+                    {
+                        return Ok(hash);
+                    }
+                    full_hash_file(&path, options.full_buffer_size, algorithm)
+                };
+                let result = match cache {
+                    Some(cache) => cache.get_or_compute(&path, hash_file),
+                    None => hash_file(),
+                };
+
+                let current = processed.fetch_add(1, Ordering::Relaxed);
+                if current.is_multiple_of(100) {
+                    progress.on_bytes_hashed(bytes_hashed.load(Ordering::Relaxed));
+                    progress.on_progress(Phase::Hash, current.min(total), Some(total));
+                }
+
+                let result = match result {
+                    Ok(hash) => Some((hash, path)),
+                    Err(e) => {
+                        errors.record(&path, &e);
+                        None
+                    }
+                };
+                if let Some(tracker) = &tracker {
+                    tracker.finish(idx, result.as_ref());
+                }
+                result.map(|(hash, path)| ((idx, hash), size, path))
+            })
+            .collect();
+
+        // Ordered by candidate group, so a cap keeps the same groups every run.
+        let mut by_hash: BTreeMap<(usize, String), (u64, Vec<PathBuf>)> = BTreeMap::new();
+        for (key, size, path) in hash_results {
+            by_hash
+                .entry(key)
+                .or_insert((size, Vec::new()))
+                .1
+                .push(path);
+        }
+        let (found, unique): (Vec<_>, Vec<_>) = by_hash
+            .into_iter()
+            .map(|((_, hash), (size, files))| (hash, size, files))
+            .partition(|(_, _, files)| files.len() > 1);
+        unique_at_full += unique.len() as u64;
+        found
+    });
+    truncated |= cut_short;
+
+    progress.on_bytes_hashed(bytes_hashed.load(Ordering::Relaxed));
+    progress.on_progress(Phase::Hash, total, Some(total));
+    if truncated {
+        warn!(
+            "Stopped collecting duplicate groups after {}, results are incomplete",
+            options.max_groups.unwrap_or_default()
+        );
+    }
+
+    if INTERRUPTED.load(Ordering::Relaxed) {
+        return Err(DupFindError::Interrupted.into());
+    }
+    let hashes = matched_pairs
+        .into_iter()
+        .chain(found)
+        .map(|(hash, _, files)| (hash, files))
+        .collect();
+
+    counters.quick_hashed.store(quick_hashed, Ordering::Relaxed);
+    counters.quick_collisions.store(
+        quick_passed - passed_mid + unique_at_full,
+        Ordering::Relaxed,
+    );
+    counters
+        .full_reads_avoided
+        .store(quick_hashed - fully_read, Ordering::Relaxed);
+    Ok(hashes)
+}
+
+/// A group found while hashing, as sent to `compute_hashes`' `confirmed`.
+fn confirmed_group(hash: &str, size: u64, files: &[PathBuf]) -> DuplicateGroup {
+    DuplicateGroup {
+        hash: hash.to_string(),
+        size,
+        files: files
+            .iter()
+            .map(|p| p.to_string_lossy().into_owned())
+            .collect(),
+        metadata: Vec::new(),
+        devices: Vec::new(),
+        prefix_only: None,
+    }
+}
+
+/// `groups` largest size first, each with its files in path order, so the
+/// groups a `max_groups` cap keeps do not depend on hash map order.
+fn in_order(mut groups: Candidates) -> Candidates {
+    for (_, files) in &mut groups {
+        files.sort();
+    }
+    groups.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| a.1.cmp(&b.1)));
+    groups
+}
+
+/// Run `settle` over `groups` in order, a batch at a time, until it has found
+/// `max` duplicate groups; the groups after that are never read. Returns the
+/// first `max` groups found, and whether any were dropped or never settled.
+fn settle_capped<G, T>(
+    groups: Vec<G>,
+    max: Option<usize>,
+    mut settle: impl FnMut(Vec<G>) -> Vec<T>,
+) -> (Vec<T>, bool) {
+    let Some(max) = max else {
+        return (settle(groups), false);
+    };
+    let mut found = Vec::new();
+    let mut rest = groups.into_iter();
+    while found.len() < max && !INTERRUPTED.load(Ordering::Relaxed) {
+        // A candidate group usually holds one duplicate group, so settle as
+        // many as are still missing.
+        let batch: Vec<G> = rest.by_ref().take(max - found.len()).collect();
+        if batch.is_empty() {
+            break;
+        }
+        found.extend(settle(batch));
+    }
+    let truncated = found.len() > max || rest.next().is_some();
+    found.truncate(max);
+    (found, truncated)
+}
+
+fn file_count(groups: &Candidates) -> u64 {
+    groups.iter().map(|(_, files)| files.len() as u64).sum()
+}
+
+/// Fill `buf` as far as possible, returning fewer bytes only at end of file.
+fn read_chunk(reader: &mut impl Read, buf: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(filled)
+}
+
+/// Compare two files byte by byte, stopping at the first difference.
+pub fn files_equal(a: &Path, b: &Path) -> Result<bool> {
+    const CHUNK: usize = 64 * 1024;
+
+    let mut reader_a = BufReader::with_capacity(CHUNK, open_regular(a)?);
+    let mut reader_b = BufReader::with_capacity(CHUNK, open_regular(b)?);
+    let mut buf_a = vec![0u8; CHUNK];
+    let mut buf_b = vec![0u8; CHUNK];
+
+    loop {
+        let n_a = read_chunk(&mut reader_a, &mut buf_a)?;
+        let n_b = read_chunk(&mut reader_b, &mut buf_b)?;
+        if n_a != n_b || buf_a[..n_a] != buf_b[..n_b] {
+            return Ok(false);
+        }
+        if n_a == 0 {
+            return Ok(true);
+        }
+    }
+}
+
+/// Stream two files side by side, hashing the first, with the buffer size and
+/// memory maps the full hash would use. Returns its full hash if the contents
+/// match, `None` at the first difference, plus the number of bytes read from
+/// both files. A match is only reported if neither file changed meanwhile.
+fn compare_and_hash(a: &Path, b: &Path, options: &HashOptions) -> Result<(Option<String>, u64)> {
+    let mut file_a = open_regular(a)?;
+    let mut file_b = open_regular(b)?;
+    let (before_a, before_b) = (file_a.metadata()?, file_b.metadata()?);
+    if before_a.len() != before_b.len() {
+        return Ok((None, 0));
+    }
+    let chunk = options.full_buffer_size * 1024 * 1024;
+    let mut hasher = FileHasher::new(options.algorithm);
+
+    let mapped = if options.mmap && before_a.len() >= MMAP_MIN_SIZE {
+        compare_mapped(&file_a, &file_b, chunk, &mut hasher)?
+    } else {
+        None
+    };
+    let (same, read) = match mapped {
+        Some(compared) => compared,
+        None => {
+            let mut buf_a = vec![0u8; chunk];
+            let mut buf_b = vec![0u8; chunk];
+            let mut read = 0;
+            loop {
+                let n_a = read_chunk(&mut file_a, &mut buf_a)?;
+                let n_b = read_chunk(&mut file_b, &mut buf_b)?;
+                read += (n_a + n_b) as u64;
+                if n_a != n_b || buf_a[..n_a] != buf_b[..n_b] {
+                    break (false, read);
+                }
+                if n_a == 0 {
+                    break (true, read);
+                }
+                hasher.update(&buf_a[..n_a]);
+            }
+        }
+    };
+    if !same {
+        return Ok((None, read));
+    }
+
+    ensure_unchanged(a, &before_a)?;
+    ensure_unchanged(b, &before_b)?;
+    Ok((Some(hasher.finalize_hex()), read))
+}
+
+/// `compare_and_hash` over memory maps of both files, `chunk` bytes at a time.
+/// Returns whether they match and the bytes compared, or `None` when they
+/// cannot be mapped and should be read instead.
+fn compare_mapped(
+    a: &File,
+    b: &File,
+    chunk: usize,
+    hasher: &mut FileHasher,
+) -> Result<Option<(bool, u64)>> {
+    let map = |file: &File| GuardedMap::new(file).ok().flatten();
+    let (Some(map_a), Some(map_b)) = (map(a), map(b)) else {
+        return Ok(None);
+    };
+    let mut read = 0;
+    let mut same = map_a.bytes().len() == map_b.bytes().len();
+    for (x, y) in map_a.bytes().chunks(chunk).zip(map_b.bytes().chunks(chunk)) {
+        read += (x.len() + y.len()) as u64;
+        if !same || x != y {
+            same = false;
+            break;
+        }
+        hasher.update_parallel(x);
+    }
+    if map_a.truncated() || map_b.truncated() {
+        bail!("file was truncated while hashing");
+    }
+    Ok(Some((same, read)))
+}
+
+/// Fail if `path` no longer has the size and mtime it had in `before`, so a
+/// file rewritten while it was read is not reported with a hash of mixed
+/// contents.
+fn ensure_unchanged(path: &Path, before: &fs::Metadata) -> Result<()> {
+    let after = fs::metadata(path)?;
+    if after.len() != before.len() || after.modified().ok() != before.modified().ok() {
+        bail!("file changed while hashing");
+    }
+    Ok(())
+}
+
+/// Full hash of every file in `files`, for `--output-sums`. Files already
+/// grouped in `known` keep the hash found there, so only files the tiers ruled
+/// out early are read again. Unreadable files are recorded and left out.
+pub fn checksum_files(
+    files: &[ScannedFile],
+    known: &HashMap<String, Vec<PathBuf>>,
+    options: &HashOptions,
+    cache: Option<&HashCache>,
+    errors: &ErrorCollector,
+) -> Vec<(PathBuf, String)> {
+    let known: HashMap<&Path, &str> = known
+        .iter()
+        .flat_map(|(hash, paths)| paths.iter().map(move |p| (p.as_path(), hash.as_str())))
+        .collect();
+    let mut sums: Vec<(PathBuf, String)> = files
+        .par_iter()
+        .filter_map(|file| {
+            if INTERRUPTED.load(Ordering::Relaxed) {
+                return None;
+            }
+            if let Some(hash) = known.get(file.path.as_path()) {
+                return Some((file.path.clone(), hash.to_string()));
+            }
+            if file.size == 0 {
+                return Some((file.path.clone(), empty_hash(options.algorithm)));
+            }
+            let hash_file = || {
+                if options.mmap
+                    && file.size >= MMAP_MIN_SIZE
+                    && let Some(hash) = mmap_hash_file(&file.path, options.algorithm)?
+                {
+                    return Ok(hash);
+                }
+                full_hash_file(&file.path, options.full_buffer_size, options.algorithm)
+            };
+            let result = match cache {
+                Some(cache) => cache.get_or_compute(&file.path, hash_file),
+                None => hash_file(),
+            };
+            match result {
+                Ok(hash) => Some((file.path.clone(), hash)),
+                Err(e) => {
+                    errors.record(&file.path, &e);
+                    None
+                }
+            }
+        })
+        .collect();
+    sums.sort();
+    sums
+}
+
+/// Bytes in each range compared by `Verification::Sample`.
+const SAMPLE_RANGE: u64 = 4096;
+
+/// How duplicate groups are checked after the full hash.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verification {
+    /// Compare whole files byte by byte.
+    Full,
+    /// Compare `ranges` byte ranges at offsets drawn from `seed`, the same
+    /// offsets for every file of a size, so a run can be reproduced.
+    Sample { ranges: usize, seed: u64 },
+}
+
+impl Verification {
+    fn same(self, a: &Path, b: &Path) -> Result<bool> {
+        match self {
+            Verification::Full => files_equal(a, b),
+            Verification::Sample { ranges, seed } => samples_equal(a, b, ranges, seed),
+        }
+    }
+}
+
+/// SplitMix64, enough to spread sample offsets without a RNG dependency.
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+}
+
+/// Compare `ranges` randomly placed ranges of two files of the same size.
+pub fn samples_equal(a: &Path, b: &Path, ranges: usize, seed: u64) -> Result<bool> {
+    let mut file_a = open_regular(a)?;
+    let mut file_b = open_regular(b)?;
+    let size = file_a.metadata()?.len();
+    if file_b.metadata()?.len() != size {
+        return Ok(false);
+    }
+
+    let len = size.min(SAMPLE_RANGE) as usize;
+    let mut buf_a = vec![0u8; len];
+    let mut buf_b = vec![0u8; len];
+    let mut rng = SplitMix64(seed ^ size);
+    for _ in 0..ranges {
+        let offset = rng.next() % (size - len as u64 + 1);
+        file_a.seek(SeekFrom::Start(offset))?;
+        file_b.seek(SeekFrom::Start(offset))?;
+        file_a.read_exact(&mut buf_a)?;
+        file_b.read_exact(&mut buf_b)?;
+        if buf_a != buf_b {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+/// Split a hash group into subgroups whose members are identical, as far as
+/// `how` can tell. Subgroups with a single file are dropped. A file that
+/// cannot be read is recorded in `errors` and left out, and the rest of the
+/// group is still verified.
+pub fn verify_group(
+    paths: &[PathBuf],
+    how: Verification,
+    errors: &ErrorCollector,
+) -> Vec<Vec<PathBuf>> {
+    let mut remaining: Vec<PathBuf> = paths.to_vec();
+    let mut subgroups = Vec::new();
+
+    'groups: while !remaining.is_empty() {
+        let reference = remaining.remove(0);
+        let mut same = vec![reference.clone()];
+        let mut different = Vec::new();
+        let mut rest = std::mem::take(&mut remaining).into_iter();
+        while let Some(path) = rest.next() {
+            match how.same(&reference, &path) {
+                Ok(true) => same.push(path),
+                Ok(false) => different.push(path),
+                // Comparing the reference with itself tells which of the
+                // two could not be read.
+                Err(e) => match how.same(&reference, &reference) {
+                    Ok(_) => errors.record(&path, &e),
+                    Err(e) => {
+                        errors.record(&reference, &e);
+                        remaining = same.into_iter().skip(1).chain(different).collect();
+                        remaining.push(path);
+                        remaining.extend(rest);
+                        continue 'groups;
+                    }
+                },
+            }
+        }
+        if same.len() > 1 {
+            subgroups.push(same);
+        }
+        remaining = different;
+    }
+
+    subgroups
+}
+
+/// Run [`verify_group`] over every hash group in parallel. Files that cannot
+/// be read are recorded in `errors` and left out rather than reported as
+/// unverified duplicates.
+pub fn verify_hashes(
+    hashes: HashMap<String, Vec<PathBuf>>,
+    how: Verification,
+    errors: &ErrorCollector,
+) -> Result<HashMap<String, Vec<PathBuf>>> {
+    let verified: Vec<(String, Vec<PathBuf>)> = hashes
         .into_par_iter()
-        .filter(|(_, files)| files.len() >= 2)
-        .flat_map(|(_size, files)| {
+        .flat_map(|(hash, files)| {
             if INTERRUPTED.load(Ordering::Relaxed) {
                 return Vec::new();
             }
-            let quick_hashes: Vec<_> = files
-                .par_iter()
-                .filter_map(|path| {
-                    quick_hash_file(path, quick_hash_size, quick_buffer_size)
-                        .map(|hash| (hash, path.clone()))
-                        .ok()
-                })
-                .collect();
-            let mut quick_groups: HashMap<String, Vec<std::path::PathBuf>> = HashMap::new();
-            for (hash, path) in quick_hashes {
-                quick_groups.entry(hash).or_default().push(path);
-            }
-            quick_groups
-                .into_par_iter()
-                .filter(|(_, paths)| paths.len() >= 2)
-                .flat_map(|(_, paths)| {
-                    paths
-                        .par_iter()
-                        .filter_map(|path| {
-                            let result = full_hash_file(path, full_buffer_size)
-                                .map(|hash| (hash, path.clone()));
-
-                            let current = processed.fetch_add(1, Ordering::Relaxed);
-                            if current.is_multiple_of(100) {
-                                progress.set_position(current.min(total));
-                            }
-
-                            result.ok()
-                        })
-                        .collect::<Vec<_>>()
+            let subgroups = verify_group(&files, how, errors);
+            if subgroups.len() > 1 {
+                warn!(
+                    "Hash {} covers files with different content, splitting group",
+                    hash
+                );
+            }
+            subgroups
+                .into_iter()
+                .enumerate()
+                .map(|(i, group)| {
+                    let id = if i == 0 {
+                        hash.clone()
+                    } else {
+                        split_group_id(&hash, i as u64)
+                    };
+                    (id, group)
                 })
                 .collect::<Vec<_>>()
         })
         .collect();
 
-    progress.set_position(total);
-
-    let mut hashes: HashMap<String, Vec<std::path::PathBuf>> = HashMap::new();
-    for (hash, path) in hash_results {
-        hashes.entry(hash).or_default().push(path);
+    if INTERRUPTED.load(Ordering::Relaxed) {
+        return Err(DupFindError::Interrupted.into());
     }
 
-    hashes.retain(|_, files| files.len() > 1);
-    Ok(hashes)
+    Ok(verified.into_iter().collect())
 }
 
 #[cfg(test)]
@@ -104,7 +1246,7 @@ mod tests {
         let file_path = dir.path().join("test.txt");
         fs::write(&file_path, "Hello, World!").unwrap();
 
-        let hash = quick_hash_file(&file_path, 8192, 64).unwrap();
+        let hash = quick_hash_file(&file_path, 8192, 64, HashAlgorithm::Blake3).unwrap();
         assert!(!hash.is_empty());
         assert_eq!(hash.len(), 64);
     }
@@ -115,7 +1257,7 @@ mod tests {
         let file_path = dir.path().join("test.txt");
         fs::write(&file_path, "Hello, World!").unwrap();
 
-        let hash = full_hash_file(&file_path, 1).unwrap();
+        let hash = full_hash_file(&file_path, 1, HashAlgorithm::Blake3).unwrap();
         assert!(!hash.is_empty());
         assert_eq!(hash.len(), 64);
     }
@@ -130,8 +1272,8 @@ mod tests {
         fs::write(&file1, content).unwrap();
         fs::write(&file2, content).unwrap();
 
-        let hash1 = full_hash_file(&file1, 1).unwrap();
-        let hash2 = full_hash_file(&file2, 1).unwrap();
+        let hash1 = full_hash_file(&file1, 1, HashAlgorithm::Blake3).unwrap();
+        let hash2 = full_hash_file(&file2, 1, HashAlgorithm::Blake3).unwrap();
         assert_eq!(hash1, hash2);
     }
 
@@ -144,8 +1286,8 @@ mod tests {
         fs::write(&file1, "Content A").unwrap();
         fs::write(&file2, "Content B").unwrap();
 
-        let hash1 = full_hash_file(&file1, 1).unwrap();
-        let hash2 = full_hash_file(&file2, 1).unwrap();
+        let hash1 = full_hash_file(&file1, 1, HashAlgorithm::Blake3).unwrap();
+        let hash2 = full_hash_file(&file2, 1, HashAlgorithm::Blake3).unwrap();
         assert_ne!(hash1, hash2);
     }
 
@@ -155,8 +1297,623 @@ mod tests {
         let file_path = dir.path().join("small.txt");
         fs::write(&file_path, "Small file").unwrap();
 
-        let quick = quick_hash_file(&file_path, 8192, 64).unwrap();
-        let full = full_hash_file(&file_path, 1).unwrap();
+        let quick = quick_hash_file(&file_path, 8192, 64, HashAlgorithm::Blake3).unwrap();
+        let full = full_hash_file(&file_path, 1, HashAlgorithm::Blake3).unwrap();
+        assert_eq!(quick, full);
+    }
+
+    #[test]
+    fn test_algorithms_match_reference_digests() {
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("abc.txt");
+        fs::write(&file_path, "abc").unwrap();
+
+        let cases = [
+            (
+                HashAlgorithm::Sha256,
+                "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad",
+            ),
+            (HashAlgorithm::Md5, "900150983cd24fb0d6963f7d28e17f72"),
+            (HashAlgorithm::Xxh3, "78af5f94892f3950"),
+        ];
+        for (algorithm, expected) in cases {
+            assert_eq!(full_hash_file(&file_path, 1, algorithm).unwrap(), expected);
+            assert_eq!(
+                quick_hash_file(&file_path, 8192, 64, algorithm).unwrap(),
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn test_verify_group_splits_different_content() {
+        let dir = tempdir().unwrap();
+        let a = dir.path().join("a.bin");
+        let b = dir.path().join("b.bin");
+        let c = dir.path().join("c.bin");
+        let d = dir.path().join("d.bin");
+        fs::write(&a, "xxxx-one").unwrap();
+        fs::write(&b, "xxxx-two").unwrap();
+        fs::write(&c, "xxxx-one").unwrap();
+        fs::write(&d, "xxxx-two").unwrap();
+
+        let groups = verify_group(
+            &[a.clone(), b.clone(), c.clone(), d.clone()],
+            Verification::Full,
+            &ErrorCollector::default(),
+        );
+        assert_eq!(groups, vec![vec![a, c], vec![b, d]]);
+    }
+
+    #[test]
+    fn test_verify_group_leaves_out_unreadable_files() {
+        let dir = tempdir().unwrap();
+        let paths: Vec<_> = ["a", "b", "c", "d"]
+            .iter()
+            .map(|name| {
+                let path = dir.path().join(name);
+                fs::write(&path, "same").unwrap();
+                path
+            })
+            .collect();
+        // A directory stands in for an unreadable file, even as root.
+        let unreadable_path = dir.path().join("unreadable");
+        fs::create_dir(&unreadable_path).unwrap();
+
+        for (unreadable, rest) in [(0, [1, 2, 3]), (2, [0, 1, 3])] {
+            let mut group = paths.clone();
+            group[unreadable] = unreadable_path.clone();
+            let errors = ErrorCollector::default();
+            let groups = verify_group(&group, Verification::Full, &errors);
+            let expected: Vec<_> = rest.iter().map(|&i| paths[i].clone()).collect();
+            assert_eq!(groups, vec![expected]);
+            let errors = errors.into_errors();
+            assert_eq!(errors.len(), 1);
+            assert_eq!(errors[0].path, unreadable_path.display().to_string());
+        }
+    }
+
+    #[test]
+    fn test_files_equal_detects_length_difference() {
+        let dir = tempdir().unwrap();
+        let a = dir.path().join("a.bin");
+        let b = dir.path().join("b.bin");
+        fs::write(&a, "prefix").unwrap();
+        fs::write(&b, "prefix-longer").unwrap();
+
+        assert!(!files_equal(&a, &b).unwrap());
+        assert!(files_equal(&a, &a).unwrap());
+    }
+
+    #[test]
+    fn test_mid_hash_detects_difference_past_prefix() {
+        let dir = tempdir().unwrap();
+        let a = dir.path().join("a.bin");
+        let b = dir.path().join("b.bin");
+        let mut content = vec![0u8; 4096];
+        fs::write(&a, &content).unwrap();
+        content[2048] = 1;
+        fs::write(&b, &content).unwrap();
+
+        let algo = HashAlgorithm::Blake3;
+        assert_eq!(
+            quick_hash_file(&a, 1024, 64, algo).unwrap(),
+            quick_hash_file(&b, 1024, 64, algo).unwrap()
+        );
+        assert_ne!(
+            mid_hash_file(&a, 4096, 512, algo).unwrap(),
+            mid_hash_file(&b, 4096, 512, algo).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_compute_hashes_cascades_tiers() {
+        let dir = tempdir().unwrap();
+        let mut content = vec![7u8; 8192];
+        let same_a = dir.path().join("same_a.bin");
+        let same_b = dir.path().join("same_b.bin");
+        let differs = dir.path().join("differs.bin");
+        fs::write(&same_a, &content).unwrap();
+        fs::write(&same_b, &content).unwrap();
+        content[4000] = 0;
+        fs::write(&differs, &content).unwrap();
+
+        let groups = HashMap::from([(8192, vec![same_a.clone(), same_b.clone(), differs])]);
+        let options = HashOptions {
+            quick_hash_size: 1024,
+            mid_hash_size: 256,
+            ..Default::default()
+        };
+        let errors = ErrorCollector::default();
+        let counters = HashCounters::default();
+        let hashes = compute_hashes(
+            groups,
+            &options,
+            None,
+            &errors,
+            None,
+            &crate::NoProgress,
+            &counters,
+        )
+        .unwrap();
+
+        assert_eq!(hashes.len(), 1);
+        let mut files = hashes.into_values().next().unwrap();
+        files.sort();
+        assert_eq!(files, vec![same_a, same_b]);
+        // `differs` shares the quick hash but the mid tier spares its full read.
+        assert_eq!(counters.quick_hashed.into_inner(), 3);
+        assert_eq!(counters.quick_collisions.into_inner(), 1);
+        assert_eq!(counters.full_reads_avoided.into_inner(), 1);
+    }
+
+    #[test]
+    fn test_compute_hashes_records_unreadable_files() {
+        let dir = tempdir().unwrap();
+        let a = dir.path().join("a.bin");
+        let b = dir.path().join("b.bin");
+        fs::write(&a, "same").unwrap();
+        fs::write(&b, "same").unwrap();
+        let missing = dir.path().join("missing.bin");
+        // Reading a directory fails with a real error, unlike a deleted file.
+        let unreadable = dir.path().join("dir.bin");
+        fs::create_dir(&unreadable).unwrap();
+
+        let groups = HashMap::from([(4, vec![a, b, missing, unreadable.clone()])]);
+        let errors = ErrorCollector::default();
+        let hashes = compute_hashes(
+            groups,
+            &HashOptions::default(),
+            None,
+            &errors,
+            None,
+            &crate::NoProgress,
+            &HashCounters::default(),
+        )
+        .unwrap();
+
+        assert_eq!(hashes.len(), 1);
+        assert_eq!(errors.vanished(), 1);
+        let errors = errors.into_errors();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].path, unreadable.display().to_string());
+    }
+
+    #[test]
+    fn test_mmap_hash_matches_buffered() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("big.bin");
+        let data: Vec<u8> = (0..3_000_000u32).map(|i| (i % 251) as u8).collect();
+        fs::write(&path, &data).unwrap();
+
+        for algorithm in [HashAlgorithm::Blake3, HashAlgorithm::Xxh3] {
+            assert_eq!(
+                mmap_hash_file(&path, algorithm).unwrap(),
+                Some(full_hash_file(&path, 1, algorithm).unwrap())
+            );
+        }
+    }
+
+    #[test]
+    fn test_compute_hashes_streams_confirmed_groups() {
+        let dir = tempdir().unwrap();
+        let paths: Vec<_> = ["a", "b", "c"]
+            .iter()
+            .map(|name| {
+                let p = dir.path().join(name);
+                fs::write(&p, if *name == "c" { "diff" } else { "same" }).unwrap();
+                p
+            })
+            .collect();
+
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let groups = HashMap::from([(4, paths)]);
+        let errors = ErrorCollector::default();
+        let options = HashOptions::default();
+        let hashes = compute_hashes(
+            groups,
+            &options,
+            None,
+            &errors,
+            Some(&sender),
+            &crate::NoProgress,
+            &HashCounters::default(),
+        )
+        .unwrap();
+        drop(sender);
+
+        let streamed: Vec<_> = receiver.iter().collect();
+        assert_eq!(streamed.len(), 1);
+        assert_eq!(streamed[0].files.len(), 2);
+        assert!(hashes.contains_key(&streamed[0].hash));
+    }
+
+    #[test]
+    fn test_zero_or_huge_buffers_rejected() {
+        assert!(HashOptions::default().validate().is_ok());
+        let zero = HashOptions {
+            quick_hash_size: 0,
+            ..Default::default()
+        };
+        assert!(zero.validate().is_err());
+        let huge = HashOptions {
+            full_buffer_size: 4096,
+            ..Default::default()
+        };
+        assert!(huge.validate().is_err());
+    }
+
+    #[test]
+    fn test_sample_buffer_capped_at_file_size() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tiny.txt");
+        fs::write(&path, "tiny").unwrap();
+
+        let big = 512 * 1024 * 1024;
+        let quick = quick_hash_file(&path, big, 64, HashAlgorithm::Blake3).unwrap();
+        let mid = mid_hash_file(&path, 4, big, HashAlgorithm::Blake3).unwrap();
+        assert_eq!(
+            quick,
+            quick_hash_file(&path, 4, 64, HashAlgorithm::Blake3).unwrap()
+        );
+        assert!(!mid.is_empty());
+        assert_eq!(SAMPLE_BUFFER.with_borrow(|buffer| buffer.len()), 4);
+    }
+
+    #[test]
+    fn test_sample_buffer_bounded_for_large_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("large.bin");
+        let content: Vec<u8> = (0..3 * 1024 * 1024).map(|i| (i % 251) as u8).collect();
+        fs::write(&path, &content).unwrap();
+        let size = content.len() as u64;
+        let full = full_hash_file(&path, 1, HashAlgorithm::Blake3).unwrap();
+
+        // A sample far beyond the file streams it through the 64 KB buffer.
+        let big = 512 * 1024 * 1024;
+        let quick = quick_hash_file(&path, big, 64, HashAlgorithm::Blake3).unwrap();
+        assert_eq!(quick, full);
+        assert_eq!(SAMPLE_BUFFER.with_borrow(|buffer| buffer.len()), 64 * 1024);
+        mid_hash_file(&path, size, big, HashAlgorithm::Blake3).unwrap();
+        assert_eq!(SAMPLE_BUFFER.with_borrow(|buffer| buffer.len()), 64 * 1024);
+
+        // A buffer setting above what a thread keeps is dropped after use.
+        let quick = quick_hash_file(&path, big, 2048, HashAlgorithm::Blake3).unwrap();
         assert_eq!(quick, full);
+        assert_eq!(SAMPLE_BUFFER.with_borrow(|buffer| buffer.capacity()), 0);
+    }
+
+    #[test]
+    fn test_pairs_compared_directly() {
+        let dir = tempfile::tempdir().unwrap();
+        let write = |name: &str, content: &str| {
+            let path = dir.path().join(name);
+            fs::write(&path, content).unwrap();
+            path
+        };
+        let same = vec![write("a", "same content"), write("b", "same content")];
+        let differ = vec![write("c", "content one"), write("d", "content two")];
+
+        let groups = HashMap::from([(12, same.clone()), (11, differ)]);
+        let errors = ErrorCollector::default();
+        let hashes = compute_hashes(
+            groups,
+            &HashOptions::default(),
+            None,
+            &errors,
+            None,
+            &crate::NoProgress,
+            &HashCounters::default(),
+        )
+        .unwrap();
+
+        let full = full_hash_file(&same[0], 1, HashAlgorithm::Blake3).unwrap();
+        assert_eq!(hashes.len(), 1);
+        assert_eq!(hashes[&full].len(), 2);
+    }
+
+    #[test]
+    fn test_pair_comparison_follows_full_hash_options() {
+        let dir = tempfile::tempdir().unwrap();
+        let size = MMAP_MIN_SIZE as usize + 3;
+        let content: Vec<u8> = (0..size).map(|i| (i % 253) as u8).collect();
+        let (a, b, c) = (
+            dir.path().join("a"),
+            dir.path().join("b"),
+            dir.path().join("c"),
+        );
+        fs::write(&a, &content).unwrap();
+        fs::write(&b, &content).unwrap();
+        let mut changed = content.clone();
+        changed[0] ^= 1;
+        fs::write(&c, &changed).unwrap();
+        let full = full_hash_file(&a, 1, HashAlgorithm::Blake3).unwrap();
+
+        for mmap in [false, true] {
+            let options = HashOptions {
+                mmap,
+                full_buffer_size: 4,
+                ..Default::default()
+            };
+            let (hash, read) = compare_and_hash(&a, &b, &options).unwrap();
+            assert_eq!(hash.as_deref(), Some(full.as_str()));
+            assert_eq!(read, 2 * size as u64);
+            // Reading stops after the first 4 MB chunk of each.
+            let (hash, read) = compare_and_hash(&a, &c, &options).unwrap();
+            assert_eq!(hash, None);
+            assert_eq!(read, 2 * 4 * 1024 * 1024);
+        }
+    }
+
+    #[test]
+    fn test_max_groups_truncates_collection() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut groups = HashMap::new();
+        for size in 1..=3u64 {
+            let files: Vec<_> = ["a", "b", "c"]
+                .iter()
+                .map(|name| {
+                    let path = dir.path().join(format!("{}{}", name, size));
+                    fs::write(&path, "x".repeat(size as usize)).unwrap();
+                    path
+                })
+                .collect();
+            groups.insert(size, files);
+        }
+        let options = HashOptions {
+            max_groups: Some(2),
+            ..Default::default()
+        };
+        let errors = ErrorCollector::default();
+        let hashes = compute_hashes(
+            groups,
+            &options,
+            None,
+            &errors,
+            None,
+            &crate::NoProgress,
+            &HashCounters::default(),
+        )
+        .unwrap();
+
+        assert_eq!(hashes.len(), 2);
+        assert!(hashes.values().all(|files| files.len() == 3));
+    }
+
+    #[test]
+    fn test_early_quick_hashes_cover_repeated_sizes_only() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = |name: &str, content: &str| {
+            let path = dir.path().join(name);
+            fs::write(&path, content).unwrap();
+            ScannedFile {
+                path,
+                size: content.len() as u64,
+                symlink: false,
+            }
+        };
+        let walked = [
+            file("a", "same5"),
+            file("unique", "seven!!"),
+            file("b", "same5"),
+            file("pair1", "pair66"),
+            file("c", "same5"),
+            file("pair2", "pair66"),
+            file("late", "diff5"),
+            file("empty1", ""),
+            file("empty2", ""),
+        ];
+        let link = dir.path().join("a-link");
+        fs::hard_link(&walked[0].path, &link).unwrap();
+        let link = ScannedFile {
+            path: link,
+            size: 5,
+            symlink: false,
+        };
+        let options = HashOptions::default();
+
+        let early = EarlyQuickHashes::default();
+        rayon::in_place_scope(|scope| {
+            for file in walked.iter().chain([&link]) {
+                early.feed(file, &options, scope);
+            }
+        });
+        // Only the third file of a size on is hashed, and never a hard link.
+        let (quick, bytes) = early.into_parts();
+        let mut hashed: Vec<_> = quick.keys().map(|p| p.file_name().unwrap()).collect();
+        hashed.sort();
+        assert_eq!(hashed, ["c", "late"]);
+        assert_eq!(bytes, 10);
+        let same5 = quick_hash_file(&walked[0].path, 8192, 64, HashAlgorithm::Blake3).unwrap();
+        assert_eq!(quick[&walked[4].path], same5);
+    }
+
+    #[test]
+    fn test_known_quick_hashes_settle_pairs_unread() {
+        let dir = tempfile::tempdir().unwrap();
+        let a = dir.path().join("a");
+        let b = dir.path().join("b");
+        fs::write(&a, "one").unwrap();
+        fs::write(&b, "two").unwrap();
+        let quick = HashMap::from([(a.clone(), "x".to_string()), (b.clone(), "y".to_string())]);
+        let counters = HashCounters::default();
+        let hashes = compute_hashes_with(
+            HashMap::from([(3, vec![a, b])]),
+            &HashOptions::default(),
+            KnownHashes {
+                cache: None,
+                quick: Some(&quick),
+            },
+            &ErrorCollector::default(),
+            None,
+            &crate::NoProgress,
+            &counters,
+        )
+        .unwrap();
+
+        assert!(hashes.is_empty());
+        assert_eq!(counters.bytes_read.into_inner(), 0);
+        assert_eq!(counters.full_reads_avoided.into_inner(), 2);
+    }
+
+    #[test]
+    fn test_max_groups_stops_hashing_at_the_cap() {
+        let dir = tempfile::tempdir().unwrap();
+        let candidates = |copies: usize| {
+            let mut groups = HashMap::new();
+            for size in [100, 200, 300u64] {
+                let files: Vec<_> = (0..copies)
+                    .map(|i| {
+                        let path = dir.path().join(format!("{}-{}-{}", copies, size, i));
+                        fs::write(&path, vec![size as u8; size as usize]).unwrap();
+                        path
+                    })
+                    .collect();
+                groups.insert(size, files);
+            }
+            groups
+        };
+        let options = HashOptions {
+            max_groups: Some(1),
+            ..Default::default()
+        };
+        let run = |groups| {
+            let counters = HashCounters::default();
+            let errors = ErrorCollector::default();
+            let hashes = compute_hashes(
+                groups,
+                &options,
+                None,
+                &errors,
+                None,
+                &crate::NoProgress,
+                &counters,
+            )
+            .unwrap();
+            (hashes, counters)
+        };
+
+        // Pairs are compared directly; only the largest pair is read.
+        let (hashes, counters) = run(candidates(2));
+        assert_eq!(hashes.len(), 1);
+        assert!(hashes.values().next().unwrap()[0].ends_with("2-300-0"));
+        assert_eq!(counters.bytes_read.into_inner(), 600);
+
+        // Larger groups are quick-hashed, but only the kept one is read in full.
+        let (hashes, counters) = run(candidates(3));
+        assert_eq!(hashes.len(), 1);
+        assert!(hashes.values().next().unwrap()[0].ends_with("3-300-0"));
+        assert_eq!(counters.quick_hashed.into_inner(), 9);
+        assert_eq!(counters.full_reads_avoided.into_inner(), 6);
+    }
+
+    #[test]
+    fn test_prefix_only_groups_by_prefix() {
+        let dir = tempfile::tempdir().unwrap();
+        let write = |name: &str, content: &str| {
+            let path = dir.path().join(name);
+            fs::write(&path, content).unwrap();
+            path
+        };
+        let probable = vec![write("a", "header-one"), write("b", "header-two")];
+        let other = write("c", "other-text");
+
+        let groups = HashMap::from([(10, vec![probable[0].clone(), probable[1].clone(), other])]);
+        let options = HashOptions {
+            quick_hash_size: 7,
+            prefix_only: true,
+            ..Default::default()
+        };
+        let errors = ErrorCollector::default();
+        let counters = HashCounters::default();
+        let hashes = compute_hashes(
+            groups,
+            &options,
+            None,
+            &errors,
+            None,
+            &crate::NoProgress,
+            &counters,
+        )
+        .unwrap();
+
+        assert_eq!(hashes.len(), 1);
+        let mut files = hashes.into_values().next().unwrap();
+        files.sort();
+        assert_eq!(files, probable);
+        assert_eq!(counters.bytes_read.into_inner(), 21);
+    }
+
+    #[test]
+    fn test_prefix_only_stops_at_max_groups_and_streams_marked_groups() {
+        let dir = tempfile::tempdir().unwrap();
+        let write = |name: &str, content: &str| {
+            let path = dir.path().join(name);
+            fs::write(&path, content).unwrap();
+            path
+        };
+        let larger = vec![write("a", "header-one!!"), write("b", "header-two!!")];
+        let smaller = vec![write("c", "header-one"), write("d", "header-two")];
+        let groups = HashMap::from([(12, larger.clone()), (10, smaller)]);
+        let options = HashOptions {
+            quick_hash_size: 7,
+            prefix_only: true,
+            max_groups: Some(1),
+            ..Default::default()
+        };
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let errors = ErrorCollector::default();
+        let counters = HashCounters::default();
+        let hashes = compute_hashes(
+            groups,
+            &options,
+            None,
+            &errors,
+            Some(&sender),
+            &crate::NoProgress,
+            &counters,
+        )
+        .unwrap();
+        drop(sender);
+
+        assert_eq!(hashes.into_values().collect::<Vec<_>>(), [larger]);
+        assert_eq!(counters.bytes_read.into_inner(), 14);
+        let streamed: Vec<_> = receiver.iter().collect();
+        assert_eq!(streamed.len(), 1);
+        assert_eq!(streamed[0].prefix_only, Some(7));
+    }
+
+    #[test]
+    fn test_samples_equal_checks_sampled_ranges() {
+        let dir = tempdir().unwrap();
+        let a = dir.path().join("a.bin");
+        let b = dir.path().join("b.bin");
+        let c = dir.path().join("c.bin");
+        fs::write(&a, vec![1u8; 100_000]).unwrap();
+        fs::write(&b, vec![1u8; 100_000]).unwrap();
+        fs::write(&c, vec![2u8; 100_000]).unwrap();
+
+        assert!(samples_equal(&a, &b, 8, 42).unwrap());
+        assert!(!samples_equal(&a, &c, 1, 42).unwrap());
+        // Files smaller than one range are compared whole.
+        fs::write(&a, "tiny").unwrap();
+        fs::write(&b, "tinY").unwrap();
+        assert!(!samples_equal(&a, &b, 1, 7).unwrap());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_fifo_is_rejected_without_blocking() {
+        let dir = tempfile::tempdir().unwrap();
+        let fifo = dir.path().join("pipe");
+        let status = std::process::Command::new("mkfifo")
+            .arg(&fifo)
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        let err = full_hash_file(&fifo, 1, HashAlgorithm::Xxh3).unwrap_err();
+        assert_eq!(err.to_string(), "not a regular file");
+        assert!(quick_hash_file(&fifo, 4096, 64, HashAlgorithm::Xxh3).is_err());
     }
 }
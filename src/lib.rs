@@ -0,0 +1,24 @@
+pub mod actions;
+pub mod cache;
+pub mod error;
+pub mod finder;
+pub mod hasher;
+pub mod mapped;
+pub mod output;
+pub mod progress;
+pub mod scanner;
+pub mod similar;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+pub mod statistics;
+pub mod utils;
+
+pub use error::DupFindError;
+pub use finder::DupFinder;
+pub use hasher::{HashAlgorithm, HashCounters, HashOptions, compute_hashes};
+pub use progress::{NoProgress, Phase, ProgressReporter};
+pub use scanner::{
+    ScanOptions, ScannedTree, collapse_hardlinks, group_by_size, read_file_list, scan_files,
+    scan_files_with,
+};
+pub use statistics::{Duplicates, ScanError, ScanResults, ScanStatistics, calculate_statistics};
@@ -1,132 +1,506 @@
 mod args;
-mod hasher;
-mod output;
-mod scanner;
-mod statistics;
-mod utils;
+mod cli_progress;
+mod config;
+mod tui;
+mod watch;
 
 use anyhow::Context;
-use args::Args;
-use clap::Parser;
-use indicatif::{ProgressBar, ProgressStyle};
-use log::info;
-use output::{print_results, save_results_json};
-use scanner::{group_by_size, scan_files};
-use statistics::calculate_statistics;
+use args::{Args, ColorChoice, LogFormat};
+use cli_progress::CliProgress;
+use colored::Colorize;
+use dupfind::actions::{self, Action, ActionSummary, AuditLog};
+use dupfind::output::{
+    Hyperlinks, NdjsonWriter, PrintOptions, attach_devices, attach_metadata, build_results,
+    format_bytes, format_streamed_group, format_summary_line, format_timings, is_zstd_path,
+    load_results_json, prefix_only_notice, print_broken_links, print_errors,
+    print_extension_breakdown, print_results, print_results_json, print_similar,
+    print_size_histogram, print_symlinks, results_schema, save_checksums, save_results_csv,
+    save_results_html, save_results_json, save_results_json_zst, set_hyperlinks, set_units,
+};
+use dupfind::scanner::{HiddenPredicate, is_immutable};
+#[cfg(feature = "sqlite")]
+use dupfind::sqlite::save_results_sqlite;
+use dupfind::statistics::{DuplicateGroup, ScanResults, extension_breakdown, size_histogram};
+use dupfind::utils::{INTERRUPTED, canonical_or_original, dedupe_roots};
+use dupfind::{DupFindError, DupFinder};
+use env_logger::WriteStyle;
+use indicatif::HumanDuration;
+use log::{info, warn};
+use std::fs;
+use std::io::{self, IsTerminal, Write};
 use std::path::Path;
+use std::process::ExitCode;
+use std::sync::Arc;
 use std::sync::atomic::Ordering;
-use std::time::Instant;
-use utils::{INTERRUPTED, validate_path};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
 
-fn main() -> anyhow::Result<()> {
-    let args = Args::parse();
+/// How often `--resume` saves hashing progress.
+const CHECKPOINT_INTERVAL: Duration = Duration::from_secs(30);
 
+/// Exit status: 0 when no duplicates were found, 1 when some were, 2 on error.
+fn main() -> ExitCode {
+    let args = match Args::parse_with_config() {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("Error: {:#}", e);
+            return ExitCode::from(2);
+        }
+    };
+    let exit_zero = args.exit_zero;
+    if args.print_schema {
+        return match serde_json::to_string_pretty(&results_schema()) {
+            Ok(schema) => {
+                println!("{}", schema);
+                ExitCode::SUCCESS
+            }
+            Err(e) => {
+                eprintln!("Error: {:#}", e);
+                ExitCode::from(2)
+            }
+        };
+    }
+
+    match run(args) {
+        Ok(true) if !exit_zero => ExitCode::from(1),
+        Ok(_) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {:#}", e);
+            ExitCode::from(2)
+        }
+    }
+}
+
+/// Run the scan and any requested action, returning whether duplicates were found.
+fn run(args: Args) -> anyhow::Result<bool> {
     ctrlc::set_handler(|| {
         INTERRUPTED.store(true, Ordering::Relaxed);
         eprintln!("\nInterrupted by user, cleaning up...");
     })
     .context("Failed to set signal handler")?;
 
-    env_logger::builder()
+    set_units(args.units);
+    // --no-color promises plain text, so it overrides --hyperlinks too.
+    set_hyperlinks(if args.no_color {
+        Hyperlinks::Never
+    } else {
+        args.hyperlinks
+    });
+    let color = if args.no_color {
+        ColorChoice::Never
+    } else {
+        args.color
+    };
+    let write_style = match color {
+        ColorChoice::Always => {
+            colored::control::set_override(true);
+            console::set_colors_enabled(true);
+            console::set_colors_enabled_stderr(true);
+            WriteStyle::Always
+        }
+        ColorChoice::Never => {
+            colored::control::set_override(false);
+            console::set_colors_enabled(false);
+            console::set_colors_enabled_stderr(false);
+            WriteStyle::Never
+        }
+        ColorChoice::Auto => {
+            if !io::stdout().is_terminal() {
+                colored::control::set_override(false);
+            }
+            WriteStyle::Auto
+        }
+    };
+    let mut logger = env_logger::builder();
+    logger
         .filter_level(args.log_level)
         .format_timestamp_secs()
-        .init();
+        .write_style(write_style);
+    if args.log_format == LogFormat::Json {
+        logger.format(|buf, record| {
+            let line = serde_json::json!({
+                "timestamp": humantime::format_rfc3339_millis(SystemTime::now()).to_string(),
+                "level": record.level().as_str(),
+                "target": record.target(),
+                "message": record.args().to_string(),
+            });
+            writeln!(buf, "{}", line)
+        });
+    }
+    logger.init();
 
-    if args.threads > 0 {
-        rayon::ThreadPoolBuilder::new()
-            .num_threads(args.threads)
-            .build_global()
-            .context("Failed to configure thread pool")?;
+    // Bars on a redirected stderr only clutter the log.
+    let hide_bars = args.quiet || !io::stderr().is_terminal();
+    let progress = Arc::new(CliProgress::new(
+        hide_bars,
+        Duration::from_millis(args.progress_interval),
+    ));
+    let (roots, overlapping) = dedupe_roots(&args.paths);
+    for root in &overlapping {
+        warn!(
+            "Skipping {}: it is already covered by another path",
+            root.display()
+        );
+    }
+    let mut finder = DupFinder::with_roots(&roots)
+        .follow_links(args.follow_links)
+        .one_filesystem(args.one_filesystem)
+        .report_symlinks(args.report_symlinks)
+        .min_size(args.min_size)
+        .max_size(args.max_size)
+        .ignore(&args.ignore)
+        .max_depth(args.max_depth)
+        .min_depth(args.min_depth)
+        .modified_after(args.modified_after)
+        .modified_before(args.modified_before)
+        .include_dotfiles(args.hidden || args.hidden_dotfiles)
+        .include_system_hidden(args.hidden || args.hidden_system)
+        .no_gitignore(args.no_gitignore)
+        .include(&args.include)
+        .exclude(&args.exclude)
+        .skip_extensions(&args.skip_ext)
+        .algorithm(args.algorithm)
+        .quick_hash_size(args.quick_hash_size)
+        .quick_buffer_size(args.quick_buffer_size)
+        .mid_hash_size(args.mid_hash_size)
+        .full_buffer_size(args.full_buffer_size)
+        .mmap(args.mmap)
+        .max_groups(args.max_groups)
+        .prefix_only(args.prefix_only)
+        .threads(args.threads)
+        .verify(args.verify)
+        .cross_only(args.cross_only)
+        .cross_device_only(args.cross_device_only)
+        .within_subdir(args.report_duplicates_only_within_root)
+        .same_name(args.same_name)
+        .min_duplicates(args.min_duplicates)
+        .case_insensitive_names(args.case_insensitive_names)
+        .absolute_paths(args.absolute_paths)
+        .ignore_hardlinks(args.ignore_hardlinks)
+        .include_empty(args.include_empty)
+        .checksums(args.output_sums.is_some())
+        .similar_images(args.similar_images.then_some(args.image_threshold))
+        .progress(progress.clone());
+    if let Some(list) = &args.from_file {
+        finder = finder.file_list(list);
+    }
+    if let Some(path) = &args.baseline {
+        finder = finder.baseline(load_results_json(path)?);
+    }
+    // Without --cache, --resume keeps its hashes in a file of its own that
+    // is removed once a scan completes.
+    let resume_file = match (&args.cache, args.resume) {
+        (None, true) => {
+            let path = config::default_resume_path()
+                .context("No cache directory found for --resume, pass --cache")?;
+            if let Some(dir) = path.parent() {
+                fs::create_dir_all(dir)
+                    .with_context(|| format!("Failed to create {}", dir.display()))?;
+            }
+            Some(path)
+        }
+        _ => None,
+    };
+    let cache_path = args.cache.clone().or_else(|| resume_file.clone());
+    if let Some(cache) = &cache_path {
+        finder = finder.cache(cache);
+    }
+    if let Some(ranges) = args.sample_verify {
+        let seed = args.seed.unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .map_or(0, |d| d.as_nanos() as u64)
+        });
+        finder = finder.sample_verify(ranges, seed);
+    }
+    if args.skip_immutable {
+        finder = finder.hidden_predicate(HiddenPredicate::new(is_immutable));
+    }
+    if args.resume {
+        finder = finder.checkpoint_interval(Some(CHECKPOINT_INTERVAL));
     }
 
-    let start_time = Instant::now();
-    let dir = Path::new(&args.path);
-    validate_path(dir)?;
+    // --stream and --output-ndjson both take groups as hashing confirms them.
+    let mut streamer = None;
+    if args.stream || args.output_ndjson.is_some() {
+        let (sender, receiver) = mpsc::channel::<DuplicateGroup>();
+        finder = finder.stream(sender);
+        let progress = progress.clone();
+        let absolute = args.absolute_paths;
+        let print = args.stream;
+        let min_duplicates = args.min_duplicates;
+        let mut ndjson = args
+            .output_ndjson
+            .as_deref()
+            .map(NdjsonWriter::create)
+            .transpose()?;
+        // The file now exists, possibly under a scanned path; keep it out.
+        if let Some(path) = &args.output_ndjson {
+            finder = finder.ignore([std::path::absolute(path)?.display().to_string()]);
+        }
+        streamer = Some(thread::spawn(move || -> anyhow::Result<()> {
+            for mut group in receiver {
+                if absolute {
+                    for file in &mut group.files {
+                        *file = canonical_or_original(Path::new(file)).display().to_string();
+                    }
+                }
+                if print {
+                    progress.println(&format_streamed_group(&group));
+                }
+                if let Some(ndjson) = &mut ndjson
+                    && group.files.len() >= min_duplicates
+                {
+                    ndjson.write(&group)?;
+                }
+            }
+            Ok(())
+        }));
+    }
 
-    info!("Starting duplicate file scan in {}", dir.display());
-    info!(
-        "Configuration: quick_hash={}B, quick_buf={}KB, full_buf={}MB",
-        args.quick_hash_size, args.quick_buffer_size, args.full_buffer_size
-    );
+    // Closing the channel after the scan wakes the timer so it exits at once.
+    let stats_timer = args.stats_interval.map(|secs| {
+        let (stop, stopped) = mpsc::channel::<()>();
+        let progress = progress.clone();
+        let start = Instant::now();
+        let timer = thread::spawn(move || {
+            while let Err(RecvTimeoutError::Timeout) =
+                stopped.recv_timeout(Duration::from_secs(secs))
+            {
+                if INTERRUPTED.load(Ordering::Relaxed) {
+                    break;
+                }
+                let line = progress.stats_line();
+                progress.suspend(|| info!("After {}: {}", HumanDuration(start.elapsed()), line));
+            }
+        });
+        (stop, timer)
+    });
 
-    let scan_progress = ProgressBar::new_spinner();
-    scan_progress.set_style(
-        ProgressStyle::default_spinner()
-            .template("{spinner:.green} {msg}")
-            .unwrap(),
-    );
-    scan_progress.set_message("Scanning files...");
-
-    let files = scan_files(
-        dir,
-        args.follow_links,
-        args.min_size,
-        &args.ignore,
-        args.hidden,
-        &scan_progress,
-    )?;
-    let msg = format!("Found {} files", files.len());
-    scan_progress.finish_with_message(msg);
-
-    if files.is_empty() {
-        info!("No files found to process");
-        return Ok(());
-    }
-
-    let group_progress = ProgressBar::new(files.len() as u64);
-    group_progress.set_style(
-        ProgressStyle::default_bar()
-            .template("{bar:40.cyan/blue} {pos:>7}/{len:7} {msg}")
-            .unwrap(),
-    );
-    group_progress.set_message("Grouping by size...");
+    let found = finder.find();
+    if let Some((stop, timer)) = stats_timer {
+        drop(stop);
+        let _ = timer.join();
+    }
+    // Dropping the finder closes the stream so the printer thread can finish.
+    let mut streamed = Ok(());
+    let finder = match streamer {
+        Some(streamer) => {
+            drop(finder);
+            streamed = streamer.join().unwrap_or(Ok(()));
+            None
+        }
+        None => Some(finder),
+    };
+    progress.finish();
+    streamed?;
+    if matches!(found, Err(DupFindError::Interrupted)) && cache_path.is_some() {
+        info!("Hashes computed so far were saved, rerun with --resume to continue");
+    }
+    let found = found?;
+    if let Some(path) = &resume_file {
+        let _ = fs::remove_file(path);
+    }
+    let (hashes, stats, duration) = (&found.hashes, &found.stats, found.duration);
+    if args.profile {
+        eprintln!("{}", format_timings(&found.timings, duration));
+    }
+    let breakdown = if args.by_extension {
+        extension_breakdown(hashes)
+    } else {
+        Vec::new()
+    };
+    let histogram = if args.size_histogram {
+        size_histogram(hashes, args.units)
+    } else {
+        Vec::new()
+    };
+    let results = || {
+        let mut results = ScanResults {
+            extension_breakdown: breakdown.clone(),
+            size_histogram: histogram.clone(),
+            ..build_results(&found)
+        };
+        if args.show_metadata {
+            attach_metadata(&mut results.groups);
+        }
+        if args.show_devices || args.cross_device_only {
+            attach_devices(&mut results.groups);
+        }
+        results
+    };
 
-    let groups = group_by_size(&files, &group_progress)?;
-    let num_size_groups = groups.len();
-    let msg = format!("Found {} size groups", num_size_groups);
-    group_progress.finish_with_message(msg);
+    let print_options = PrintOptions {
+        summary: args.summary,
+        sort_by: args.sort_by,
+        reverse: args.reverse,
+        top: args.top,
+        group_by_dir: args.group_by_dir,
+        show_metadata: args.show_metadata,
+        show_devices: args.show_devices || args.cross_device_only,
+    };
+    if args.json_stdout {
+        print_results_json(&results())?;
+    } else {
+        // An empty folder is still worth watching.
+        if !args.watch
+            && (stats.total_files_scanned == 0
+                || (stats.total_size_groups == 0
+                    && found.similar.is_empty()
+                    && found.checksums.is_empty()))
+        {
+            if args.summary_line {
+                println!(
+                    "{}",
+                    format_summary_line(stats, duration, found.prefix_only)
+                );
+            }
+            return Ok(false);
+        }
 
-    if groups.is_empty() {
-        info!("No potential duplicates found");
-        return Ok(());
+        if let Some(bytes) = found.prefix_only {
+            println!("{}", prefix_only_notice(bytes).yellow());
+        }
+        print_results(stats, hashes, &print_options)?;
+        print_extension_breakdown(&breakdown);
+        print_size_histogram(&histogram);
+        print_similar(&found.similar);
+        print_symlinks(&found.symlinks);
+        if args.report_broken_links {
+            print_broken_links(&found.broken_links);
+        }
+        print_errors(&found.errors);
     }
 
-    let total_to_hash: usize = groups.values().map(|files| files.len()).sum();
-    let hash_progress = ProgressBar::new(total_to_hash as u64);
-    hash_progress.set_style(
-        ProgressStyle::default_bar()
-            .template("{bar:40.green/yellow} {pos:>7}/{len:7} {percent:>3}% {msg}")
-            .unwrap(),
-    );
-    hash_progress.set_message("Computing hashes...");
+    if let Some(json_path) = args.output_json {
+        if is_zstd_path(&json_path) {
+            save_results_json_zst(&json_path, &results(), args.zstd_level)?;
+        } else {
+            save_results_json(&json_path, &results())?;
+        }
+        info!("Results saved to {}", json_path.display());
+    }
 
-    let hashes = hasher::compute_hashes(
-        groups,
-        args.quick_hash_size,
-        args.quick_buffer_size,
-        args.full_buffer_size,
-        &hash_progress,
-    )?;
+    if let Some(ndjson_path) = args.output_ndjson {
+        info!("Results saved to {}", ndjson_path.display());
+    }
 
-    hash_progress.finish_with_message("Hash computation completed");
+    if let Some(csv_path) = args.output_csv {
+        save_results_csv(&csv_path, hashes, found.prefix_only)?;
+        info!("Results saved to {}", csv_path.display());
+    }
 
-    let stats = calculate_statistics(&hashes, files.len(), num_size_groups)?;
-    let duration = start_time.elapsed().as_secs_f64();
+    if let Some(sums_path) = args.output_sums {
+        save_checksums(&sums_path, &found.checksums)?;
+        info!("Checksums saved to {}", sums_path.display());
+    }
 
-    print_results(&stats, &hashes)?;
+    if let Some(html_path) = args.output_html {
+        save_results_html(&html_path, stats, hashes, found.prefix_only)?;
+        info!("Report saved to {}", html_path.display());
+    }
 
-    if let Some(json_path) = args.output_json {
-        save_results_json(&json_path, &stats, &hashes, duration)?;
-        info!("Results saved to {}", json_path.display());
+    #[cfg(feature = "sqlite")]
+    if let Some(db_path) = args.output_sqlite {
+        save_results_sqlite(&db_path, &results())?;
+        info!("Scan appended to {}", db_path.display());
+    }
+
+    if let Some(command) = &args.exec {
+        let summary = actions::exec_groups(hashes, command, args.dry_run)?;
+        if summary.runs > 0 {
+            info!("Ran {} commands, {} failed", summary.runs, summary.failures);
+        }
+    }
+
+    // Opened before anything changes, so a bad path fails the run cleanly.
+    let mut audit = match &args.audit_log {
+        Some(path) if !args.dry_run => Some(AuditLog::open(path)?),
+        _ => None,
+    };
+    if args.interactive {
+        // An aborted review still reports what was found.
+        if let Some(summary) = tui::review(hashes, &args.protect, audit.as_mut(), args.dry_run)? {
+            log_reclaimed(&summary, stats.total_wasted_space, args.dry_run);
+        }
+    }
+
+    let action = if args.delete {
+        Some(Action::Delete)
+    } else if args.hardlink {
+        Some(Action::Hardlink)
+    } else if args.reflink {
+        Some(Action::Reflink)
+    } else if args.symlink {
+        Some(Action::Symlink {
+            absolute: args.absolute_symlinks,
+            manifest: args.symlink_manifest.clone(),
+        })
+    } else {
+        args.move_to.clone().map(Action::Move)
+    };
+    if let Some(action) = action {
+        // Always preview: the keep strategy and existing hardlinks make the
+        // bytes actually freed differ from the wasted total.
+        let plan = actions::plan_action(hashes, &action, args.keep, &args.prefer, &args.protect);
+        plan.print(&action);
+        if args.dry_run {
+            let summary = ActionSummary {
+                files_affected: plan.files(),
+                bytes_reclaimed: plan.bytes_freed(),
+            };
+            log_reclaimed(&summary, stats.total_wasted_space, true);
+        } else if actions::confirm(&plan, args.yes, args.quiet)? {
+            let summary = actions::apply_plan(&plan, &action, audit.as_mut(), false)?;
+            log_reclaimed(&summary, stats.total_wasted_space, false);
+        } else if plan.files() > 0 {
+            info!("Aborted, no files were changed");
+        }
     }
 
     info!(
-        "Scan completed in {:.2}s: {} duplicate groups, {} files, {} wasted",
+        "Scan completed in {:.2}s: {} duplicate groups, {} files, {} wasted, {} read of {} scanned",
         duration,
         stats.total_duplicate_groups,
         stats.total_duplicate_files,
-        humansize::format_size(stats.total_wasted_space, humansize::DECIMAL)
+        format_bytes(stats.total_wasted_space),
+        format_bytes(stats.total_bytes_read),
+        format_bytes(stats.total_bytes_scanned)
     );
+    if args.summary_line {
+        println!(
+            "{}",
+            format_summary_line(stats, duration, found.prefix_only)
+        );
+    }
 
-    Ok(())
+    if args.watch {
+        let finder = finder.context("--watch cannot be combined with --stream")?;
+        watch::watch(
+            &finder,
+            &roots,
+            Duration::from_secs(args.watch_interval),
+            build_results(&found),
+            &print_options,
+            &progress,
+        )?;
+    }
+
+    Ok(stats.total_duplicate_groups > 0)
+}
+
+fn log_reclaimed(summary: &ActionSummary, wasted: u64, dry_run: bool) {
+    let verb = if dry_run {
+        "Would reclaim"
+    } else {
+        "Reclaimed"
+    };
+    info!(
+        "{} {} of {} theoretical ({} files)",
+        verb,
+        format_bytes(summary.bytes_reclaimed),
+        format_bytes(wasted),
+        summary.files_affected
+    );
 }
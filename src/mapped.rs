@@ -0,0 +1,288 @@
+//! Memory maps that survive the file being truncated while they are read.
+//!
+//! Reading a page of a mapping that lies past the end of a shrunken file
+//! raises SIGBUS, which normally kills the process. While a `GuardedMap` is
+//! alive, a SIGBUS handler recognizes faults inside it, maps zeros over the
+//! rest of the mapping so the read can finish, and marks the map truncated.
+//! The caller then throws away what it read.
+
+use memmap2::Mmap;
+use std::fs::File;
+use std::io;
+use std::mem::ManuallyDrop;
+
+/// A read-only map of a whole file, see the module docs.
+pub struct GuardedMap {
+    map: ManuallyDrop<Mmap>,
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    slot: &'static guard::Slot,
+}
+
+impl GuardedMap {
+    /// Map `file`. Returns `None` where a fault could not be caught (too many
+    /// maps at once, or a platform without a handler), in which case the
+    /// caller should read the file instead.
+    pub fn new(file: &File) -> io::Result<Option<Self>> {
+        #[cfg(any(target_os = "linux", target_os = "macos"))]
+        {
+            let Some(slot) = guard::claim() else {
+                return Ok(None);
+            };
+            // SAFETY: read-only; faults from truncation are caught by the
+            // handler, and callers compare metadata to catch other changes.
+            let map = match unsafe { Mmap::map(file) } {
+                Ok(map) => map,
+                Err(e) => {
+                    slot.release();
+                    return Err(e);
+                }
+            };
+            slot.watch(map.as_ptr() as usize, map.len());
+            Ok(Some(GuardedMap {
+                map: ManuallyDrop::new(map),
+                slot,
+            }))
+        }
+        #[cfg(windows)]
+        {
+            // Windows refuses to truncate a file while it is mapped.
+            // SAFETY: as above; callers compare metadata to catch changes.
+            let map = unsafe { Mmap::map(file)? };
+            Ok(Some(GuardedMap {
+                map: ManuallyDrop::new(map),
+            }))
+        }
+        #[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+        {
+            let _ = file;
+            Ok(None)
+        }
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        &self.map
+    }
+
+    /// Whether a read reached past the end of the file, so some of the bytes
+    /// read were zeros rather than its contents.
+    pub fn truncated(&self) -> bool {
+        #[cfg(any(target_os = "linux", target_os = "macos"))]
+        {
+            self.slot.truncated()
+        }
+        #[cfg(not(any(target_os = "linux", target_os = "macos")))]
+        {
+            false
+        }
+    }
+}
+
+impl Drop for GuardedMap {
+    fn drop(&mut self) {
+        // Stop claiming the range before unmapping it, so a new map at the
+        // same addresses is never mistaken for this one.
+        #[cfg(any(target_os = "linux", target_os = "macos"))]
+        self.slot.unwatch();
+        // SAFETY: dropped exactly once, here.
+        unsafe { ManuallyDrop::drop(&mut self.map) };
+        #[cfg(any(target_os = "linux", target_os = "macos"))]
+        self.slot.release();
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+mod guard {
+    use std::ptr;
+    use std::sync::OnceLock;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+    /// Maps guarded at once; more than this are read instead.
+    const SLOT_COUNT: usize = 64;
+
+    /// One guarded range. The handler only reads atomics, so it never waits
+    /// on a lock held by the thread it interrupted.
+    pub struct Slot {
+        claimed: AtomicBool,
+        start: AtomicUsize,
+        len: AtomicUsize,
+        truncated: AtomicBool,
+    }
+
+    impl Slot {
+        const fn new() -> Self {
+            Slot {
+                claimed: AtomicBool::new(false),
+                start: AtomicUsize::new(0),
+                len: AtomicUsize::new(0),
+                truncated: AtomicBool::new(false),
+            }
+        }
+
+        pub fn watch(&self, start: usize, len: usize) {
+            self.truncated.store(false, Ordering::Relaxed);
+            self.len.store(len, Ordering::Relaxed);
+            self.start.store(start, Ordering::Release);
+        }
+
+        pub fn unwatch(&self) {
+            self.start.store(0, Ordering::Release);
+        }
+
+        pub fn release(&self) {
+            self.claimed.store(false, Ordering::Release);
+        }
+
+        pub fn truncated(&self) -> bool {
+            self.truncated.load(Ordering::Acquire)
+        }
+    }
+
+    static SLOTS: [Slot; SLOT_COUNT] = [const { Slot::new() }; SLOT_COUNT];
+    static PAGE_SIZE: AtomicUsize = AtomicUsize::new(0);
+    static PREVIOUS: OnceLock<libc::sigaction> = OnceLock::new();
+
+    /// Take a free slot, installing the handler on first use.
+    pub fn claim() -> Option<&'static Slot> {
+        if !install() {
+            return None;
+        }
+        SLOTS.iter().find(|slot| {
+            slot.claimed
+                .compare_exchange(false, true, Ordering::AcqRel, Ordering::Relaxed)
+                .is_ok()
+        })
+    }
+
+    fn install() -> bool {
+        static INSTALLED: OnceLock<bool> = OnceLock::new();
+        *INSTALLED.get_or_init(|| unsafe {
+            let page = libc::sysconf(libc::_SC_PAGESIZE);
+            if page <= 0 {
+                return false;
+            }
+            PAGE_SIZE.store(page as usize, Ordering::Relaxed);
+
+            let mut action: libc::sigaction = std::mem::zeroed();
+            action.sa_sigaction = on_sigbus as *const () as usize;
+            action.sa_flags = libc::SA_SIGINFO | libc::SA_RESTART;
+            libc::sigemptyset(&mut action.sa_mask);
+            let mut previous: libc::sigaction = std::mem::zeroed();
+            if libc::sigaction(libc::SIGBUS, &action, &mut previous) != 0 {
+                return false;
+            }
+            let _ = PREVIOUS.set(previous);
+            true
+        })
+    }
+
+    #[cfg(target_os = "linux")]
+    unsafe fn fault_address(info: *const libc::siginfo_t) -> usize {
+        unsafe { (*info).si_addr() as usize }
+    }
+
+    #[cfg(target_os = "macos")]
+    unsafe fn fault_address(info: *const libc::siginfo_t) -> usize {
+        unsafe { (*info).si_addr as usize }
+    }
+
+    extern "C" fn on_sigbus(
+        signal: libc::c_int,
+        info: *mut libc::siginfo_t,
+        context: *mut libc::c_void,
+    ) {
+        let address = unsafe { fault_address(info) };
+        let page = PAGE_SIZE.load(Ordering::Relaxed);
+        for slot in &SLOTS {
+            let start = slot.start.load(Ordering::Acquire);
+            let len = slot.len.load(Ordering::Relaxed);
+            if start == 0 || address < start || address - start >= len {
+                continue;
+            }
+            // Replace the rest of the mapping with zeros in one go, so a
+            // large truncated file faults once rather than once per page.
+            let from = address & !(page - 1);
+            let end = (start + len).next_multiple_of(page);
+            let zeros = unsafe {
+                libc::mmap(
+                    from as *mut libc::c_void,
+                    end - from,
+                    libc::PROT_READ,
+                    libc::MAP_PRIVATE | libc::MAP_ANON | libc::MAP_FIXED,
+                    -1,
+                    0,
+                )
+            };
+            if zeros != libc::MAP_FAILED {
+                slot.truncated.store(true, Ordering::Release);
+                return;
+            }
+        }
+        unsafe { forward(signal, info, context) };
+    }
+
+    /// Hand a fault that is not ours to the handler installed before, or
+    /// restore the default action so the access kills the process as it
+    /// would have without us.
+    unsafe fn forward(signal: libc::c_int, info: *mut libc::siginfo_t, context: *mut libc::c_void) {
+        unsafe {
+            match PREVIOUS.get() {
+                Some(previous)
+                    if previous.sa_sigaction != libc::SIG_DFL
+                        && previous.sa_sigaction != libc::SIG_IGN =>
+                {
+                    if previous.sa_flags & libc::SA_SIGINFO != 0 {
+                        let handler: extern "C" fn(
+                            libc::c_int,
+                            *mut libc::siginfo_t,
+                            *mut libc::c_void,
+                        ) = std::mem::transmute(previous.sa_sigaction);
+                        handler(signal, info, context);
+                    } else {
+                        let handler: extern "C" fn(libc::c_int) =
+                            std::mem::transmute(previous.sa_sigaction);
+                        handler(signal);
+                    }
+                }
+                _ => {
+                    let mut default: libc::sigaction = std::mem::zeroed();
+                    default.sa_sigaction = libc::SIG_DFL;
+                    libc::sigaction(libc::SIGBUS, &default, ptr::null_mut());
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    #[test]
+    fn test_truncation_while_mapped_is_caught() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("big");
+        fs::write(&path, vec![7u8; 1 << 20]).unwrap();
+        let file = File::open(&path).unwrap();
+        let map = GuardedMap::new(&file).unwrap().unwrap();
+
+        File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_len(0)
+            .unwrap();
+        let sum: u64 = map.bytes().iter().map(|&b| b as u64).sum();
+        assert_eq!(sum, 0);
+        assert!(map.truncated());
+
+        // A fresh map of an intact file is not marked.
+        fs::write(&path, vec![1u8; 4096]).unwrap();
+        let map = GuardedMap::new(&File::open(&path).unwrap())
+            .unwrap()
+            .unwrap();
+        assert_eq!(map.bytes().len(), 4096);
+        assert!(!map.truncated());
+    }
+}
@@ -1,16 +1,75 @@
 use anyhow::{Context, Result};
+use clap::ValueEnum;
 use colored::Colorize;
-use humansize::{DECIMAL, format_size};
+use humansize::{BINARY, DECIMAL, format_size};
+use std::cmp::Ordering;
 use std::collections::HashMap;
-use std::fs::OpenOptions;
-use std::io::Write;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering as AtomicOrdering};
+use std::time::Duration;
 use supports_hyperlinks::Stream;
 
-use crate::statistics::{DuplicateGroup, ScanResults, ScanStatistics};
+use crate::error::DupFindError;
+use crate::statistics::{
+    DuplicateGroup, Duplicates, ExtensionStats, FileMetadata, SCHEMA_VERSION, ScanError,
+    ScanResults, ScanStatistics, SimilarGroup, SizeBucket, SymlinkEntry, group_digest,
+};
+use crate::utils::{devices, write_atomically};
+
+/// Default zstd level for `.zst` results, zstd's own balance of speed and size.
+pub const DEFAULT_ZSTD_LEVEL: i32 = 3;
+
+/// Frame magic at the start of every zstd stream.
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+/// Whether results written to `path` should be zstd-compressed.
+pub fn is_zstd_path(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext == "zst")
+}
+
+/// When listed paths become clickable OSC 8 hyperlinks.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum Hyperlinks {
+    /// When the terminal advertises support and colors are on
+    #[default]
+    Auto,
+    Always,
+    /// Plain paths, for terminals that misreport support
+    Never,
+}
+
+impl Hyperlinks {
+    fn enabled(self) -> bool {
+        match self {
+            Hyperlinks::Auto => {
+                supports_hyperlinks::on(Stream::Stdout)
+                    && colored::control::SHOULD_COLORIZE.should_colorize()
+            }
+            Hyperlinks::Always => true,
+            Hyperlinks::Never => false,
+        }
+    }
+}
+
+static HYPERLINKS: AtomicU8 = AtomicU8::new(Hyperlinks::Auto as u8);
+
+/// Choose when `format_path` emits hyperlinks; set once at startup.
+pub fn set_hyperlinks(mode: Hyperlinks) {
+    HYPERLINKS.store(mode as u8, AtomicOrdering::Relaxed);
+}
+
+fn hyperlinks() -> Hyperlinks {
+    match HYPERLINKS.load(AtomicOrdering::Relaxed) {
+        m if m == Hyperlinks::Always as u8 => Hyperlinks::Always,
+        m if m == Hyperlinks::Never as u8 => Hyperlinks::Never,
+        _ => Hyperlinks::Auto,
+    }
+}
 
 fn format_path(path: &Path) -> String {
-    if supports_hyperlinks::on(Stream::Stdout) {
+    if hyperlinks().enabled() {
         let display = path.display();
         let uri = format!("file://{}", path.display());
         format!("\x1b]8;;{}\x07{}\x1b]8;;\x07", uri, display)
@@ -19,7 +78,167 @@ fn format_path(path: &Path) -> String {
     }
 }
 
-pub fn print_results(stats: &ScanStatistics, hashes: &HashMap<String, Vec<PathBuf>>) -> Result<()> {
+/// Units for byte counts in everything dupfind prints or writes for people.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum Units {
+    /// Powers of 1000: kB, MB, GB
+    #[default]
+    Decimal,
+    /// Powers of 1024: KiB, MiB, GiB
+    Binary,
+}
+
+impl Units {
+    pub fn format(self, bytes: u64) -> String {
+        match self {
+            Units::Decimal => format_size(bytes, DECIMAL),
+            Units::Binary => format_size(bytes, BINARY),
+        }
+    }
+}
+
+static BINARY_UNITS: AtomicBool = AtomicBool::new(false);
+
+/// Choose the units `format_bytes` uses from now on; set once at startup.
+pub fn set_units(units: Units) {
+    BINARY_UNITS.store(units == Units::Binary, AtomicOrdering::Relaxed);
+}
+
+/// Format a byte count in the units picked with `set_units` (decimal by default).
+pub fn format_bytes(bytes: u64) -> String {
+    if BINARY_UNITS.load(AtomicOrdering::Relaxed) {
+        Units::Binary.format(bytes)
+    } else {
+        Units::Decimal.format(bytes)
+    }
+}
+
+/// Largest groups listed by `--summary` unless `--top` says otherwise.
+const SUMMARY_TOP_GROUPS: usize = 5;
+
+/// Order of groups in the terminal listing.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum SortBy {
+    /// Total size of all copies in the group, largest first (the default
+    /// order from before sorting was configurable)
+    #[default]
+    Wasted,
+    /// Size of a single file, largest first
+    Size,
+    /// Number of copies, most first
+    Count,
+    /// Path of the first file, alphabetically
+    Path,
+}
+
+/// How `print_results` lays out the listing.
+#[derive(Debug, Clone, Default)]
+pub struct PrintOptions {
+    /// Print totals and the largest groups only
+    pub summary: bool,
+    pub sort_by: SortBy,
+    /// Invert the sort order
+    pub reverse: bool,
+    /// List only this many groups after sorting; totals still cover all of them
+    pub top: Option<usize>,
+    /// Cluster groups by the set of directories their files live in
+    pub group_by_dir: bool,
+    /// Append permissions, owner and mtime to every listed file
+    pub show_metadata: bool,
+    /// List the devices each group's copies live on
+    pub show_devices: bool,
+}
+
+/// Duplicate groups whose files all live in the same set of directories.
+#[derive(Debug, PartialEq)]
+struct DirCluster {
+    dirs: Vec<PathBuf>,
+    /// Indices into the sorted groups, in listing order.
+    groups: Vec<usize>,
+    wasted: u64,
+}
+
+/// Cluster sorted groups by their members' parent directories, so mirror
+/// trees show up as one entry. Clusters keep the order of their first group.
+fn cluster_by_dir(groups: &[(String, Vec<PathBuf>, u64)]) -> Vec<DirCluster> {
+    let mut clusters: Vec<DirCluster> = Vec::new();
+    let mut index: HashMap<Vec<PathBuf>, usize> = HashMap::new();
+    for (i, (_, files, size)) in groups.iter().enumerate() {
+        let mut dirs: Vec<PathBuf> = files
+            .iter()
+            .map(|f| f.parent().unwrap_or(Path::new("")).to_path_buf())
+            .collect();
+        dirs.sort();
+        dirs.dedup();
+        let slot = *index.entry(dirs.clone()).or_insert_with(|| {
+            clusters.push(DirCluster {
+                dirs,
+                groups: Vec::new(),
+                wasted: 0,
+            });
+            clusters.len() - 1
+        });
+        clusters[slot].groups.push(i);
+        clusters[slot].wasted += size * (files.len() as u64 - 1);
+    }
+    clusters
+}
+
+fn print_by_dir(groups: &[(String, Vec<PathBuf>, u64)]) {
+    for cluster in cluster_by_dir(groups) {
+        let dirs: Vec<String> = cluster.dirs.iter().map(|d| format_path(d)).collect();
+        println!("{}", dirs.join(&format!(" {} ", "↔".dimmed())).bold());
+        println!(
+            "  {} {} {} {}",
+            match cluster.groups.len() {
+                1 => "1 group".to_string(),
+                n => format!("{} groups", n),
+            }
+            .white(),
+            "·".dimmed(),
+            format_bytes(cluster.wasted).red(),
+            "wasted".dimmed()
+        );
+        for &i in &cluster.groups {
+            let (_, files, size) = &groups[i];
+            let mut names: Vec<_> = files
+                .iter()
+                .filter_map(|f| f.file_name())
+                .map(|n| n.to_string_lossy())
+                .collect();
+            names.sort();
+            names.dedup();
+            println!(
+                "  {} {} {} {}",
+                "│".dimmed(),
+                format_bytes(*size).white(),
+                format!("× {}", files.len()).dimmed(),
+                names.join(", ")
+            );
+        }
+        println!();
+    }
+}
+
+fn sort_groups(groups: &mut [(String, Vec<PathBuf>, u64)], sort_by: SortBy, reverse: bool) {
+    groups.sort_by(|a, b| {
+        let wasted = |g: &(String, Vec<PathBuf>, u64)| g.2 * g.1.len() as u64;
+        let order = match sort_by {
+            SortBy::Wasted => wasted(b).cmp(&wasted(a)),
+            SortBy::Size => b.2.cmp(&a.2),
+            SortBy::Count => b.1.len().cmp(&a.1.len()),
+            SortBy::Path => Ordering::Equal,
+        };
+        let order = order.then_with(|| a.1[0].cmp(&b.1[0]));
+        if reverse { order.reverse() } else { order }
+    });
+}
+
+pub fn print_results(
+    stats: &ScanStatistics,
+    hashes: &HashMap<String, Vec<PathBuf>>,
+    options: &PrintOptions,
+) -> Result<()> {
     if stats.total_duplicate_groups == 0 {
         println!("{}", "No duplicates found.".green());
         return Ok(());
@@ -34,8 +253,17 @@ pub fn print_results(stats: &ScanStatistics, hashes: &HashMap<String, Vec<PathBu
         } else {
             "duplicate groups"
         },
-        format_size(stats.total_wasted_space, DECIMAL).red().bold()
+        format_bytes(stats.total_wasted_space).red().bold()
     );
+    if let Some(top) = options.top
+        && !options.summary
+        && top < stats.total_duplicate_groups
+    {
+        println!(
+            "{}",
+            format!("Showing {} of {} groups", top, stats.total_duplicate_groups).dimmed()
+        );
+    }
     println!();
 
     let mut sorted_groups: Vec<_> = hashes
@@ -54,20 +282,32 @@ pub fn print_results(stats: &ScanStatistics, hashes: &HashMap<String, Vec<PathBu
         })
         .collect();
 
-    sorted_groups.sort_by(|a, b| {
-        let wasted_a = a.1.len() as u64 * a.2;
-        let wasted_b = b.1.len() as u64 * b.2;
-        wasted_b.cmp(&wasted_a)
-    });
+    sort_groups(&mut sorted_groups, options.sort_by, options.reverse);
 
-    for (idx, (_hash, files, size)) in sorted_groups.iter().enumerate() {
+    if options.summary {
+        let top = options.top.unwrap_or(SUMMARY_TOP_GROUPS);
+        print_summary(stats, &sorted_groups, top);
+        return Ok(());
+    }
+
+    let shown = options.top.unwrap_or(sorted_groups.len());
+    if options.group_by_dir {
+        print_by_dir(&sorted_groups[..shown.min(sorted_groups.len())]);
+        return Ok(());
+    }
+    for (idx, (_hash, files, size)) in sorted_groups.iter().take(shown).enumerate() {
         let wasted = size * (files.len() as u64 - 1);
 
+        let size_label = if *size == 0 {
+            "empty".yellow()
+        } else {
+            format_bytes(*size).white()
+        };
         println!(
             "{} {} {} {} {}",
             format!("#{}", idx + 1).cyan().bold(),
             "·".dimmed(),
-            format_size(*size, DECIMAL).white(),
+            size_label,
             "×".dimmed(),
             format!("{} files", files.len()).white(),
         );
@@ -80,74 +320,979 @@ pub fn print_results(stats: &ScanStatistics, hashes: &HashMap<String, Vec<PathBu
             } else {
                 "  │".dimmed()
             };
-            println!("{} {}", prefix, format_path(path));
+            let mut notes = Vec::new();
+            if path.is_symlink() {
+                notes.push("(symlink)".to_string());
+            }
+            if options.show_metadata {
+                notes.push(format_metadata(path));
+            }
+            if notes.is_empty() {
+                println!("{} {}", prefix, format_path(path));
+            } else {
+                println!(
+                    "{} {} {}",
+                    prefix,
+                    format_path(path),
+                    notes.join(" ").dimmed()
+                );
+            }
         }
 
-        println!(
-            "    {} {}",
-            "wasted:".dimmed(),
-            format_size(wasted, DECIMAL).red()
-        );
+        println!("    {} {}", "wasted:".dimmed(), format_bytes(wasted).red());
+        if options.show_devices {
+            let devices: Vec<String> = devices(files).iter().map(u64::to_string).collect();
+            println!("    {} {}", "devices:".dimmed(), devices.join(", "));
+        }
         println!();
     }
 
     Ok(())
 }
 
-pub fn save_results_json(
-    path: &Path,
+fn print_summary(
     stats: &ScanStatistics,
-    hashes: &HashMap<String, Vec<PathBuf>>,
-    duration: f64,
-) -> Result<()> {
-    let groups: Vec<DuplicateGroup> = hashes
+    sorted_groups: &[(String, Vec<PathBuf>, u64)],
+    top: usize,
+) {
+    println!(
+        "  {} {} in {} files (avg {}), {} read while hashing",
+        "scanned:".dimmed(),
+        format_bytes(stats.total_bytes_scanned).white(),
+        stats.total_files_scanned,
+        format_bytes(stats.average_file_size),
+        format_bytes(stats.total_bytes_read)
+    );
+    let quick = &stats.quick_hash;
+    if quick.files > 0 {
+        println!(
+            "  {} {} files, {} collisions, {} full reads avoided",
+            "quick hash:".dimmed(),
+            quick.files,
+            quick.collisions,
+            quick.full_reads_avoided
+        );
+    }
+    println!(
+        "  {} {}",
+        "duplicate files:".dimmed(),
+        stats.total_duplicate_files.to_string().white()
+    );
+    println!(
+        "  {} {}",
+        "wasted space:".dimmed(),
+        format_bytes(stats.total_wasted_space).red()
+    );
+    println!();
+
+    println!("{}", "Top groups:".bold());
+    for (idx, (_hash, files, size)) in sorted_groups.iter().take(top).enumerate() {
+        println!(
+            "  {} {} {} {} {}",
+            format!("#{}", idx + 1).cyan().bold(),
+            format_bytes(*size).white(),
+            "×".dimmed(),
+            format!("{} files", files.len()).white(),
+            format_path(&files[0]).dimmed()
+        );
+    }
+    if sorted_groups.len() > top {
+        println!(
+            "  {}",
+            format!("... and {} more", sorted_groups.len() - top).dimmed()
+        );
+    }
+    println!();
+}
+
+/// The serializable form of a group, if it still has at least two files on disk.
+fn existing_group(hash: &str, files: &[PathBuf]) -> Option<DuplicateGroup> {
+    let mut existing_files: Vec<_> = files
         .iter()
-        .filter_map(|(hash, files)| {
-            let existing_files: Vec<_> = files
-                .iter()
-                .filter(|p| p.exists())
-                .filter_map(|p| p.to_str().map(String::from))
-                .collect();
+        .filter(|p| p.exists())
+        .filter_map(|p| p.to_str().map(String::from))
+        .collect();
 
-            if existing_files.len() < 2 {
-                return None;
-            }
+    if existing_files.len() < 2 {
+        return None;
+    }
+    existing_files.sort();
 
-            let size = std::fs::metadata(&files[0])
-                .ok()
-                .map(|m| m.len())
-                .unwrap_or(0);
+    let size = std::fs::metadata(&files[0])
+        .ok()
+        .map(|m| m.len())
+        .unwrap_or(0);
 
-            Some(DuplicateGroup {
-                hash: hash.clone(),
-                size,
-                files: existing_files,
-            })
-        })
+    Some(DuplicateGroup {
+        hash: group_digest(hash).to_string(),
+        size,
+        files: existing_files,
+        metadata: Vec::new(),
+        devices: Vec::new(),
+        prefix_only: None,
+    })
+}
+
+/// Fill in each group's `metadata` from the files on disk, for `--show-metadata`.
+pub fn attach_metadata(groups: &mut [DuplicateGroup]) {
+    for group in groups {
+        group.metadata = group
+            .files
+            .iter()
+            .filter_map(|f| FileMetadata::read(Path::new(f)))
+            .collect();
+    }
+}
+
+/// Fill in each group's `devices` from the files on disk, for `--show-devices`.
+pub fn attach_devices(groups: &mut [DuplicateGroup]) {
+    for group in groups {
+        let files: Vec<PathBuf> = group.files.iter().map(PathBuf::from).collect();
+        group.devices = devices(&files);
+    }
+}
+
+/// `rw-r--r-- 1000:1000 2024-01-31T08:00:00Z`, or empty if the file is gone.
+fn format_metadata(path: &Path) -> String {
+    let Some(meta) = FileMetadata::read(path) else {
+        return String::new();
+    };
+    let mut parts = vec![meta.mode];
+    if let (Some(uid), Some(gid)) = (meta.uid, meta.gid) {
+        parts.push(format!("{}:{}", uid, gid));
+    }
+    parts.extend(meta.modified);
+    parts.join(" ")
+}
+
+/// Single `key=value` line with the headline numbers, for `--summary-line`.
+/// The format is stable so scripts can parse it without JSON tooling.
+pub fn format_summary_line(
+    stats: &ScanStatistics,
+    duration: f64,
+    prefix_only: Option<usize>,
+) -> String {
+    let wasted = format_bytes(stats.total_wasted_space).replace(' ', "");
+    let mut line = format!(
+        "DUPFIND groups={} files={} wasted={} wasted_bytes={} duration={:.1}s",
+        stats.total_duplicate_groups,
+        stats.total_duplicate_files,
+        wasted,
+        stats.total_wasted_space,
+        duration
+    );
+    if let Some(bytes) = prefix_only {
+        line.push_str(&format!(" prefix_only={}", bytes));
+    }
+    line
+}
+
+/// Warning for results of `--prefix-only`, which compared `bytes` leading
+/// bytes of each file and nothing more.
+pub fn prefix_only_notice(bytes: usize) -> String {
+    format!(
+        "Probable duplicates: only size and the first {} were compared, contents are unverified",
+        format_bytes(bytes as u64)
+    )
+}
+
+/// Time spent in each phase and its share of the run, for `--profile`.
+pub fn format_timings(timings: &[(&str, Duration)], total: f64) -> String {
+    let mut out = "Phase timings:".bold().to_string();
+    for (phase, elapsed) in timings {
+        let secs = elapsed.as_secs_f64();
+        let share = if total > 0.0 {
+            secs / total * 100.0
+        } else {
+            0.0
+        };
+        out.push_str(&format!("\n  {:<11}{:>9.3}s {:>5.1}%", phase, secs, share));
+    }
+    out.push_str(&format!("\n  {:<11}{:>9.3}s", "total", total));
+    out
+}
+
+/// One group confirmed during hashing, for `--stream`.
+pub fn format_streamed_group(group: &DuplicateGroup) -> String {
+    let mut out = format!(
+        "{} {} {} {}",
+        "Duplicate:".yellow().bold(),
+        format_bytes(group.size).white(),
+        "×".dimmed(),
+        format!("{} files", group.files.len()).white()
+    );
+    for file in &group.files {
+        out.push_str(&format!("\n  {}", file));
+    }
+    out
+}
+
+/// Groups that still have at least two files on disk, largest files first
+/// and then by hash, each listing its files by path. Reports of the same
+/// tree come out identical, so they can be diffed.
+fn existing_groups(hashes: &HashMap<String, Vec<PathBuf>>) -> Vec<DuplicateGroup> {
+    let mut groups: Vec<_> = hashes
+        .iter()
+        .filter_map(|(hash, files)| existing_group(hash, files))
         .collect();
+    groups.sort_by(|a, b| {
+        b.size
+            .cmp(&a.size)
+            .then_with(|| a.hash.cmp(&b.hash))
+            .then_with(|| a.files.cmp(&b.files))
+    });
+    groups
+}
+
+/// Build the serializable form of a scan, skipping groups that no longer
+/// have at least two files on disk.
+pub fn build_results(found: &Duplicates) -> ScanResults {
+    let stats = &found.stats;
+    let groups = existing_groups(&found.hashes);
 
-    let results = ScanResults {
+    ScanResults {
+        schema_version: SCHEMA_VERSION,
+        algorithm: found.algorithm,
         total_files_scanned: stats.total_files_scanned,
+        total_bytes_scanned: stats.total_bytes_scanned,
+        average_file_size: stats.average_file_size,
+        total_bytes_read: stats.total_bytes_read,
         total_size_groups: stats.total_size_groups,
         total_duplicate_groups: stats.total_duplicate_groups,
         total_duplicate_files: stats.total_duplicate_files,
         total_wasted_space: stats.total_wasted_space,
-        scan_duration_seconds: duration,
+        scan_duration_seconds: found.duration,
+        prefix_only: found.prefix_only,
         groups,
-    };
+        similar_groups: found
+            .similar
+            .iter()
+            .map(|files| SimilarGroup {
+                files: files.iter().map(|p| p.display().to_string()).collect(),
+            })
+            .collect(),
+        symlinks: found
+            .symlinks
+            .iter()
+            .map(|(link, target)| SymlinkEntry {
+                link: link.display().to_string(),
+                target: target.display().to_string(),
+            })
+            .collect(),
+        extension_breakdown: Vec::new(),
+        size_histogram: Vec::new(),
+        errors: found.errors.clone(),
+    }
+}
 
-    let json =
-        serde_json::to_string_pretty(&results).context("Failed to serialize results to JSON")?;
+/// Width of the longest bar printed by `print_size_histogram`.
+const HISTOGRAM_WIDTH: u64 = 30;
 
-    let mut file = OpenOptions::new()
-        .create(true)
-        .write(true)
-        .truncate(true)
-        .open(path)
-        .with_context(|| format!("Failed to open output file: {}", path.display()))?;
+/// Bars of wasted space per size bucket, for `--size-histogram`.
+pub fn print_size_histogram(histogram: &[SizeBucket]) {
+    if histogram.is_empty() {
+        return;
+    }
+    let largest = histogram.iter().map(|b| b.wasted_space).max().unwrap_or(0);
+    println!("{}", "By file size:".bold());
+    for bucket in histogram {
+        let width = (bucket.wasted_space * HISTOGRAM_WIDTH)
+            .checked_div(largest)
+            .unwrap_or(0);
+        println!(
+            "  {:<14} {:>6} groups {:>10}  {}",
+            bucket.label,
+            bucket.groups,
+            format_bytes(bucket.wasted_space),
+            "#".repeat(width as usize).red()
+        );
+    }
+    println!();
+}
 
-    file.write_all(json.as_bytes())
-        .context("Failed to write JSON output")?;
+/// Table of wasted space per file extension, for `--by-extension`.
+pub fn print_extension_breakdown(breakdown: &[ExtensionStats]) {
+    if breakdown.is_empty() {
+        return;
+    }
+    println!("{}", "By extension:".bold());
+    println!(
+        "  {}",
+        format!(
+            "{:<12} {:>8} {:>8} {:>12}",
+            "extension", "groups", "files", "wasted"
+        )
+        .dimmed()
+    );
+    for ext in breakdown {
+        println!(
+            "  {:<12} {:>8} {:>8} {}",
+            ext.extension,
+            ext.groups,
+            ext.files,
+            format!("{:>12}", format_bytes(ext.wasted_space)).red()
+        );
+    }
+    println!();
+}
+
+/// List the `--similar-images` groups, kept apart from identical copies.
+pub fn print_similar(groups: &[Vec<PathBuf>]) {
+    if groups.is_empty() {
+        return;
+    }
+    println!(
+        "{} {} {}",
+        "Found".bold(),
+        groups.len().to_string().yellow().bold(),
+        if groups.len() == 1 {
+            "group of similar images"
+        } else {
+            "groups of similar images"
+        }
+    );
+    println!();
+    for (idx, files) in groups.iter().enumerate() {
+        println!(
+            "{} {} {} {}",
+            format!("#{}", idx + 1).cyan().bold(),
+            "·".dimmed(),
+            "similar".magenta(),
+            format!("{} images", files.len()).white(),
+        );
+        for path in files {
+            let size = std::fs::metadata(path).map(|m| m.len()).unwrap_or(0);
+            println!(
+                "  {} {} {}",
+                "~".dimmed(),
+                format_path(path),
+                format!("({})", format_bytes(size)).dimmed()
+            );
+        }
+        println!();
+    }
+}
+
+/// List symlinks skipped under `--follow-links` for a missing target, for
+/// `--report-broken-links`.
+pub fn print_broken_links(links: &[PathBuf]) {
+    if links.is_empty() {
+        return;
+    }
+    println!(
+        "{} {} {}",
+        "Skipped".bold(),
+        links.len().to_string().yellow().bold(),
+        if links.len() == 1 {
+            "broken symlink"
+        } else {
+            "broken symlinks"
+        }
+    );
+    println!();
+    for link in links {
+        println!("  {}", format_path(link));
+    }
+    println!();
+}
+
+/// List symlinks that point at duplicated files, for `--report-symlinks`.
+pub fn print_symlinks(links: &[(PathBuf, PathBuf)]) {
+    if links.is_empty() {
+        return;
+    }
+    println!(
+        "{} {} {}",
+        "Found".bold(),
+        links.len().to_string().yellow().bold(),
+        if links.len() == 1 {
+            "symlink to a duplicated file"
+        } else {
+            "symlinks to duplicated files"
+        }
+    );
+    println!();
+    for (link, target) in links {
+        println!(
+            "  {} {} {}",
+            format_path(link),
+            "->".dimmed(),
+            format_path(target)
+        );
+    }
+    println!();
+}
+
+/// JSON Schema of the `--output-json` format, tagged with `SCHEMA_VERSION`.
+pub fn results_schema() -> schemars::Schema {
+    let mut schema = schemars::schema_for!(ScanResults);
+    schema.insert("version".to_string(), SCHEMA_VERSION.into());
+    schema
+}
+
+/// List files that could not be read, so permissions can be fixed before a rerun.
+pub fn print_errors(errors: &[ScanError]) {
+    if errors.is_empty() {
+        return;
+    }
+
+    println!(
+        "{} {}",
+        format!("Could not read {} files:", errors.len())
+            .red()
+            .bold(),
+        "(excluded from results)".dimmed()
+    );
+    for error in errors {
+        println!(
+            "  {} {}",
+            format_path(Path::new(&error.path)),
+            error.error.dimmed()
+        );
+    }
+    println!();
+}
+
+/// Read results written by `save_results_json` or `save_results_json_zst`,
+/// e.g. a `--baseline`. Compressed files are recognised by their content.
+pub fn load_results_json(path: &Path) -> Result<ScanResults, DupFindError> {
+    let file = File::open(path).map_err(|e| DupFindError::io(path, e))?;
+    let mut reader = BufReader::new(file);
+    let compressed = reader
+        .fill_buf()
+        .map_err(|e| DupFindError::io(path, e))?
+        .starts_with(&ZSTD_MAGIC);
+    if compressed {
+        let decoder = zstd::Decoder::with_buffer(reader).map_err(|e| DupFindError::io(path, e))?;
+        Ok(serde_json::from_reader(decoder)?)
+    } else {
+        Ok(serde_json::from_reader(reader)?)
+    }
+}
+
+pub fn save_results_json(path: &Path, results: &ScanResults) -> Result<(), DupFindError> {
+    write_atomically(path, |file| {
+        Ok(serde_json::to_writer_pretty(file, results)?)
+    })
+}
+
+/// Like `save_results_json`, but streamed through a zstd encoder at `level`
+/// (1-22), so large result sets never sit uncompressed in memory or on disk.
+pub fn save_results_json_zst(
+    path: &Path,
+    results: &ScanResults,
+    level: i32,
+) -> Result<(), DupFindError> {
+    write_atomically(path, |file| {
+        let mut encoder = zstd::Encoder::new(file, level).map_err(|e| DupFindError::io(path, e))?;
+        serde_json::to_writer_pretty(&mut encoder, results)?;
+        encoder.finish().map_err(|e| DupFindError::io(path, e))?;
+        Ok(())
+    })
+}
+
+/// Write the same JSON as `save_results_json` to stdout, for piping into other tools.
+pub fn print_results_json(results: &ScanResults) -> Result<()> {
+    let mut stdout = io::stdout().lock();
+    serde_json::to_writer_pretty(&mut stdout, results)
+        .context("Failed to serialize results to JSON")?;
+    writeln!(stdout).context("Failed to write JSON output")?;
+    Ok(())
+}
+
+/// Writes one compact JSON object per duplicate group, one per line, as groups
+/// are confirmed during the scan. Each line is flushed on its own, so readers
+/// can consume the file while it is being written and the full result set is
+/// never held in memory. Unlike the other reports it is written in place, not
+/// swapped in at the end.
+pub struct NdjsonWriter {
+    writer: BufWriter<File>,
+}
+
+impl NdjsonWriter {
+    pub fn create(path: &Path) -> Result<Self> {
+        let file = File::create(path)
+            .with_context(|| format!("Failed to create NDJSON file: {}", path.display()))?;
+        Ok(Self {
+            writer: BufWriter::new(file),
+        })
+    }
+
+    pub fn write(&mut self, group: &DuplicateGroup) -> Result<()> {
+        serde_json::to_writer(&mut self.writer, group).context("Failed to serialize group")?;
+        writeln!(self.writer).context("Failed to write NDJSON output")?;
+        self.writer.flush().context("Failed to write NDJSON output")
+    }
+}
+
+/// Quote a CSV field per RFC 4180 when it contains a delimiter, quote or newline.
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// Write one row per duplicate file. `is_original` marks the first path of
+/// each group in sorted order, only a stable reference copy: the copy an
+/// action keeps is chosen by `--keep`, `--prefer` and `--protect`, which a
+/// result file does not know. `probable` is true for every row with
+/// `prefix_only`, when contents were not compared in full.
+pub fn save_results_csv(
+    path: &Path,
+    hashes: &HashMap<String, Vec<PathBuf>>,
+    prefix_only: Option<usize>,
+) -> Result<()> {
+    write_atomically(path, |writer| write_csv(writer, hashes, prefix_only))
+}
+
+fn write_csv(
+    writer: &mut impl Write,
+    hashes: &HashMap<String, Vec<PathBuf>>,
+    prefix_only: Option<usize>,
+) -> Result<()> {
+    writeln!(writer, "group_id,hash,size,path,is_original,probable")
+        .context("Failed to write CSV output")?;
+    for (idx, group) in existing_groups(hashes).iter().enumerate() {
+        for (i, file) in group.files.iter().enumerate() {
+            writeln!(
+                writer,
+                "{},{},{},{},{},{}",
+                idx + 1,
+                group.hash,
+                group.size,
+                csv_field(file),
+                i == 0,
+                prefix_only.is_some()
+            )
+            .context("Failed to write CSV output")?;
+        }
+    }
+    writer.flush().context("Failed to write CSV output")?;
 
     Ok(())
 }
+
+/// Write `HASH  PATH` lines as `sha256sum`/`b3sum` do, so `b3sum -c` and
+/// friends can check them. Like coreutils, a path containing a backslash or
+/// newline is escaped and its line starts with `\`.
+pub fn save_checksums(path: &Path, checksums: &[(PathBuf, String)]) -> Result<()> {
+    write_atomically(path, |writer| write_checksums(writer, checksums))
+}
+
+fn write_checksums(writer: &mut impl Write, checksums: &[(PathBuf, String)]) -> Result<()> {
+    for (file, hash) in checksums {
+        let name = file.to_string_lossy();
+        if name.contains(['\\', '\n']) {
+            let escaped = name.replace('\\', "\\\\").replace('\n', "\\n");
+            writeln!(writer, "\\{}  {}", hash, escaped)
+        } else {
+            writeln!(writer, "{}  {}", hash, name)
+        }
+        .context("Failed to write checksums")?;
+    }
+    writer.flush().context("Failed to write checksums")?;
+    Ok(())
+}
+
+/// Page for `--output-html`. Placeholders in `{{...}}` are filled in by
+/// `save_results_html`; the script only sorts the groups already on the page.
+const HTML_TEMPLATE: &str = r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta charset="utf-8">
+<title>dupfind report</title>
+<style>
+body { font-family: system-ui, sans-serif; margin: 2em; color: #222; }
+.totals { color: #555; margin-bottom: 1em; }
+.notice { color: #b60; font-weight: bold; }
+.sort button { margin-right: .3em; }
+details { border: 1px solid #ddd; border-radius: 4px; margin: .4em 0; padding: .3em .6em; }
+summary { cursor: pointer; }
+summary .size { font-weight: bold; }
+summary .wasted { color: #b30; }
+table { border-collapse: collapse; margin: .4em 0 .2em 1.2em; }
+td { padding: .1em .6em; font-family: monospace; }
+td.kept { color: #070; }
+</style>
+</head>
+<body>
+<h1>Duplicate files</h1>
+<p class="totals">{{totals}}</p>
+{{notice}}
+<p class="sort">Sort by:
+<button data-key="wasted">wasted</button><button data-key="size">size</button><button data-key="count">copies</button><button data-key="path">path</button>
+</p>
+<div id="groups">
+{{groups}}</div>
+<script>
+document.querySelectorAll(".sort button").forEach(button => {
+  button.addEventListener("click", () => {
+    const key = button.dataset.key;
+    const list = document.getElementById("groups");
+    const groups = Array.from(list.children);
+    groups.sort((a, b) => key === "path"
+      ? a.dataset.path.localeCompare(b.dataset.path)
+      : Number(b.dataset[key]) - Number(a.dataset[key]));
+    groups.forEach(group => list.appendChild(group));
+  });
+});
+</script>
+</body>
+</html>
+"#;
+
+fn html_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// `file://` URL for a path, percent-encoding everything but unreserved characters.
+fn file_url(path: &Path) -> String {
+    let absolute = std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+    let mut url = String::from("file://");
+    if !absolute.to_string_lossy().starts_with('/') {
+        url.push('/');
+    }
+    for byte in absolute.to_string_lossy().replace('\\', "/").bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' | b':' => {
+                url.push(byte as char)
+            }
+            _ => url.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    url
+}
+
+/// Write a self-contained HTML page with one collapsible section per group,
+/// largest waste first, under a warning when only prefixes were compared.
+pub fn save_results_html(
+    path: &Path,
+    stats: &ScanStatistics,
+    hashes: &HashMap<String, Vec<PathBuf>>,
+    prefix_only: Option<usize>,
+) -> Result<()> {
+    let mut groups = existing_groups(hashes);
+    groups.sort_by(|a, b| {
+        let wasted = |g: &DuplicateGroup| g.size * (g.files.len() as u64 - 1);
+        wasted(b)
+            .cmp(&wasted(a))
+            .then_with(|| a.files.cmp(&b.files))
+    });
+
+    let mut sections = String::new();
+    for group in &groups {
+        let wasted = group.size * (group.files.len() as u64 - 1);
+        sections.push_str(&format!(
+            "<details data-wasted=\"{}\" data-size=\"{}\" data-count=\"{}\" data-path=\"{}\">\n\
+             <summary><span class=\"size\">{}</span> × {} files, <span class=\"wasted\">{} wasted</span> — {}</summary>\n<table>\n",
+            wasted,
+            group.size,
+            group.files.len(),
+            html_escape(&group.files[0]),
+            format_bytes(group.size),
+            group.files.len(),
+            format_bytes(wasted),
+            html_escape(&group.files[0]),
+        ));
+        for (i, file) in group.files.iter().enumerate() {
+            let class = if i == 0 { " class=\"kept\"" } else { "" };
+            sections.push_str(&format!(
+                "<tr><td{}><a href=\"{}\">{}</a></td></tr>\n",
+                class,
+                html_escape(&file_url(Path::new(file))),
+                html_escape(file)
+            ));
+        }
+        sections.push_str("</table>\n</details>\n");
+    }
+
+    let totals = format!(
+        "{} files scanned, {} duplicate groups, {} duplicate files, {} wasted",
+        stats.total_files_scanned,
+        stats.total_duplicate_groups,
+        stats.total_duplicate_files,
+        format_bytes(stats.total_wasted_space)
+    );
+    let notice = prefix_only
+        .map(|bytes| format!("<p class=\"notice\">{}</p>", prefix_only_notice(bytes)))
+        .unwrap_or_default();
+    let html = HTML_TEMPLATE
+        .replace("{{totals}}", &totals)
+        .replace("{{notice}}", &notice)
+        .replace("{{groups}}", &sections);
+    write_atomically(path, |writer| {
+        writer
+            .write_all(html.as_bytes())
+            .with_context(|| format!("Failed to write HTML output: {}", path.display()))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::statistics::calculate_statistics;
+    use crate::utils::scan_tempdir;
+
+    #[test]
+    fn test_csv_field_quoting() {
+        assert_eq!(csv_field("/plain/path.txt"), "/plain/path.txt");
+        assert_eq!(csv_field("/a,b/c.txt"), "\"/a,b/c.txt\"");
+        assert_eq!(csv_field("say \"hi\".txt"), "\"say \"\"hi\"\".txt\"");
+    }
+
+    #[test]
+    fn test_csv_marks_probable_rows() {
+        let dir = tempfile::tempdir().unwrap();
+        let paths: Vec<_> = ["a", "b"]
+            .iter()
+            .map(|name| {
+                let p = dir.path().join(name);
+                std::fs::write(&p, "same").unwrap();
+                p
+            })
+            .collect();
+        let hashes = HashMap::from([("h".to_string(), paths)]);
+        let csv = |prefix_only| {
+            let mut out = Vec::new();
+            write_csv(&mut out, &hashes, prefix_only).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+
+        let verified = csv(None);
+        assert!(verified.starts_with("group_id,hash,size,path,is_original,probable\n"));
+        assert!(verified.lines().skip(1).all(|row| row.ends_with(",false")));
+        let probable = csv(Some(4096));
+        assert!(probable.lines().skip(1).all(|row| row.ends_with(",true")));
+    }
+
+    #[test]
+    fn test_sort_groups() {
+        let group = |size: u64, names: &[&str]| {
+            let files = names.iter().map(PathBuf::from).collect();
+            (String::new(), files, size)
+        };
+        let mut groups = vec![
+            group(100, &["/c", "/c2"]),
+            group(10, &["/a", "/a2", "/a3", "/a4"]),
+            group(40, &["/b", "/b2", "/b3"]),
+        ];
+        let firsts = |groups: &[(String, Vec<PathBuf>, u64)]| {
+            groups.iter().map(|g| g.1[0].clone()).collect::<Vec<_>>()
+        };
+
+        sort_groups(&mut groups, SortBy::Wasted, false);
+        assert_eq!(firsts(&groups), ["/c", "/b", "/a"].map(PathBuf::from));
+        // Same key as before --sort-by existed: 100 B × 2 ahead of 60 B × 3.
+        let mut pair_first = vec![group(60, &["/t", "/t2", "/t3"]), group(100, &["/p", "/p2"])];
+        sort_groups(&mut pair_first, SortBy::Wasted, false);
+        assert_eq!(firsts(&pair_first), ["/p", "/t"].map(PathBuf::from));
+        sort_groups(&mut groups, SortBy::Count, false);
+        assert_eq!(firsts(&groups), ["/a", "/b", "/c"].map(PathBuf::from));
+        sort_groups(&mut groups, SortBy::Path, true);
+        assert_eq!(firsts(&groups), ["/c", "/b", "/a"].map(PathBuf::from));
+    }
+
+    #[test]
+    fn test_cluster_by_dir() {
+        let group = |size: u64, names: &[&str]| {
+            let files = names.iter().map(PathBuf::from).collect();
+            (String::new(), files, size)
+        };
+        let groups = vec![
+            group(100, &["/a/x", "/b/x"]),
+            group(10, &["/c/y", "/c/z"]),
+            group(5, &["/b/y", "/a/y"]),
+        ];
+        let clusters = cluster_by_dir(&groups);
+        assert_eq!(clusters.len(), 2);
+        assert_eq!(clusters[0].dirs, ["/a", "/b"].map(PathBuf::from));
+        assert_eq!(clusters[0].groups, [0, 2]);
+        assert_eq!(clusters[0].wasted, 105);
+        assert_eq!(clusters[1].dirs, [PathBuf::from("/c")]);
+    }
+
+    #[test]
+    fn test_ndjson_one_group_per_line() {
+        let dir = tempfile::tempdir().unwrap();
+        let paths: Vec<_> = ["a", "b", "c", "d"]
+            .iter()
+            .map(|name| {
+                let p = dir.path().join(name);
+                std::fs::write(&p, "same").unwrap();
+                p
+            })
+            .collect();
+        let out = dir.path().join("out.ndjson");
+
+        let mut writer = NdjsonWriter::create(&out).unwrap();
+        writer
+            .write(&existing_group("h1", &paths[..2]).unwrap())
+            .unwrap();
+        // Already on disk before the writer is done.
+        assert_eq!(std::fs::read_to_string(&out).unwrap().lines().count(), 1);
+        writer
+            .write(&existing_group("h2", &paths[2..]).unwrap())
+            .unwrap();
+        drop(writer);
+
+        let text = std::fs::read_to_string(&out).unwrap();
+        let groups: Vec<DuplicateGroup> = text
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(groups.len(), 2);
+        assert!(groups.iter().all(|g| g.files.len() == 2 && g.size == 4));
+    }
+
+    #[test]
+    fn test_results_schema_is_versioned() {
+        let schema = serde_json::to_value(results_schema()).unwrap();
+        assert_eq!(schema["version"], SCHEMA_VERSION);
+        assert!(schema["properties"]["groups"].is_object());
+        assert!(schema["properties"]["schema_version"].is_object());
+    }
+
+    #[test]
+    fn test_summary_line_format() {
+        let stats = ScanStatistics {
+            total_files_scanned: 1000,
+            total_bytes_scanned: 0,
+            average_file_size: 0,
+            total_size_groups: 200,
+            total_duplicate_groups: 123,
+            total_duplicate_files: 456,
+            total_wasted_space: 7_800_000_000,
+            total_bytes_read: 0,
+            quick_hash: Default::default(),
+        };
+        assert_eq!(
+            format_summary_line(&stats, 12.34, None),
+            "DUPFIND groups=123 files=456 wasted=7.80GB wasted_bytes=7800000000 duration=12.3s"
+        );
+        assert!(format_summary_line(&stats, 12.34, Some(4096)).ends_with(" prefix_only=4096"));
+    }
+
+    #[test]
+    fn test_html_report_escapes_and_links() {
+        let dir = tempfile::tempdir().unwrap();
+        let a = dir.path().join("a <b>.txt");
+        let b = dir.path().join("c.txt");
+        std::fs::write(&a, "same").unwrap();
+        std::fs::write(&b, "same").unwrap();
+        let hashes = HashMap::from([("h".to_string(), vec![a.clone(), b])]);
+        let report = dir.path().join("report.html");
+
+        save_results_html(
+            &report,
+            &calculate_statistics(&hashes, 2, 8, 1).unwrap(),
+            &hashes,
+            None,
+        )
+        .unwrap();
+        let html = std::fs::read_to_string(&report).unwrap();
+        assert!(html.contains("a &lt;b&gt;.txt"));
+        assert!(html.contains("a%20%3Cb%3E.txt"));
+        assert_eq!(html.matches("<details").count(), 1);
+        assert!(!html.contains("{{"));
+        assert!(!html.contains("Probable duplicates"));
+
+        save_results_html(
+            &report,
+            &calculate_statistics(&hashes, 2, 8, 1).unwrap(),
+            &hashes,
+            Some(4096),
+        )
+        .unwrap();
+        let html = std::fs::read_to_string(&report).unwrap();
+        assert!(html.contains("<p class=\"notice\">Probable duplicates"));
+    }
+
+    #[test]
+    fn test_checksums_escape_like_coreutils() {
+        let dir = tempfile::tempdir().unwrap();
+        let sums = dir.path().join("sums");
+        let checksums = vec![
+            (PathBuf::from("plain.txt"), "aa".to_string()),
+            (PathBuf::from("new\nline"), "bb".to_string()),
+        ];
+        save_checksums(&sums, &checksums).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&sums).unwrap(),
+            "aa  plain.txt\n\\bb  new\\nline\n"
+        );
+    }
+
+    #[test]
+    fn test_format_timings() {
+        let timings = [
+            ("scan", Duration::from_millis(500)),
+            ("hash", Duration::from_millis(1500)),
+        ];
+        let text = format_timings(&timings, 2.0);
+        assert_eq!(
+            text.lines().collect::<Vec<_>>(),
+            [
+                "Phase timings:",
+                "  scan           0.500s  25.0%",
+                "  hash           1.500s  75.0%",
+                "  total          2.000s",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_units() {
+        assert_eq!(Units::Decimal.format(1_500), "1.50 kB");
+        assert_eq!(Units::Binary.format(1_536), "1.50 KiB");
+    }
+
+    #[test]
+    fn test_hyperlink_override() {
+        assert!(Hyperlinks::Always.enabled());
+        assert!(!Hyperlinks::Never.enabled());
+    }
+
+    #[test]
+    fn test_zstd_results_round_trip() {
+        let dir = scan_tempdir();
+        std::fs::write(dir.path().join("a"), "same").unwrap();
+        std::fs::write(dir.path().join("b"), "same").unwrap();
+        let results = build_results(&crate::DupFinder::new(dir.path()).find().unwrap());
+        let out = dir.path().join("results.json.zst");
+
+        assert!(is_zstd_path(&out));
+        save_results_json_zst(&out, &results, DEFAULT_ZSTD_LEVEL).unwrap();
+        assert!(std::fs::read(&out).unwrap().starts_with(&ZSTD_MAGIC));
+        let loaded = load_results_json(&out).unwrap();
+        assert_eq!(loaded.groups.len(), 1);
+        assert_eq!(loaded.groups[0].files.len(), 2);
+    }
+
+    #[test]
+    fn test_json_groups_are_stably_ordered() {
+        let dir = scan_tempdir();
+        for i in 0..20 {
+            let content = "x".repeat(i + 1);
+            for copy in ["c", "a", "b"] {
+                std::fs::write(dir.path().join(format!("{copy}{i}")), &content).unwrap();
+            }
+        }
+
+        let groups = || {
+            let results = build_results(&crate::DupFinder::new(dir.path()).find().unwrap());
+            serde_json::to_string(&results.groups).unwrap()
+        };
+        let first = groups();
+        assert_eq!(groups(), first);
+
+        let results = build_results(&crate::DupFinder::new(dir.path()).find().unwrap());
+        assert_eq!(results.groups[0].size, 20);
+        assert!(results.groups.windows(2).all(|w| w[0].size > w[1].size));
+        assert!(results.groups[0].files.is_sorted());
+    }
+}
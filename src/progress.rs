@@ -0,0 +1,27 @@
+/// Pipeline phases, in the order they run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    Scan,
+    Group,
+    Hash,
+    /// Perceptual hashing for `--similar-images`.
+    Images,
+}
+
+/// Receives progress updates from the scan pipeline.
+///
+/// Called from rayon worker threads, so implementations should be cheap.
+/// `total` is `None` while the amount of work is unknown (during the walk).
+pub trait ProgressReporter: Send + Sync {
+    fn on_progress(&self, phase: Phase, done: u64, total: Option<u64>);
+
+    /// Total bytes read by the hashing phase so far, for throughput display.
+    fn on_bytes_hashed(&self, _bytes: u64) {}
+}
+
+/// Discards all progress updates.
+pub struct NoProgress;
+
+impl ProgressReporter for NoProgress {
+    fn on_progress(&self, _phase: Phase, _done: u64, _total: Option<u64>) {}
+}
@@ -1,19 +1,27 @@
-use anyhow::{Result, bail};
-use indicatif::ProgressBar;
+use anyhow::{Context, Result};
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use ignore::{DirEntry, WalkBuilder, WalkState};
 use log::warn;
 use rayon::prelude::*;
-use std::collections::{HashMap, HashSet};
-use std::path::Path;
-use std::sync::Arc;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader};
+use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicU64, Ordering};
-use walkdir::WalkDir;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
 
-use crate::utils::INTERRUPTED;
+use crate::error::DupFindError;
+use crate::progress::{Phase, ProgressReporter};
+use crate::statistics::split_group_id;
+use crate::utils::{INTERRUPTED, devices, file_id};
 
 /// Check if file/directory has system "hidden" flag.
 /// - macOS: BSD `UF_HIDDEN` flag (e.g., ~/Library)
 /// - Windows: `FILE_ATTRIBUTE_HIDDEN` or `FILE_ATTRIBUTE_SYSTEM`
-/// - Linux: no system hidden flags, only dotfiles
+/// - Linux: no system hidden flags, only dotfiles (see `is_immutable` and
+///   `ScanOptions::hidden_predicate` for opting into more)
 #[cfg(target_os = "macos")]
 fn has_hidden_flag(path: &Path) -> bool {
     use std::ffi::CString;
@@ -37,18 +45,18 @@ fn has_hidden_flag(path: &Path) -> bool {
 }
 
 #[cfg(target_os = "windows")]
-fn has_hidden_flag(path: &Path) -> bool {
+fn has_hidden_attributes(metadata: &fs::Metadata) -> bool {
     use std::os::windows::fs::MetadataExt;
 
     const FILE_ATTRIBUTE_HIDDEN: u32 = 0x2;
     const FILE_ATTRIBUTE_SYSTEM: u32 = 0x4;
 
-    if let Ok(meta) = std::fs::metadata(path) {
-        let attrs = meta.file_attributes();
-        (attrs & FILE_ATTRIBUTE_HIDDEN != 0) || (attrs & FILE_ATTRIBUTE_SYSTEM != 0)
-    } else {
-        false
-    }
+    metadata.file_attributes() & (FILE_ATTRIBUTE_HIDDEN | FILE_ATTRIBUTE_SYSTEM) != 0
+}
+
+#[cfg(target_os = "windows")]
+fn has_hidden_flag(path: &Path) -> bool {
+    fs::symlink_metadata(path).is_ok_and(|m| has_hidden_attributes(&m))
 }
 
 #[cfg(not(any(target_os = "macos", target_os = "windows")))]
@@ -56,142 +64,1152 @@ fn has_hidden_flag(_path: &Path) -> bool {
     false
 }
 
-pub fn scan_files(
-    dir: &Path,
-    follow_links: bool,
-    min_size: u64,
-    extra_ignore: &[String],
-    include_hidden: bool,
-    progress: &ProgressBar,
-) -> Result<Vec<walkdir::DirEntry>> {
-    let ignored: HashSet<&str> = extra_ignore.iter().map(|s| s.as_str()).collect();
+/// Whether the file carries the Linux immutable attribute (`chattr +i`),
+/// usually system files not meant to be touched. Always false elsewhere.
+/// It costs an open per entry, so it only runs as a `hidden_predicate`.
+#[cfg(target_os = "linux")]
+pub fn is_immutable(path: &Path) -> bool {
+    use std::os::fd::AsRawFd;
+    use std::os::unix::fs::OpenOptionsExt;
 
-    // Check if root directory itself is hidden
-    if !include_hidden {
-        if let Some(name) = dir.file_name().and_then(|n| n.to_str())
-            && name.starts_with('.')
+    const FS_IMMUTABLE_FL: libc::c_long = 0x10;
+
+    // Non-blocking so FIFOs and devices never stall the walk.
+    let Ok(file) = fs::OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_NONBLOCK | libc::O_NOFOLLOW | libc::O_NOCTTY)
+        .open(path)
+    else {
+        return false;
+    };
+    let mut flags: libc::c_long = 0;
+    let result = unsafe { libc::ioctl(file.as_raw_fd(), libc::FS_IOC_GETFLAGS, &mut flags) };
+    result == 0 && flags & FS_IMMUTABLE_FL != 0
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn is_immutable(_path: &Path) -> bool {
+    false
+}
+
+/// Extra test for entries to treat as system-hidden, on top of the
+/// platform flags, e.g. `is_immutable`. Such entries are skipped unless
+/// `include_system_hidden` is set.
+#[derive(Clone)]
+pub struct HiddenPredicate(Arc<dyn Fn(&Path) -> bool + Send + Sync>);
+
+impl HiddenPredicate {
+    pub fn new(predicate: impl Fn(&Path) -> bool + Send + Sync + 'static) -> Self {
+        Self(Arc::new(predicate))
+    }
+
+    pub fn matches(&self, path: &Path) -> bool {
+        (self.0)(path)
+    }
+}
+
+impl fmt::Debug for HiddenPredicate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("HiddenPredicate(..)")
+    }
+}
+
+/// Hidden-flag check for a walked entry. On Windows the walker already holds
+/// the entry's attributes from the directory listing, so no extra stat is
+/// needed; elsewhere this falls back to `has_hidden_flag`.
+#[cfg(target_os = "windows")]
+fn entry_has_hidden_flag(entry: &DirEntry) -> bool {
+    match entry.metadata() {
+        Ok(metadata) => has_hidden_attributes(&metadata),
+        Err(_) => has_hidden_flag(entry.path()),
+    }
+}
+
+#[cfg(not(target_os = "windows"))]
+fn entry_has_hidden_flag(entry: &DirEntry) -> bool {
+    has_hidden_flag(entry.path())
+}
+
+/// Per-directory ignore file read in addition to `.gitignore` and `.ignore`.
+pub const IGNORE_FILENAME: &str = ".dupfindignore";
+
+/// Filters applied while walking the tree.
+#[derive(Debug, Clone, Default)]
+pub struct ScanOptions {
+    pub follow_links: bool,
+    /// Without `follow_links`, still pick up symlinks to regular files so
+    /// they can be reported; see `set_aside_symlinks`
+    pub report_symlinks: bool,
+    /// Do not descend into directories on a different filesystem than the root
+    pub one_filesystem: bool,
+    /// Skip files smaller than this many bytes
+    pub min_size: u64,
+    /// Skip files larger than this many bytes
+    pub max_size: Option<u64>,
+    /// Directory names to skip anywhere in the tree, or exact paths/globs if they contain a separator
+    pub ignore: Vec<String>,
+    /// Scan files and directories whose name starts with '.'
+    pub include_dotfiles: bool,
+    /// Scan entries flagged hidden by the OS (macOS `UF_HIDDEN`, Windows hidden/system)
+    pub include_system_hidden: bool,
+    /// Also treat entries this predicate matches as system-hidden
+    pub hidden_predicate: Option<HiddenPredicate>,
+    /// Do not read `.gitignore` files (`.ignore` and `.dupfindignore` still apply)
+    pub no_gitignore: bool,
+    /// Only scan files whose path relative to the root matches one of these globs
+    pub include: Vec<String>,
+    /// Never scan files whose path relative to the root matches one of these globs
+    pub exclude: Vec<String>,
+    /// Skip files with these extensions, given in lowercase without the dot
+    pub skip_extensions: Vec<String>,
+    /// Deepest directory level to descend into; 0 means only files directly in the root
+    pub max_depth: Option<usize>,
+    /// Skip files less than this many directories below the root
+    pub min_depth: usize,
+    /// Skip files last modified before this time
+    pub modified_after: Option<SystemTime>,
+    /// Skip files last modified after this time
+    pub modified_before: Option<SystemTime>,
+}
+
+/// Include/exclude globs compiled once before the walk. Exclude wins when
+/// both match; an empty include list admits everything.
+struct PathFilter {
+    include: Option<GlobSet>,
+    exclude: GlobSet,
+}
+
+impl PathFilter {
+    fn new(include: &[String], exclude: &[String]) -> Result<Self> {
+        let include = if include.is_empty() {
+            None
+        } else {
+            Some(build_globset(include)?)
+        };
+        Ok(Self {
+            include,
+            exclude: build_globset(exclude)?,
+        })
+    }
+
+    fn matches(&self, relative: &Path) -> bool {
+        if self.exclude.is_match(relative) {
+            return false;
+        }
+        self.include
+            .as_ref()
+            .is_none_or(|set| set.is_match(relative))
+    }
+}
+
+/// Whether a file's mtime falls inside the configured window. Files whose
+/// mtime cannot be read are kept rather than silently dropped.
+fn within_mtime_window(path: &Path, metadata: &fs::Metadata, options: &ScanOptions) -> bool {
+    if options.modified_after.is_none() && options.modified_before.is_none() {
+        return true;
+    }
+    let mtime = match metadata.modified() {
+        Ok(t) => t,
+        Err(e) => {
+            warn!(
+                "Cannot read modification time of {}, including it: {}",
+                path.display(),
+                e
+            );
+            return true;
+        }
+    };
+    options.modified_after.is_none_or(|after| mtime >= after)
+        && options.modified_before.is_none_or(|before| mtime <= before)
+}
+
+/// `--ignore` entries. Bare names skip directories with that name anywhere;
+/// entries containing a path separator skip exactly that path (resolved
+/// against the working directory), and may use glob syntax.
+struct IgnoreRules {
+    names: HashSet<String>,
+    paths: HashSet<PathBuf>,
+    globs: GlobSet,
+    has_path_rules: bool,
+}
+
+impl IgnoreRules {
+    fn new(entries: &[String]) -> Result<Self> {
+        let mut names = HashSet::new();
+        let mut paths = HashSet::new();
+        let mut globs = Vec::new();
+        for entry in entries {
+            if !entry.contains(['/', std::path::MAIN_SEPARATOR]) {
+                names.insert(entry.clone());
+                continue;
+            }
+            let absolute = std::path::absolute(entry)
+                .with_context(|| format!("Invalid ignore path: {}", entry))?;
+            if entry.contains(['*', '?', '[', '{']) {
+                globs.push(absolute.to_string_lossy().into_owned());
+            } else {
+                paths.insert(absolute);
+            }
+        }
+        Ok(Self {
+            has_path_rules: !paths.is_empty() || !globs.is_empty(),
+            names,
+            paths,
+            globs: build_globset(&globs)?,
+        })
+    }
+
+    fn matches(&self, entry: &DirEntry) -> bool {
+        if entry.file_type().is_some_and(|t| t.is_dir())
+            && entry
+                .file_name()
+                .to_str()
+                .is_some_and(|name| self.names.contains(name))
         {
-            return Ok(Vec::new());
+            return true;
         }
-        if has_hidden_flag(dir) {
-            return Ok(Vec::new());
+        if !self.has_path_rules {
+            return false;
         }
+        std::path::absolute(entry.path())
+            .is_ok_and(|path| self.paths.contains(&path) || self.globs.is_match(&path))
     }
+}
+
+fn build_globset(patterns: &[String]) -> Result<GlobSet> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        let glob =
+            Glob::new(pattern).with_context(|| format!("Invalid glob pattern: {}", pattern))?;
+        builder.add(glob);
+    }
+    Ok(builder.build()?)
+}
+
+/// A file accepted by the scan, with its size as seen at scan time.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScannedFile {
+    pub path: PathBuf,
+    pub size: u64,
+    /// Picked up as a symlink by `report_symlinks`; `size` is the target's.
+    pub symlink: bool,
+}
+
+/// A symlink found by `report_symlinks`, with the scanned path that stands
+/// for its data: a scanned file with the same data, or the link itself when
+/// the target lies outside the scan.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LinkedFile {
+    pub link: PathBuf,
+    pub data: PathBuf,
+}
+
+/// Symlinks whose data is one of the duplicates, as (link, target). A link
+/// hashed in place of its target is reported with the resolved target.
+pub fn linked_duplicates(
+    hashes: &HashMap<String, Vec<PathBuf>>,
+    links: &[LinkedFile],
+) -> Vec<(PathBuf, PathBuf)> {
+    let duplicated: HashSet<&Path> = hashes.values().flatten().map(PathBuf::as_path).collect();
+    links
+        .iter()
+        .filter(|l| duplicated.contains(l.data.as_path()))
+        .map(|l| {
+            let target = if l.data == l.link {
+                fs::canonicalize(&l.link).unwrap_or_else(|_| l.link.clone())
+            } else {
+                l.data.clone()
+            };
+            (l.link.clone(), target)
+        })
+        .collect()
+}
+
+/// Take symlinks out of `files` where their target was scanned too, so a link
+/// and its target are not hashed, or reported, as two copies. A link to a file
+/// outside the scan stays in `files` in place of its target, once per target.
+pub fn set_aside_symlinks(files: &mut Vec<ScannedFile>) -> Vec<LinkedFile> {
+    let mut owners: HashMap<FileKey, PathBuf> = files
+        .iter()
+        .filter(|f| !f.symlink)
+        .filter_map(|f| file_key(&f.path).map(|key| (key, f.path.clone())))
+        .collect();
+    let mut links = Vec::new();
+    files.retain(|f| {
+        if !f.symlink {
+            return true;
+        }
+        let Some(key) = file_key(&f.path) else {
+            return false;
+        };
+        let data = owners.entry(key).or_insert_with(|| f.path.clone()).clone();
+        let keep = data == f.path;
+        links.push(LinkedFile {
+            link: f.path.clone(),
+            data,
+        });
+        keep
+    });
+    links
+}
 
-    let mut walker = WalkDir::new(dir);
-    if !follow_links {
-        walker = walker.follow_links(false);
+/// Identity of a file's underlying data: device and inode where the platform
+/// provides them, otherwise the canonical path.
+#[derive(PartialEq, Eq, Hash)]
+enum FileKey {
+    Id(u64, u64),
+    Path(PathBuf),
+}
+
+fn file_key(path: &Path) -> Option<FileKey> {
+    match file_id(path) {
+        Some((dev, ino)) => Some(FileKey::Id(dev, ino)),
+        None => fs::canonicalize(path).ok().map(FileKey::Path),
     }
+}
+
+/// Files found by `scan_files`, and the dangling symlinks met on the way
+/// with `follow_links`, both sorted by path.
+#[derive(Debug, Default)]
+pub struct ScannedTree {
+    pub files: Vec<ScannedFile>,
+    pub broken_links: Vec<PathBuf>,
+}
 
+pub fn scan_files(
+    roots: &[PathBuf],
+    options: &ScanOptions,
+    progress: &dyn ProgressReporter,
+) -> Result<ScannedTree> {
+    scan_files_with(roots, options, progress, &|_| {})
+}
+
+/// `scan_files`, also handing each file to `sink` as soon as the walk accepts
+/// it, from the walker's threads, so work on it can start before the walk is
+/// over. With `follow_links`, a file reached twice is handed over twice.
+pub fn scan_files_with(
+    roots: &[PathBuf],
+    options: &ScanOptions,
+    progress: &dyn ProgressReporter,
+    sink: &(dyn Fn(&ScannedFile) + Sync),
+) -> Result<ScannedTree> {
+    let ignored = Arc::new(IgnoreRules::new(&options.ignore)?);
+    let filter = PathFilter::new(&options.include, &options.exclude)?;
+    let walked = Walked {
+        scanned: AtomicU64::new(0),
+        broken: Mutex::new(Vec::new()),
+        sink,
+    };
     let mut files = Vec::new();
-    let mut scanned = 0u64;
 
-    let iter = walker.into_iter().filter_entry(|e| {
-        if let Some(name) = e.file_name().to_str() {
-            // Skip dotfiles unless --hidden
-            if !include_hidden && name.starts_with('.') && name != "." {
+    for dir in roots {
+        let mut found = scan_root(dir, options, &ignored, &filter, progress, &walked)?;
+        // The walk runs on several threads, so sort for a stable order and a
+        // stable choice of which path to a linked file is kept.
+        found.sort_by(|a, b| a.path.cmp(&b.path));
+        files.append(&mut found);
+    }
+    if options.follow_links {
+        drop_aliases(&mut files);
+    }
+    let total = files.len() as u64;
+    progress.on_progress(Phase::Scan, total, Some(total));
+
+    let mut broken_links = walked.broken.into_inner().unwrap();
+    broken_links.sort();
+    broken_links.dedup();
+    if !broken_links.is_empty() {
+        warn!("Skipped {} broken symlinks", broken_links.len());
+    }
+    Ok(ScannedTree {
+        files,
+        broken_links,
+    })
+}
+
+/// Drop every path but the first to a file that `follow_links` reached more
+/// than once through symlinks. Hard links are separate names for a file, not
+/// links followed, so they stay for `collapse_hardlinks` to handle as asked.
+/// Files are told apart by device and inode; canonical paths are only
+/// compared between files that share them.
+fn drop_aliases(files: &mut Vec<ScannedFile>) {
+    let mut first: HashMap<FileKey, usize> = HashMap::new();
+    // Canonical paths kept so far, for files reached more than once
+    let mut names: HashMap<usize, HashSet<PathBuf>> = HashMap::new();
+    let canonical = |path: &Path| fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    let mut keep = vec![true; files.len()];
+    for (i, file) in files.iter().enumerate() {
+        let Some(key) = file_key(&file.path) else {
+            continue;
+        };
+        let j = *first.entry(key).or_insert(i);
+        if j != i {
+            let kept = names
+                .entry(j)
+                .or_insert_with(|| HashSet::from([canonical(&files[j].path)]));
+            keep[i] = kept.insert(canonical(&file.path));
+        }
+    }
+    let mut keep = keep.into_iter();
+    files.retain(|_| keep.next().unwrap_or(true));
+}
+
+/// The symlink a walk error is about, if it is one whose target is missing.
+/// Only these reach the walker as errors, and only when following links.
+fn dangling_link(err: &ignore::Error) -> Option<&Path> {
+    match err {
+        ignore::Error::WithPath { path, .. } => {
+            let link = fs::symlink_metadata(path).is_ok_and(|m| m.file_type().is_symlink());
+            (link && !path.exists()).then_some(path.as_path())
+        }
+        ignore::Error::WithDepth { err, .. } => dangling_link(err),
+        _ => None,
+    }
+}
+
+/// Shared by the walks of every root.
+struct Walked<'a> {
+    scanned: AtomicU64,
+    broken: Mutex<Vec<PathBuf>>,
+    sink: &'a (dyn Fn(&ScannedFile) + Sync),
+}
+
+fn scan_root(
+    dir: &Path,
+    options: &ScanOptions,
+    ignored: &Arc<IgnoreRules>,
+    filter: &PathFilter,
+    progress: &dyn ProgressReporter,
+    walked: &Walked,
+) -> Result<Vec<ScannedFile>> {
+    let include_dotfiles = options.include_dotfiles;
+    let include_system_hidden = options.include_system_hidden;
+
+    // Check if root directory itself is hidden
+    if !include_dotfiles
+        && let Some(name) = dir.file_name().and_then(|n| n.to_str())
+        && name.starts_with('.')
+    {
+        return Ok(Vec::new());
+    }
+    let predicate = options.hidden_predicate.clone();
+    let system_hidden = move |path: &Path, flagged: bool| {
+        flagged || predicate.as_ref().is_some_and(|p| p.matches(path))
+    };
+    if !include_system_hidden && system_hidden(dir, has_hidden_flag(dir)) {
+        return Ok(Vec::new());
+    }
+
+    let use_gitignore = !options.no_gitignore;
+    let ignored = Arc::clone(ignored);
+    let walker = WalkBuilder::new(dir)
+        // A root that is a symlink to a directory (say /data -> /mnt/disk) is
+        // always entered; this only decides whether links inside it are.
+        .follow_links(options.follow_links)
+        .same_file_system(options.one_filesystem)
+        // Walk with as many threads as the hashing pool; stat latency, not
+        // CPU, is what limits the walk on network filesystems.
+        .threads(rayon::current_num_threads())
+        // The walker counts the root itself as depth 0, its files as depth 1
+        .max_depth(options.max_depth.map(|d| d + 1))
+        // Hidden entries are filtered below so that system-hidden flags apply too
+        .hidden(false)
+        .git_ignore(use_gitignore)
+        .git_global(use_gitignore)
+        .git_exclude(use_gitignore)
+        .require_git(false)
+        .add_custom_ignore_filename(IGNORE_FILENAME)
+        .filter_entry(move |e| {
+            // Skip ignored directories and paths, with or without --hidden
+            if ignored.matches(e) {
                 return false;
             }
-            // Skip ignored directories
-            if e.file_type().is_dir() && ignored.contains(name) {
-                return false;
+            // Skip dotfiles unless --hidden-dotfiles, and entries with a
+            // system hidden flag unless --hidden-system
+            let dotfile = e.file_name().to_str().is_some_and(|n| n.starts_with('.'));
+            (include_dotfiles || !dotfile)
+                && (include_system_hidden || !system_hidden(e.path(), entry_has_hidden_flag(e)))
+        })
+        .build_parallel();
+
+    let found = Mutex::new(Vec::new());
+    walker.run(|| {
+        let found = &found;
+        Box::new(move |entry| {
+            if INTERRUPTED.load(Ordering::Relaxed) {
+                return WalkState::Quit;
+            }
+            let entry = match entry {
+                Ok(e) => e,
+                Err(e) => {
+                    match dangling_link(&e) {
+                        Some(link) => walked.broken.lock().unwrap().push(link.to_path_buf()),
+                        None => warn!("Error reading directory entry: {}", e),
+                    }
+                    return WalkState::Continue;
+                }
+            };
+
+            if let Some(file) = accept_entry(entry, dir, options, filter) {
+                (walked.sink)(&file);
+                found.lock().unwrap().push(file);
+                let count = walked.scanned.fetch_add(1, Ordering::Relaxed) + 1;
+                if count.is_multiple_of(1000) {
+                    progress.on_progress(Phase::Scan, count, None);
+                }
             }
+            WalkState::Continue
+        })
+    });
+
+    if INTERRUPTED.load(Ordering::Relaxed) {
+        return Err(DupFindError::Interrupted.into());
+    }
+    Ok(found.into_inner().unwrap())
+}
+
+/// Apply the per-file filters to a walked entry.
+fn accept_entry(
+    entry: DirEntry,
+    root: &Path,
+    options: &ScanOptions,
+    filter: &PathFilter,
+) -> Option<ScannedFile> {
+    let symlink = !options.follow_links && entry.path_is_symlink();
+    if symlink && !options.report_symlinks {
+        return None;
+    }
+
+    if !symlink && !entry.file_type().is_some_and(|t| t.is_file()) {
+        return None;
+    }
+
+    if entry.depth() <= options.min_depth {
+        return None;
+    }
+
+    let relative = entry.path().strip_prefix(root).unwrap_or(entry.path());
+    if !filter.matches(relative) || has_skipped_extension(entry.path(), options) {
+        return None;
+    }
+
+    let metadata = if symlink {
+        // Dangling links and links to directories are simply not files.
+        match fs::metadata(entry.path()) {
+            Ok(m) if m.is_file() => m,
+            _ => return None,
         }
-        // Skip files/dirs with system hidden flag (macOS UF_HIDDEN)
-        if !include_hidden && has_hidden_flag(e.path()) {
-            return false;
+    } else {
+        match entry.metadata() {
+            Ok(m) => m,
+            Err(e) => {
+                warn!("Cannot read metadata for {}: {}", entry.path().display(), e);
+                return None;
+            }
         }
-        true
+    };
+
+    let size = metadata.len();
+    if size < options.min_size || options.max_size.is_some_and(|max| size > max) {
+        return None;
+    }
+    if !within_mtime_window(entry.path(), &metadata, options) {
+        return None;
+    }
+
+    Some(ScannedFile {
+        path: entry.into_path(),
+        size,
+        symlink,
+    })
+}
+
+/// Whether the extension of `path` is in `skip_extensions`, ignoring case.
+fn has_skipped_extension(path: &Path, options: &ScanOptions) -> bool {
+    !options.skip_extensions.is_empty()
+        && path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .is_some_and(|ext| options.skip_extensions.contains(&ext.to_lowercase()))
+}
+
+/// Index of the scan root a path was found under.
+pub fn root_index(path: &Path, roots: &[PathBuf]) -> Option<usize> {
+    roots.iter().position(|root| path.starts_with(root))
+}
+
+/// Keep only groups whose files were found under more than one root.
+pub fn retain_cross_root(hashes: &mut HashMap<String, Vec<PathBuf>>, roots: &[PathBuf]) {
+    hashes.retain(|_, files| {
+        let first = files.first().and_then(|p| root_index(p, roots));
+        files.iter().any(|p| root_index(p, roots) != first)
     });
+}
 
-    for entry in iter {
-        if INTERRUPTED.load(Ordering::Relaxed) {
-            bail!("Scan interrupted by user");
+/// Keep only groups with copies on more than one device (mount).
+pub fn retain_cross_device(hashes: &mut HashMap<String, Vec<PathBuf>>) {
+    hashes.retain(|_, files| devices(files).len() > 1);
+}
+
+/// Split every group by `key` and drop the resulting singletons. The first
+/// part by key keeps the group's id, the others get ids of their own from
+/// `split_group_id`.
+fn split_groups<K: Ord>(hashes: &mut HashMap<String, Vec<PathBuf>>, key: impl Fn(&Path) -> K) {
+    let mut split = HashMap::new();
+    for (id, files) in hashes.drain() {
+        let mut by_key: BTreeMap<K, Vec<PathBuf>> = BTreeMap::new();
+        for file in files {
+            by_key.entry(key(&file)).or_default().push(file);
+        }
+        let parts = by_key.into_values().filter(|files| files.len() > 1);
+        for (n, files) in parts.enumerate() {
+            let part = if n == 0 {
+                id.clone()
+            } else {
+                split_group_id(&id, n as u64)
+            };
+            split.insert(part, files);
+        }
+    }
+    *hashes = split;
+}
+
+/// Split groups by file name, so only same-named copies remain. With
+/// `ignore_case`, names are compared after Unicode case folding.
+pub fn split_by_name(hashes: &mut HashMap<String, Vec<PathBuf>>, ignore_case: bool) {
+    split_groups(hashes, |file| {
+        let name = file.file_name().unwrap_or_default();
+        if ignore_case {
+            caseless::default_case_fold_str(&name.to_string_lossy()).into()
+        } else {
+            name.to_os_string()
         }
+    });
+}
 
-        let entry = match entry {
-            Ok(e) => e,
-            Err(e) => {
-                warn!("Error reading directory entry: {}", e);
-                continue;
-            }
+/// Split groups by the first-level directory under their root, so only copies
+/// within one subtree remain; files directly in a root count as one more
+/// subtree. The inverse of `retain_cross_root`.
+pub fn split_by_subdir(hashes: &mut HashMap<String, Vec<PathBuf>>, roots: &[PathBuf]) {
+    split_groups(hashes, |file| {
+        let Some(root) = roots.iter().find(|root| file.starts_with(root)) else {
+            return file.parent().unwrap_or(file).to_path_buf();
         };
+        let mut below = file.strip_prefix(root).unwrap_or(file).components();
+        match (below.next(), below.next()) {
+            (Some(dir), Some(_)) => root.join(dir),
+            _ => root.clone(),
+        }
+    });
+}
 
-        if !follow_links && entry.path().is_symlink() {
+/// Read candidate files from a newline-separated list instead of walking a
+/// tree; `-` reads stdin. Missing and non-regular entries are skipped with a
+/// warning, and the size, mtime and extension limits in `options` still apply.
+pub fn read_file_list(source: &Path, options: &ScanOptions) -> Result<Vec<ScannedFile>> {
+    let reader: Box<dyn BufRead> = if source == Path::new("-") {
+        Box::new(BufReader::new(io::stdin()))
+    } else {
+        let file = File::open(source)
+            .with_context(|| format!("Failed to open file list: {}", source.display()))?;
+        Box::new(BufReader::new(file))
+    };
+
+    let mut files = Vec::new();
+    for line in reader.lines() {
+        if INTERRUPTED.load(Ordering::Relaxed) {
+            return Err(DupFindError::Interrupted.into());
+        }
+        let line = line.context("Failed to read file list")?;
+        if line.is_empty() {
             continue;
         }
 
-        if !entry.file_type().is_file() {
+        let path = PathBuf::from(line);
+        if has_skipped_extension(&path, options) {
             continue;
         }
-
-        let metadata = match entry.metadata() {
+        let metadata = match fs::metadata(&path) {
             Ok(m) => m,
             Err(e) => {
-                warn!("Cannot read metadata for {}: {}", entry.path().display(), e);
+                warn!("Skipping {}: {}", path.display(), e);
                 continue;
             }
         };
+        if !metadata.is_file() {
+            warn!("Skipping {}: not a regular file", path.display());
+            continue;
+        }
 
         let size = metadata.len();
-        if size < min_size {
+        if size < options.min_size || options.max_size.is_some_and(|max| size > max) {
             continue;
         }
-
-        files.push(entry);
-        scanned += 1;
-        if scanned.is_multiple_of(1000) {
-            let msg = format!("Scanned {} files...", scanned);
-            progress.set_message(msg);
+        if !within_mtime_window(&path, &metadata, options) {
+            continue;
         }
+        files.push(ScannedFile {
+            path,
+            size,
+            symlink: false,
+        });
     }
 
     Ok(files)
 }
 
+/// Collapse paths that are hard links to the same file into one entry, so
+/// shared storage is not reported as wasted. Returns how many paths were
+/// dropped; size groups left with a single file are removed.
+pub fn collapse_hardlinks(groups: &mut HashMap<u64, Vec<PathBuf>>) -> usize {
+    let collapsed: usize = groups
+        .par_iter_mut()
+        .map(|(_, files)| {
+            let before = files.len();
+            let mut seen = HashSet::new();
+            files.retain(|path| file_id(path).is_none_or(|id| seen.insert(id)));
+            before - files.len()
+        })
+        .sum();
+    groups.retain(|_, files| files.len() > 1);
+    collapsed
+}
+
+/// Bucket files by size, dropping sizes with a single file. Empty files are
+/// dropped too unless `include_empty` is set, in which case they share the
+/// bucket for size 0.
+///
+/// Sizes are counted first so that only paths which can have a duplicate are
+/// copied, into buckets allocated at their final size. Files of a unique
+/// size, usually most of a tree, never enter the map. A group is only complete
+/// once the walk ends, as another file of its size may still turn up; what can
+/// start earlier is quick hashing files of sizes already seen, see
+/// `EarlyQuickHashes`.
 pub fn group_by_size(
-    files: &[walkdir::DirEntry],
-    progress: &ProgressBar,
+    files: &[ScannedFile],
+    include_empty: bool,
+    progress: &dyn ProgressReporter,
 ) -> Result<HashMap<u64, Vec<std::path::PathBuf>>> {
-    let processed = Arc::new(AtomicU64::new(0));
     let total = files.len() as u64;
+    let mut counts: HashMap<u64, usize> = HashMap::new();
+    for file in files {
+        if file.size > 0 || include_empty {
+            *counts.entry(file.size).or_default() += 1;
+        }
+    }
+    let mut groups: HashMap<u64, Vec<std::path::PathBuf>> = counts
+        .into_iter()
+        .filter(|&(_, count)| count > 1)
+        .map(|(size, count)| (size, Vec::with_capacity(count)))
+        .collect();
 
-    let groups: HashMap<u64, Vec<std::path::PathBuf>> = files
-        .par_iter()
-        .filter_map(|file| {
-            if INTERRUPTED.load(Ordering::Relaxed) {
-                return None;
-            }
+    for (i, file) in files.iter().enumerate() {
+        if INTERRUPTED.load(Ordering::Relaxed) {
+            return Err(DupFindError::Interrupted.into());
+        }
+        if i.is_multiple_of(1000) {
+            progress.on_progress(Phase::Group, i as u64, Some(total));
+        }
+        if let Some(paths) = groups.get_mut(&file.size) {
+            paths.push(file.path.clone());
+        }
+    }
 
-            let metadata = file.metadata().ok()?;
-            let size = metadata.len();
-            if size == 0 {
-                return None;
-            }
+    progress.on_progress(Phase::Group, total, Some(total));
+    Ok(groups)
+}
 
-            let current = processed.fetch_add(1, Ordering::Relaxed);
-            if current.is_multiple_of(1000) {
-                progress.set_position(current.min(total));
-            }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::progress::NoProgress;
+    use crate::utils::scan_tempdir;
+    use std::fs;
 
-            Some((size, file.path().to_path_buf()))
-        })
-        .fold(
-            HashMap::<u64, Vec<std::path::PathBuf>>::new,
-            |mut acc, (size, path)| {
-                acc.entry(size).or_default().push(path);
-                acc
-            },
-        )
-        .reduce(HashMap::<u64, Vec<std::path::PathBuf>>::new, |mut a, b| {
-            for (size, paths) in b {
-                a.entry(size).or_default().extend(paths);
-            }
-            a
-        });
+    fn scanned_names(root: &Path, options: &ScanOptions) -> Vec<String> {
+        let mut names: Vec<_> = scan_files(&[root.to_path_buf()], options, &NoProgress)
+            .unwrap()
+            .files
+            .iter()
+            .map(|f| f.path.file_name().unwrap().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        names
+    }
 
-    progress.set_position(total);
-    let mut groups = groups;
-    groups.retain(|_, files| files.len() > 1);
-    Ok(groups)
+    #[test]
+    fn test_scan_respects_ignore_files() {
+        let dir = scan_tempdir();
+        let root = dir.path();
+        fs::create_dir_all(root.join("nested/target")).unwrap();
+        fs::write(root.join("keep.txt"), "a").unwrap();
+        fs::write(root.join("nested/target/build.bin"), "a").unwrap();
+        fs::write(root.join("nested/notes.tmp"), "a").unwrap();
+        fs::write(root.join("nested/.gitignore"), "target/\n").unwrap();
+        fs::write(root.join(IGNORE_FILENAME), "*.tmp\n").unwrap();
+
+        let options = ScanOptions::default();
+        assert_eq!(scanned_names(root, &options), vec!["keep.txt"]);
+
+        let options = ScanOptions {
+            no_gitignore: true,
+            ..Default::default()
+        };
+        assert_eq!(scanned_names(root, &options), vec!["build.bin", "keep.txt"]);
+    }
+
+    #[test]
+    fn test_include_exclude_globs() {
+        let dir = scan_tempdir();
+        let root = dir.path();
+        fs::create_dir_all(root.join("photos/cache")).unwrap();
+        fs::write(root.join("photos/a.jpg"), "a").unwrap();
+        fs::write(root.join("photos/b.png"), "a").unwrap();
+        fs::write(root.join("photos/cache/c.jpg"), "a").unwrap();
+        fs::write(root.join("notes.txt"), "a").unwrap();
+
+        let options = ScanOptions {
+            include: vec!["*.jpg".into(), "*.png".into()],
+            exclude: vec!["**/cache/*".into()],
+            ..Default::default()
+        };
+        assert_eq!(scanned_names(root, &options), vec!["a.jpg", "b.png"]);
+
+        let options = ScanOptions {
+            skip_extensions: vec!["png".into(), "txt".into()],
+            ..Default::default()
+        };
+        fs::rename(root.join("notes.txt"), root.join("notes.TXT")).unwrap();
+        assert_eq!(scanned_names(root, &options), vec!["a.jpg", "c.jpg"]);
+    }
+
+    #[test]
+    fn test_retain_cross_root() {
+        let roots = vec![PathBuf::from("/a"), PathBuf::from("/b")];
+        let mut hashes = HashMap::from([
+            (
+                "cross".to_string(),
+                vec![PathBuf::from("/a/x"), PathBuf::from("/b/x")],
+            ),
+            (
+                "local".to_string(),
+                vec![PathBuf::from("/a/x"), PathBuf::from("/a/y")],
+            ),
+        ]);
+
+        retain_cross_root(&mut hashes, &roots);
+        assert_eq!(hashes.len(), 1);
+        assert!(hashes.contains_key("cross"));
+    }
+
+    #[test]
+    fn test_group_by_size_keeps_only_shared_sizes() {
+        let file = |name: &str, size| ScannedFile {
+            path: PathBuf::from(name),
+            size,
+            symlink: false,
+        };
+        let files = [
+            file("a", 3),
+            file("b", 5),
+            file("c", 3),
+            file("e1", 0),
+            file("e2", 0),
+        ];
+
+        let groups = group_by_size(&files, false, &NoProgress).unwrap();
+        assert_eq!(
+            groups,
+            HashMap::from([(3, vec![PathBuf::from("a"), PathBuf::from("c")])])
+        );
+        let groups = group_by_size(&files, true, &NoProgress).unwrap();
+        assert_eq!(groups[&0], vec![PathBuf::from("e1"), PathBuf::from("e2")]);
+    }
+
+    #[test]
+    fn test_retain_cross_device_drops_single_device_groups() {
+        let dir = tempfile::tempdir().unwrap();
+        let (a, b) = (dir.path().join("a"), dir.path().join("b"));
+        fs::write(&a, "same").unwrap();
+        fs::write(&b, "same").unwrap();
+        let files = vec![a, b];
+        assert_eq!(devices(&files).len(), 1);
+
+        let mut hashes = HashMap::from([("h".to_string(), files)]);
+        retain_cross_device(&mut hashes);
+        assert!(hashes.is_empty());
+    }
+
+    #[test]
+    fn test_split_by_name() {
+        let mut hashes = HashMap::from([
+            (
+                "h1".to_string(),
+                vec![
+                    PathBuf::from("/a/report.pdf"),
+                    PathBuf::from("/b/report.pdf"),
+                    PathBuf::from("/b/final.pdf"),
+                ],
+            ),
+            (
+                "h2".to_string(),
+                vec![PathBuf::from("/a/x"), PathBuf::from("/a/y")],
+            ),
+        ]);
+
+        let mut folded = hashes.clone();
+        folded.insert(
+            "h3".to_string(),
+            vec![
+                PathBuf::from("/a/Straße.PDF"),
+                PathBuf::from("/b/STRASSE.pdf"),
+            ],
+        );
+
+        split_by_name(&mut hashes, false);
+        assert_eq!(hashes.len(), 1);
+        assert_eq!(hashes["h1"].len(), 2);
+
+        split_by_name(&mut folded, true);
+        assert_eq!(folded.len(), 2);
+        assert_eq!(folded["h3"].len(), 2);
+    }
+
+    #[test]
+    fn test_split_by_subdir() {
+        let roots = [PathBuf::from("/data")];
+        let mut hashes = HashMap::from([
+            (
+                "h1".to_string(),
+                vec![
+                    PathBuf::from("/data/live/a.txt"),
+                    PathBuf::from("/data/live/sub/a.txt"),
+                    PathBuf::from("/data/backup/a.txt"),
+                    PathBuf::from("/data/backup/old/a.txt"),
+                ],
+            ),
+            (
+                "h2".to_string(),
+                vec![
+                    PathBuf::from("/data/live/b.txt"),
+                    PathBuf::from("/data/backup/b.txt"),
+                ],
+            ),
+            (
+                "h3".to_string(),
+                vec![PathBuf::from("/data/c.txt"), PathBuf::from("/data/d.txt")],
+            ),
+        ]);
+
+        split_by_subdir(&mut hashes, &roots);
+        assert_eq!(hashes.len(), 3);
+        // The hash itself stays intact for the outputs; only the id differs.
+        assert_eq!(
+            hashes["h1"],
+            [
+                PathBuf::from("/data/backup/a.txt"),
+                PathBuf::from("/data/backup/old/a.txt")
+            ]
+        );
+        assert_eq!(
+            hashes["h1#1"],
+            [
+                PathBuf::from("/data/live/a.txt"),
+                PathBuf::from("/data/live/sub/a.txt")
+            ]
+        );
+        assert_eq!(hashes["h3"].len(), 2);
+    }
+
+    #[test]
+    fn test_read_file_list_skips_missing_and_dirs() {
+        let dir = scan_tempdir();
+        let root = dir.path();
+        fs::write(root.join("a.txt"), "a").unwrap();
+        fs::create_dir(root.join("sub")).unwrap();
+        let list = root.join("list.txt");
+        let lines = ["a.txt", "missing.txt", "sub"].map(|n| root.join(n).display().to_string());
+        fs::write(&list, lines.join("\n") + "\n").unwrap();
+
+        let files = read_file_list(&list, &ScanOptions::default()).unwrap();
+        let paths: Vec<_> = files.into_iter().map(|f| f.path).collect();
+        assert_eq!(paths, vec![root.join("a.txt")]);
+    }
+
+    #[test]
+    fn test_depth_limits() {
+        let dir = scan_tempdir();
+        let root = dir.path();
+        fs::create_dir_all(root.join("one/two")).unwrap();
+        fs::write(root.join("top.txt"), "a").unwrap();
+        fs::write(root.join("one/mid.txt"), "a").unwrap();
+        fs::write(root.join("one/two/deep.txt"), "a").unwrap();
+
+        let options = ScanOptions {
+            max_depth: Some(0),
+            ..Default::default()
+        };
+        assert_eq!(scanned_names(root, &options), vec!["top.txt"]);
+
+        let options = ScanOptions {
+            min_depth: 1,
+            max_depth: Some(1),
+            ..Default::default()
+        };
+        assert_eq!(scanned_names(root, &options), vec!["mid.txt"]);
+    }
+
+    #[test]
+    fn test_modified_window() {
+        let dir = scan_tempdir();
+        let root = dir.path();
+        let day = std::time::Duration::from_secs(86_400);
+        let now = SystemTime::now();
+        for (name, age) in [("new.txt", 1), ("old.txt", 60)] {
+            let file = fs::File::create(root.join(name)).unwrap();
+            file.set_modified(now - day * age).unwrap();
+        }
+
+        let options = ScanOptions {
+            modified_after: Some(now - day * 30),
+            ..Default::default()
+        };
+        assert_eq!(scanned_names(root, &options), vec!["new.txt"]);
+
+        let options = ScanOptions {
+            modified_before: Some(now - day * 30),
+            ..Default::default()
+        };
+        assert_eq!(scanned_names(root, &options), vec!["old.txt"]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_collapse_hardlinks() {
+        let dir = scan_tempdir();
+        let root = dir.path();
+        let (a, b, c) = (root.join("a"), root.join("b"), root.join("c"));
+        fs::write(&a, "same").unwrap();
+        fs::hard_link(&a, &b).unwrap();
+        fs::write(&c, "same").unwrap();
+
+        let mut groups = HashMap::from([(4, vec![a.clone(), b.clone()])]);
+        assert_eq!(collapse_hardlinks(&mut groups), 1);
+        assert!(groups.is_empty());
+
+        let mut groups = HashMap::from([(4, vec![a.clone(), b, c.clone()])]);
+        collapse_hardlinks(&mut groups);
+        assert_eq!(groups[&4], vec![a, c]);
+    }
+
+    #[test]
+    fn test_hidden_entries_included_with_flag() {
+        let dir = scan_tempdir();
+        let root = dir.path();
+        fs::create_dir_all(root.join(".config")).unwrap();
+        fs::write(root.join(".config/settings"), "a").unwrap();
+        fs::write(root.join(".profile"), "a").unwrap();
+        fs::write(root.join("visible"), "a").unwrap();
+
+        let options = ScanOptions::default();
+        assert_eq!(scanned_names(root, &options), vec!["visible"]);
+
+        let options = ScanOptions {
+            include_system_hidden: true,
+            ..Default::default()
+        };
+        assert_eq!(scanned_names(root, &options), vec!["visible"]);
+
+        let options = ScanOptions {
+            include_dotfiles: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            scanned_names(root, &options),
+            vec![".profile", "settings", "visible"]
+        );
+    }
+
+    #[test]
+    fn test_hidden_predicate_counts_as_system_hidden() {
+        let dir = scan_tempdir();
+        let root = dir.path();
+        fs::create_dir_all(root.join("vault")).unwrap();
+        fs::write(root.join("vault/key"), "a").unwrap();
+        fs::write(root.join("visible"), "a").unwrap();
+
+        let predicate = HiddenPredicate::new(|path| path.ends_with("vault"));
+        let options = ScanOptions {
+            hidden_predicate: Some(predicate.clone()),
+            ..Default::default()
+        };
+        assert_eq!(scanned_names(root, &options), vec!["visible"]);
+
+        let options = ScanOptions {
+            hidden_predicate: Some(predicate),
+            include_system_hidden: true,
+            ..Default::default()
+        };
+        assert_eq!(scanned_names(root, &options), vec!["key", "visible"]);
+    }
+
+    #[test]
+    fn test_ignore_by_path_and_name() {
+        let dir = scan_tempdir();
+        let root = dir.path();
+        for sub in ["app/build", "lib/build", "lib/cache", "docs"] {
+            fs::create_dir_all(root.join(sub)).unwrap();
+            fs::write(root.join(sub).join(sub.replace('/', "_")), "a").unwrap();
+        }
+
+        let options = ScanOptions {
+            ignore: vec![root.join("app/build").display().to_string()],
+            ..Default::default()
+        };
+        assert_eq!(
+            scanned_names(root, &options),
+            vec!["docs", "lib_build", "lib_cache"]
+        );
+
+        let options = ScanOptions {
+            ignore: vec!["build".into(), format!("{}/*/cache", root.display())],
+            ..Default::default()
+        };
+        assert_eq!(scanned_names(root, &options), vec!["docs"]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_follow_links_counts_each_file_once() {
+        let dir = scan_tempdir();
+        let root = dir.path();
+        fs::create_dir_all(root.join("real")).unwrap();
+        fs::write(root.join("real/file"), "a").unwrap();
+        std::os::unix::fs::symlink(root.join("real"), root.join("alias")).unwrap();
+        // A cycle back to the root must not make the walk loop either.
+        std::os::unix::fs::symlink(root, root.join("real/loop")).unwrap();
+
+        let options = ScanOptions {
+            follow_links: true,
+            ..Default::default()
+        };
+        assert_eq!(scanned_names(root, &options), vec!["file"]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_follow_links_keeps_hard_links() {
+        let dir = scan_tempdir();
+        let root = dir.path();
+        fs::create_dir_all(root.join("real")).unwrap();
+        fs::write(root.join("real/file"), "a").unwrap();
+        fs::hard_link(root.join("real/file"), root.join("real/hard")).unwrap();
+        std::os::unix::fs::symlink(root.join("real"), root.join("alias")).unwrap();
+
+        let options = ScanOptions {
+            follow_links: true,
+            ..Default::default()
+        };
+        assert_eq!(scanned_names(root, &options), vec!["file", "hard"]);
+    }
 }
@@ -0,0 +1,186 @@
+use anyhow::{Context, Result};
+use image::ImageReader;
+use image::imageops::FilterType;
+use log::debug;
+use rayon::prelude::*;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::progress::{Phase, ProgressReporter};
+use crate::scanner::ScannedFile;
+use crate::statistics::group_digest;
+use crate::utils::INTERRUPTED;
+
+/// Extensions of the formats `--similar-images` can decode.
+pub const IMAGE_EXTENSIONS: &[&str] = &["jpg", "jpeg", "png", "gif", "webp", "bmp", "tif", "tiff"];
+
+/// Default maximum Hamming distance between two dHashes still considered similar.
+pub const DEFAULT_THRESHOLD: u32 = 10;
+
+pub fn is_image(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| IMAGE_EXTENSIONS.contains(&ext.to_ascii_lowercase().as_str()))
+}
+
+/// 64-bit difference hash: shrink to 9×8 grayscale and record whether each
+/// pixel is brighter than its right neighbour. Survives resizing and
+/// re-encoding, unlike a content hash.
+pub fn dhash(path: &Path) -> Result<u64> {
+    let image = ImageReader::open(path)
+        .with_context(|| format!("Failed to open {}", path.display()))?
+        .with_guessed_format()?
+        .decode()
+        .with_context(|| format!("Failed to decode {}", path.display()))?;
+    let small = image.resize_exact(9, 8, FilterType::Triangle).to_luma8();
+
+    let mut hash = 0u64;
+    for y in 0..8 {
+        for x in 0..8 {
+            let bit = small.get_pixel(x, y)[0] > small.get_pixel(x + 1, y)[0];
+            hash = (hash << 1) | bit as u64;
+        }
+    }
+    Ok(hash)
+}
+
+fn find(parent: &mut [usize], i: usize) -> usize {
+    let mut root = i;
+    while parent[root] != root {
+        root = parent[root];
+    }
+    parent[i] = root;
+    root
+}
+
+/// Group images whose dHashes are within `threshold` bits of each other.
+/// Grouping is transitive, so a chain of close images ends up in one group.
+/// Files that cannot be decoded are skipped.
+pub fn group_similar(
+    files: &[ScannedFile],
+    threshold: u32,
+    progress: &dyn ProgressReporter,
+) -> Vec<Vec<PathBuf>> {
+    let images: Vec<_> = files.iter().filter(|f| is_image(&f.path)).collect();
+    let total = images.len() as u64;
+    let processed = AtomicU64::new(0);
+
+    let hashed: Vec<(&Path, u64)> = images
+        .par_iter()
+        .filter_map(|file| {
+            if INTERRUPTED.load(Ordering::Relaxed) {
+                return None;
+            }
+            let current = processed.fetch_add(1, Ordering::Relaxed);
+            if current.is_multiple_of(100) {
+                progress.on_progress(Phase::Images, current.min(total), Some(total));
+            }
+            match dhash(&file.path) {
+                Ok(hash) => Some((file.path.as_path(), hash)),
+                Err(e) => {
+                    debug!("Skipping image {}: {:#}", file.path.display(), e);
+                    None
+                }
+            }
+        })
+        .collect();
+    progress.on_progress(Phase::Images, total, Some(total));
+
+    let n = hashed.len();
+    let edges: Vec<(usize, usize)> = (0..n)
+        .into_par_iter()
+        .flat_map_iter(|i| {
+            let hashed = &hashed;
+            (i + 1..n)
+                .filter(move |&j| (hashed[i].1 ^ hashed[j].1).count_ones() <= threshold)
+                .map(move |j| (i, j))
+        })
+        .collect();
+
+    let mut parent: Vec<usize> = (0..n).collect();
+    for (i, j) in edges {
+        let (a, b) = (find(&mut parent, i), find(&mut parent, j));
+        parent[a] = b;
+    }
+
+    let mut groups: HashMap<usize, Vec<PathBuf>> = HashMap::new();
+    for (i, (path, _)) in hashed.iter().enumerate() {
+        let root = find(&mut parent, i);
+        groups.entry(root).or_default().push(path.to_path_buf());
+    }
+    let mut groups: Vec<_> = groups
+        .into_values()
+        .filter(|files| files.len() > 1)
+        .map(|mut files| {
+            files.sort();
+            files
+        })
+        .collect();
+    groups.sort();
+    groups
+}
+
+/// Drop similar groups whose files are all byte-identical copies, since the
+/// exact matcher already reports them.
+pub fn retain_not_identical(
+    groups: &mut Vec<Vec<PathBuf>>,
+    hashes: &HashMap<String, Vec<PathBuf>>,
+) {
+    let hash_of: HashMap<&Path, &str> = hashes
+        .iter()
+        .flat_map(|(id, files)| files.iter().map(move |f| (f.as_path(), group_digest(id))))
+        .collect();
+    groups.retain(|files| {
+        let first = hash_of.get(files[0].as_path());
+        first.is_none() || files.iter().any(|f| hash_of.get(f.as_path()) != first)
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::progress::NoProgress;
+    use image::{Rgb, RgbImage};
+    use std::fs;
+
+    fn scanned(path: PathBuf) -> ScannedFile {
+        let size = fs::metadata(&path).unwrap().len();
+        ScannedFile {
+            path,
+            size,
+            symlink: false,
+        }
+    }
+
+    #[test]
+    fn test_resized_copy_is_similar() {
+        let dir = tempfile::tempdir().unwrap();
+        let gradient = |w: u32, h: u32| {
+            RgbImage::from_fn(w, h, move |x, y| {
+                let v = (x * 255 / w) as u8;
+                Rgb([v, v, (y * 255 / h) as u8])
+            })
+        };
+        let original = dir.path().join("photo.png");
+        let resized = dir.path().join("photo_small.jpg");
+        let other = dir.path().join("other.png");
+        let broken = dir.path().join("broken.jpg");
+        gradient(128, 96).save(&original).unwrap();
+        gradient(64, 48).save(&resized).unwrap();
+        RgbImage::from_fn(128, 96, |x, _| {
+            let v = 255 - (x * 2) as u8;
+            Rgb([v, v, v])
+        })
+        .save(&other)
+        .unwrap();
+        fs::write(&broken, "not an image").unwrap();
+
+        let files: Vec<_> = [&original, &resized, &other, &broken]
+            .into_iter()
+            .map(|p| scanned(p.clone()))
+            .collect();
+        let groups = group_similar(&files, DEFAULT_THRESHOLD, &NoProgress);
+        assert_eq!(groups, vec![vec![original, resized]]);
+    }
+}
@@ -0,0 +1,158 @@
+use anyhow::{Context, Result, bail};
+use rusqlite::{Connection, params};
+use std::path::Path;
+use std::time::SystemTime;
+
+use crate::statistics::ScanResults;
+
+/// Version of the tables below, kept in `PRAGMA user_version`. Bumped on
+/// changes existing queries would notice; new columns get defaults instead.
+pub const SQLITE_SCHEMA_VERSION: i32 = 1;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS scans (
+    id INTEGER PRIMARY KEY,
+    scanned_at TEXT NOT NULL,
+    algorithm TEXT NOT NULL,
+    files_scanned INTEGER NOT NULL,
+    bytes_scanned INTEGER NOT NULL,
+    duplicate_groups INTEGER NOT NULL,
+    duplicate_files INTEGER NOT NULL,
+    wasted_bytes INTEGER NOT NULL,
+    duration_seconds REAL NOT NULL,
+    prefix_only_bytes INTEGER
+);
+CREATE TABLE IF NOT EXISTS groups (
+    id INTEGER PRIMARY KEY,
+    scan_id INTEGER NOT NULL REFERENCES scans(id),
+    hash TEXT NOT NULL,
+    size INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS files (
+    group_id INTEGER NOT NULL REFERENCES groups(id),
+    path TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS groups_scan ON groups(scan_id);
+CREATE INDEX IF NOT EXISTS files_group ON files(group_id);
+CREATE INDEX IF NOT EXISTS files_path ON files(path);
+";
+
+/// Append a scan to the database at `path`, creating it and its tables if
+/// needed. Each run adds one `scans` row (timestamped in RFC 3339) with its
+/// `groups` and their `files`, so results can be compared over time.
+pub fn save_results_sqlite(path: &Path, results: &ScanResults) -> Result<()> {
+    let mut db = Connection::open(path)
+        .with_context(|| format!("Failed to open database: {}", path.display()))?;
+    let version: i32 = db.pragma_query_value(None, "user_version", |row| row.get(0))?;
+    if version > SQLITE_SCHEMA_VERSION {
+        bail!(
+            "{} uses schema version {}, newer than this dupfind supports ({})",
+            path.display(),
+            version,
+            SQLITE_SCHEMA_VERSION
+        );
+    }
+
+    let tx = db.transaction()?;
+    tx.execute_batch(SCHEMA)
+        .context("Failed to create database tables")?;
+    // Databases from before --prefix-only lack the column; NULL means verified.
+    let has_prefix: bool = tx.query_row(
+        "SELECT COUNT(*) > 0 FROM pragma_table_info('scans') WHERE name = 'prefix_only_bytes'",
+        [],
+        |row| row.get(0),
+    )?;
+    if !has_prefix {
+        tx.execute("ALTER TABLE scans ADD COLUMN prefix_only_bytes INTEGER", [])?;
+    }
+    tx.pragma_update(None, "user_version", SQLITE_SCHEMA_VERSION)?;
+    tx.execute(
+        "INSERT INTO scans (scanned_at, algorithm, files_scanned, bytes_scanned,
+             duplicate_groups, duplicate_files, wasted_bytes, duration_seconds,
+             prefix_only_bytes)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+        params![
+            humantime::format_rfc3339_seconds(SystemTime::now()).to_string(),
+            results.algorithm.to_string(),
+            results.total_files_scanned as i64,
+            results.total_bytes_scanned as i64,
+            results.total_duplicate_groups as i64,
+            results.total_duplicate_files as i64,
+            results.total_wasted_space as i64,
+            results.scan_duration_seconds,
+            results.prefix_only.map(|bytes| bytes as i64),
+        ],
+    )?;
+    let scan_id = tx.last_insert_rowid();
+    {
+        let mut insert_group =
+            tx.prepare("INSERT INTO groups (scan_id, hash, size) VALUES (?1, ?2, ?3)")?;
+        let mut insert_file = tx.prepare("INSERT INTO files (group_id, path) VALUES (?1, ?2)")?;
+        for group in &results.groups {
+            let group_id = insert_group.insert(params![scan_id, group.hash, group.size as i64])?;
+            for file in &group.files {
+                insert_file.execute(params![group_id, file])?;
+            }
+        }
+    }
+    tx.commit().context("Failed to write scan to database")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DupFinder;
+    use crate::utils::scan_tempdir;
+    use std::fs;
+
+    #[test]
+    fn test_runs_append_to_database() {
+        let dir = scan_tempdir();
+        let data = dir.path().join("data");
+        fs::create_dir(&data).unwrap();
+        fs::write(data.join("a.txt"), "duplicate").unwrap();
+        fs::write(data.join("b.txt"), "duplicate").unwrap();
+        let db_path = dir.path().join("scans.db");
+
+        let results = DupFinder::new(&data).run().unwrap();
+        save_results_sqlite(&db_path, &results).unwrap();
+        save_results_sqlite(&db_path, &results).unwrap();
+
+        let db = Connection::open(&db_path).unwrap();
+        let count = |table: &str| -> i64 {
+            db.query_row(&format!("SELECT COUNT(*) FROM {table}"), [], |row| {
+                row.get(0)
+            })
+            .unwrap()
+        };
+        assert_eq!(count("scans"), 2);
+        assert_eq!(count("groups"), 2);
+        assert_eq!(count("files"), 4);
+    }
+
+    #[test]
+    fn test_prefix_only_scans_are_marked_in_older_databases() {
+        let dir = scan_tempdir();
+        let data = dir.path().join("data");
+        fs::create_dir(&data).unwrap();
+        fs::write(data.join("a.txt"), "duplicate").unwrap();
+        fs::write(data.join("b.txt"), "duplicate").unwrap();
+        let db_path = dir.path().join("scans.db");
+        let old_schema = SCHEMA.replace(",\n    prefix_only_bytes INTEGER", "");
+        assert!(!old_schema.contains("prefix_only"));
+        Connection::open(&db_path)
+            .unwrap()
+            .execute_batch(&old_schema)
+            .unwrap();
+
+        let results = DupFinder::new(&data).prefix_only(Some(4)).run().unwrap();
+        save_results_sqlite(&db_path, &results).unwrap();
+
+        let db = Connection::open(&db_path).unwrap();
+        let bytes: Option<i64> = db
+            .query_row("SELECT prefix_only_bytes FROM scans", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(bytes, Some(4));
+    }
+}
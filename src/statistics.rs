@@ -1,39 +1,378 @@
 use anyhow::Result;
 use rayon::prelude::*;
 use std::collections::HashMap;
-use std::path::PathBuf;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
 
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Serialize, Deserialize)]
+use crate::hasher::HashAlgorithm;
+use crate::output::Units;
+use crate::utils::{owner, permissions_string};
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 pub struct DuplicateGroup {
     pub hash: String,
     pub size: u64,
     pub files: Vec<String>,
+    /// Permissions, owner and mtime of each file, in `files` order, for `--show-metadata`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub metadata: Vec<FileMetadata>,
+    /// Distinct device ids (`st_dev`) the files live on, for `--show-devices`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub devices: Vec<u64>,
+    /// Leading bytes compared, on groups streamed with `--prefix-only` (such
+    /// as NDJSON lines), which have no `ScanResults` around them to say so
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prefix_only: Option<usize>,
+}
+
+/// What helps tell copies apart when choosing which one to keep.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct FileMetadata {
+    pub path: String,
+    /// `rwxr-xr-x` bits on Unix, attributes such as `readonly,hidden` on Windows
+    pub mode: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uid: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gid: Option<u32>,
+    /// Modification time in RFC 3339, UTC
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub modified: Option<String>,
+}
+
+impl FileMetadata {
+    pub fn read(path: &Path) -> Option<Self> {
+        let meta = std::fs::metadata(path).ok()?;
+        let owner = owner(&meta);
+        Some(Self {
+            path: path.to_string_lossy().into_owned(),
+            mode: permissions_string(&meta),
+            uid: owner.map(|(uid, _)| uid),
+            gid: owner.map(|(_, gid)| gid),
+            modified: meta
+                .modified()
+                .ok()
+                .map(|t| humantime::format_rfc3339_seconds(t).to_string()),
+        })
+    }
+}
+
+/// Images that look alike without being byte-identical.
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct SimilarGroup {
+    pub files: Vec<String>,
+}
+
+/// A symlink whose target is one of the duplicates, for `--report-symlinks`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct SymlinkEntry {
+    pub link: String,
+    pub target: String,
+}
+
+/// Duplicates and wasted space for one file extension, for `--by-extension`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct ExtensionStats {
+    /// Lowercased extension, or `(none)`
+    pub extension: String,
+    pub groups: usize,
+    pub files: usize,
+    pub wasted_space: u64,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+/// Duplicates whose file size falls in one range, for `--size-histogram`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct SizeBucket {
+    pub label: String,
+    /// Smallest file size in the bucket, in bytes
+    pub min: u64,
+    /// First size past the bucket, or `None` for the last one
+    pub max: Option<u64>,
+    pub groups: usize,
+    pub files: usize,
+    pub wasted_space: u64,
+}
+
+/// Upper bounds of the `--size-histogram` buckets (1 k, 1 M and 100 M in
+/// the chosen units); the last bucket is open.
+const DECIMAL_BUCKETS: [u64; 3] = [1_000, 1_000_000, 100_000_000];
+const BINARY_BUCKETS: [u64; 3] = [1 << 10, 1 << 20, 100 << 20];
+
+/// A file that could not be read during hashing.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ScanError {
+    pub path: String,
+    pub error: String,
+}
+
+/// Thread-safe sink for per-file errors raised inside the rayon pipeline.
+#[derive(Default)]
+pub struct ErrorCollector {
+    errors: Mutex<Vec<ScanError>>,
+    vanished: AtomicUsize,
+}
+
+impl ErrorCollector {
+    pub fn record(&self, path: &Path, error: &anyhow::Error) {
+        // Files deleted after the walk are routine on a busy system, so they
+        // are only counted instead of listed as errors.
+        let not_found = error.chain().any(|cause| {
+            cause
+                .downcast_ref::<io::Error>()
+                .is_some_and(|e| e.kind() == io::ErrorKind::NotFound)
+        });
+        if not_found {
+            self.vanished.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+        self.errors.lock().unwrap().push(ScanError {
+            path: path.display().to_string(),
+            error: format!("{:#}", error),
+        });
+    }
+
+    /// Files that disappeared between the walk and hashing.
+    pub fn vanished(&self) -> usize {
+        self.vanished.load(Ordering::Relaxed)
+    }
+
+    pub fn into_errors(self) -> Vec<ScanError> {
+        let mut errors = self.errors.into_inner().unwrap();
+        errors.sort_by(|a, b| a.path.cmp(&b.path));
+        errors
+    }
+}
+
+/// Version of the JSON results format, bumped on breaking changes.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// Serializable results of a scan, as written by `--output-json`.
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 pub struct ScanResults {
+    /// Results format version, matching the `version` of `--print-schema`
+    #[serde(default)]
+    pub schema_version: u32,
+    pub algorithm: HashAlgorithm,
     pub total_files_scanned: usize,
+    #[serde(default)]
+    pub total_bytes_scanned: u64,
+    #[serde(default)]
+    pub average_file_size: u64,
+    #[serde(default)]
+    pub total_bytes_read: u64,
     pub total_size_groups: usize,
     pub total_duplicate_groups: usize,
     pub total_duplicate_files: usize,
     pub total_wasted_space: u64,
     pub scan_duration_seconds: f64,
+    /// Set by `--prefix-only`: groups only share their size and this many
+    /// leading bytes, and are probable rather than verified duplicates
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prefix_only: Option<usize>,
     pub groups: Vec<DuplicateGroup>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub similar_groups: Vec<SimilarGroup>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub symlinks: Vec<SymlinkEntry>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub extension_breakdown: Vec<ExtensionStats>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub size_histogram: Vec<SizeBucket>,
+    pub errors: Vec<ScanError>,
+}
+
+/// Separates a content hash from the counter that keeps group ids unique when
+/// several groups share one hash, as after `--verify` finds a collision or
+/// `--same-name` splits a group. Hashes are hex, so never contain it.
+const GROUP_ID_SEPARATOR: char = '#';
+
+/// Id for another group with the content hash of the group with id `id`.
+pub fn split_group_id(id: &str, n: u64) -> String {
+    format!("{}{}{}", id, GROUP_ID_SEPARATOR, n)
+}
+
+/// The content hash of the group with id `id`, as written to every output.
+pub fn group_digest(id: &str) -> &str {
+    id.split_once(GROUP_ID_SEPARATOR)
+        .map_or(id, |(digest, _)| digest)
+}
+
+/// Outcome of a scan: duplicate groups keyed by group id, plus totals. A group
+/// id is its content hash, unless other groups share that hash (see
+/// `group_digest`).
+pub struct Duplicates {
+    pub hashes: HashMap<String, Vec<PathBuf>>,
+    /// Groups of visually similar images, only filled by `similar_images`.
+    pub similar: Vec<Vec<PathBuf>>,
+    /// Symlinks pointing at duplicated files, as (link, target); only filled
+    /// by `report_symlinks`.
+    pub symlinks: Vec<(PathBuf, PathBuf)>,
+    /// Symlinks skipped because their target is missing, sorted; only met
+    /// with `follow_links`.
+    pub broken_links: Vec<PathBuf>,
+    /// Full hash of every scanned file, sorted by path; only filled by `checksums`.
+    pub checksums: Vec<(PathBuf, String)>,
+    pub stats: ScanStatistics,
+    pub algorithm: HashAlgorithm,
+    pub duration: f64,
+    pub errors: Vec<ScanError>,
+    /// Files deleted between the walk and hashing, not counted as errors.
+    pub vanished: usize,
+    /// Wall-clock time of each phase that ran, in order: scan, group, hash,
+    /// verify, similar, statistics.
+    pub timings: Vec<(&'static str, Duration)>,
+    /// Leading bytes compared when only prefixes were hashed; the groups are
+    /// then probable duplicates, not verified ones.
+    pub prefix_only: Option<usize>,
 }
 
 pub struct ScanStatistics {
     pub total_files_scanned: usize,
+    pub total_bytes_scanned: u64,
+    pub average_file_size: u64,
     pub total_size_groups: usize,
     pub total_duplicate_groups: usize,
     pub total_duplicate_files: usize,
     pub total_wasted_space: u64,
+    /// Bytes actually read while hashing, across all tiers
+    pub total_bytes_read: u64,
+    pub quick_hash: QuickHashStats,
+}
+
+/// How well the quick hash pre-filter worked, for tuning `--quick-hash-size`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct QuickHashStats {
+    /// Files compared by quick hash
+    pub files: u64,
+    /// Files that matched another by quick hash but were not duplicates;
+    /// many of these mean the sample is too small
+    pub collisions: u64,
+    /// Quick-hashed files that never needed a full read
+    pub full_reads_avoided: u64,
+}
+
+/// Mean size of the scanned files, or 0 if nothing was scanned.
+pub fn average_size(total_bytes: u64, files: usize) -> u64 {
+    total_bytes.checked_div(files as u64).unwrap_or(0)
+}
+
+/// Group duplicates by the extension of their first file, most wasted space first.
+pub fn extension_breakdown(hashes: &HashMap<String, Vec<PathBuf>>) -> Vec<ExtensionStats> {
+    let mut by_ext: HashMap<String, ExtensionStats> = HashMap::new();
+    for files in hashes.values() {
+        let Some(first) = files.first() else {
+            continue;
+        };
+        let extension = first
+            .extension()
+            .map(|ext| ext.to_string_lossy().to_lowercase())
+            .unwrap_or_else(|| "(none)".to_string());
+        let size = std::fs::metadata(first).map(|m| m.len()).unwrap_or(0);
+
+        let entry = by_ext
+            .entry(extension.clone())
+            .or_insert_with(|| ExtensionStats {
+                extension,
+                groups: 0,
+                files: 0,
+                wasted_space: 0,
+            });
+        entry.groups += 1;
+        entry.files += files.len();
+        entry.wasted_space += size * (files.len() as u64 - 1);
+    }
+
+    let mut breakdown: Vec<_> = by_ext.into_values().collect();
+    breakdown.sort_by(|a, b| {
+        b.wasted_space
+            .cmp(&a.wasted_space)
+            .then_with(|| a.extension.cmp(&b.extension))
+    });
+    breakdown
+}
+
+/// Keep only groups that are new since `baseline`: content not duplicated
+/// then, or with more copies than it had. Groups are matched by content hash,
+/// counting the copies in every group that shares one, so renaming or moving
+/// a known copy does not make its group new again.
+pub fn retain_new_since(hashes: &mut HashMap<String, Vec<PathBuf>>, baseline: &ScanResults) {
+    let mut known: HashMap<&str, usize> = HashMap::new();
+    for group in &baseline.groups {
+        *known.entry(group_digest(&group.hash)).or_default() += group.files.len();
+    }
+    let mut copies: HashMap<String, usize> = HashMap::new();
+    for (id, files) in hashes.iter() {
+        *copies.entry(group_digest(id).to_string()).or_default() += files.len();
+    }
+    hashes.retain(|id, _| {
+        let digest = group_digest(id);
+        known
+            .get(digest)
+            .is_none_or(|&count| copies[digest] > count)
+    });
+}
+
+/// Count duplicates and wasted space per size bucket, smallest bucket first.
+/// Every bucket is listed, including empty ones. Bounds and labels follow
+/// `units`, so binary units give 1 KiB - 1 MiB rather than 1 kB - 1 MB.
+pub fn size_histogram(hashes: &HashMap<String, Vec<PathBuf>>, units: Units) -> Vec<SizeBucket> {
+    let bounds = match units {
+        Units::Decimal => DECIMAL_BUCKETS,
+        Units::Binary => BINARY_BUCKETS,
+    };
+    let mut buckets: Vec<SizeBucket> = Vec::new();
+    let mut min = 0;
+    for max in bounds {
+        let label = if min == 0 {
+            format!("< {}", units.format(max))
+        } else {
+            format!("{} - {}", units.format(min), units.format(max))
+        };
+        buckets.push(SizeBucket {
+            label,
+            min,
+            max: Some(max),
+            groups: 0,
+            files: 0,
+            wasted_space: 0,
+        });
+        min = max;
+    }
+    buckets.push(SizeBucket {
+        label: format!("{}+", units.format(min)),
+        min,
+        max: None,
+        groups: 0,
+        files: 0,
+        wasted_space: 0,
+    });
+
+    for files in hashes.values() {
+        let Some(first) = files.first() else {
+            continue;
+        };
+        let size = std::fs::metadata(first).map(|m| m.len()).unwrap_or(0);
+        let bucket = buckets
+            .iter_mut()
+            .find(|b| b.max.is_none_or(|max| size < max))
+            .expect("last bucket is unbounded");
+        bucket.groups += 1;
+        bucket.files += files.len();
+        bucket.wasted_space += size * (files.len() as u64 - 1);
+    }
+    buckets
 }
 
 pub fn calculate_statistics(
     hashes: &HashMap<String, Vec<PathBuf>>,
     total_files_scanned: usize,
+    total_bytes_scanned: u64,
     total_size_groups: usize,
 ) -> Result<ScanStatistics> {
     let total_duplicate_groups = hashes.len();
@@ -51,9 +390,86 @@ pub fn calculate_statistics(
 
     Ok(ScanStatistics {
         total_files_scanned,
+        total_bytes_scanned,
+        average_file_size: average_size(total_bytes_scanned, total_files_scanned),
         total_size_groups,
         total_duplicate_groups,
         total_duplicate_files,
         total_wasted_space,
+        total_bytes_read: 0,
+        quick_hash: QuickHashStats::default(),
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_extension_breakdown() {
+        let dir = tempfile::tempdir().unwrap();
+        let write = |name: &str, content: &str| {
+            let path = dir.path().join(name);
+            fs::write(&path, content).unwrap();
+            path
+        };
+        let hashes = HashMap::from([
+            (
+                "a".to_string(),
+                vec![write("a.JPG", "photo"), write("b.jpg", "photo")],
+            ),
+            (
+                "b".to_string(),
+                vec![
+                    write("c.jpg", "pic"),
+                    write("d.jpg", "pic"),
+                    write("e.jpg", "pic"),
+                ],
+            ),
+            (
+                "c".to_string(),
+                vec![write("README", "x"), write("COPY", "x")],
+            ),
+        ]);
+
+        let breakdown = extension_breakdown(&hashes);
+        assert_eq!(breakdown.len(), 2);
+        assert_eq!(breakdown[0].extension, "jpg");
+        assert_eq!((breakdown[0].groups, breakdown[0].files), (2, 5));
+        assert_eq!(breakdown[0].wasted_space, 5 + 6);
+        assert_eq!(breakdown[1].extension, "(none)");
+        assert_eq!(breakdown[1].wasted_space, 1);
+    }
+
+    #[test]
+    fn test_size_histogram() {
+        let dir = tempfile::tempdir().unwrap();
+        let write = |name: &str, len: usize| {
+            let path = dir.path().join(name);
+            fs::write(&path, vec![b'x'; len]).unwrap();
+            path
+        };
+        let hashes = HashMap::from([
+            ("a".to_string(), vec![write("a1", 10), write("a2", 10)]),
+            (
+                "b".to_string(),
+                vec![write("b1", 5000), write("b2", 5000), write("b3", 5000)],
+            ),
+        ]);
+
+        let histogram = size_histogram(&hashes, Units::Decimal);
+        assert_eq!(histogram.len(), 4);
+        assert_eq!(histogram[1].label, "1 kB - 1 MB");
+        assert_eq!(histogram[3].label, "100 MB+");
+        assert_eq!((histogram[0].groups, histogram[0].wasted_space), (1, 10));
+        assert_eq!((histogram[1].files, histogram[1].wasted_space), (3, 10_000));
+        assert_eq!(histogram[3].groups, 0);
+        assert_eq!(histogram[3].max, None);
+
+        let binary = size_histogram(&hashes, Units::Binary);
+        assert_eq!(binary[1].label, "1 KiB - 1 MiB");
+        assert_eq!(binary[1].min, 1024);
+        assert_eq!(binary[3].label, "100 MiB+");
+    }
+}
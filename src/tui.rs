@@ -0,0 +1,362 @@
+use anyhow::{Context, Result};
+use dupfind::actions::{
+    self, Action, ActionPlan, ActionSummary, AuditLog, PlannedGroup, canonical_paths, is_under,
+};
+use dupfind::output::format_bytes;
+use dupfind::statistics::group_digest;
+use log::info;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color, Modifier, Style, Stylize};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, List, ListItem, ListState, Paragraph};
+use ratatui::{DefaultTerminal, Frame};
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+
+struct Group {
+    hash: String,
+    size: u64,
+    files: Vec<PathBuf>,
+    delete: Vec<bool>,
+    /// Files under `--protect`, which cannot be marked for deletion.
+    protected: Vec<bool>,
+}
+
+/// Selection state for the interactive review. Every file starts out kept.
+struct Review {
+    groups: Vec<Group>,
+    group: usize,
+    file: usize,
+    confirming: bool,
+}
+
+impl Review {
+    fn new(hashes: &HashMap<String, Vec<PathBuf>>, protect: &[PathBuf]) -> Self {
+        let protect = canonical_paths(protect);
+        let mut groups: Vec<Group> = hashes
+            .iter()
+            .filter_map(|(id, files)| {
+                // Symlinks are not copies, as in `actions::plan_action`.
+                let mut files: Vec<PathBuf> = files
+                    .iter()
+                    .filter(|p| !fs::symlink_metadata(p).is_ok_and(|m| m.file_type().is_symlink()))
+                    .cloned()
+                    .collect();
+                if files.len() < 2 {
+                    return None;
+                }
+                let size = fs::metadata(&files[0]).ok()?.len();
+                files.sort();
+                Some(Group {
+                    hash: group_digest(id).to_string(),
+                    size,
+                    delete: vec![false; files.len()],
+                    protected: files.iter().map(|f| is_under(f, &protect)).collect(),
+                    files,
+                })
+            })
+            .collect();
+        groups.sort_by(|a, b| {
+            let wasted = |g: &Group| g.size * (g.files.len() as u64 - 1);
+            wasted(b)
+                .cmp(&wasted(a))
+                .then_with(|| a.files.cmp(&b.files))
+        });
+
+        Self {
+            groups,
+            group: 0,
+            file: 0,
+            confirming: false,
+        }
+    }
+
+    fn current(&self) -> &Group {
+        &self.groups[self.group]
+    }
+
+    /// Flip the selected file between keep and delete. Protected files and
+    /// the last kept copy of a group cannot be marked.
+    fn toggle(&mut self) {
+        let (g, f) = (self.group, self.file);
+        let group = &mut self.groups[g];
+        let kept = group.delete.iter().filter(|d| !**d).count();
+        if group.protected[f] {
+            return;
+        }
+        if group.delete[f] || kept > 1 {
+            group.delete[f] = !group.delete[f];
+        }
+    }
+
+    fn move_file(&mut self, down: bool) {
+        let len = self.current().files.len();
+        self.file = if down {
+            (self.file + 1).min(len - 1)
+        } else {
+            self.file.saturating_sub(1)
+        };
+    }
+
+    fn move_group(&mut self, next: bool) {
+        self.group = if next {
+            (self.group + 1).min(self.groups.len() - 1)
+        } else {
+            self.group.saturating_sub(1)
+        };
+        self.file = 0;
+    }
+
+    fn marked(&self) -> impl Iterator<Item = (u64, &PathBuf)> {
+        self.groups.iter().flat_map(|g| {
+            g.files
+                .iter()
+                .zip(&g.delete)
+                .filter(|(_, d)| **d)
+                .map(move |(p, _)| (g.size, p))
+        })
+    }
+
+    fn reclaimable(&self) -> u64 {
+        self.marked().map(|(size, _)| size).sum()
+    }
+
+    /// The selection as a plan for `actions::apply_plan`, keeping the first
+    /// unmarked copy of each group.
+    fn plan(&self, protect: &[PathBuf]) -> ActionPlan {
+        let groups = self
+            .groups
+            .iter()
+            .filter_map(|group| {
+                let (marked, kept): (Vec<_>, Vec<_>) = group
+                    .files
+                    .iter()
+                    .zip(&group.delete)
+                    .partition(|(_, delete)| **delete);
+                let keep = kept.first()?.0.clone();
+                (!marked.is_empty()).then(|| PlannedGroup {
+                    hash: group.hash.clone(),
+                    keep,
+                    remove: marked.into_iter().map(|(p, _)| (p.clone(), 0)).collect(),
+                })
+            })
+            .collect();
+        actions::plan_selection(groups, protect)
+    }
+}
+
+/// Let the user pick copies to delete, then delete them once confirmed.
+/// Returns `None` if the review was aborted.
+pub fn review(
+    hashes: &HashMap<String, Vec<PathBuf>>,
+    protect: &[PathBuf],
+    audit: Option<&mut AuditLog>,
+    dry_run: bool,
+) -> Result<Option<ActionSummary>> {
+    let mut review = Review::new(hashes, protect);
+    if review.groups.is_empty() {
+        return Ok(Some(ActionSummary::default()));
+    }
+
+    let mut terminal = ratatui::try_init().context("Failed to initialize terminal")?;
+    let confirmed = run(&mut terminal, &mut review);
+    ratatui::restore();
+    if !confirmed? {
+        info!("Interactive review aborted, no files were changed");
+        return Ok(None);
+    }
+
+    let plan = review.plan(protect);
+    actions::apply_plan(&plan, &Action::Delete, audit, dry_run).map(Some)
+}
+
+/// Event loop; returns whether the user confirmed the selection.
+fn run(terminal: &mut DefaultTerminal, review: &mut Review) -> Result<bool> {
+    loop {
+        terminal.draw(|frame| draw(frame, review))?;
+        let Event::Key(key) = event::read()? else {
+            continue;
+        };
+        if key.kind != KeyEventKind::Press {
+            continue;
+        }
+        if key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL) {
+            return Ok(false);
+        }
+
+        if review.confirming {
+            match key.code {
+                KeyCode::Char('y') => return Ok(true),
+                _ => review.confirming = false,
+            }
+            continue;
+        }
+
+        match key.code {
+            KeyCode::Char('q') | KeyCode::Esc => return Ok(false),
+            KeyCode::Up | KeyCode::Char('k') => review.move_file(false),
+            KeyCode::Down | KeyCode::Char('j') => review.move_file(true),
+            KeyCode::Left | KeyCode::PageUp => review.move_group(false),
+            KeyCode::Right | KeyCode::PageDown => review.move_group(true),
+            KeyCode::Char(' ') => review.toggle(),
+            KeyCode::Enter => review.confirming = true,
+            _ => {}
+        }
+    }
+}
+
+fn draw(frame: &mut Frame, review: &Review) {
+    let [header, body, footer] = Layout::vertical([
+        Constraint::Length(2),
+        Constraint::Min(1),
+        Constraint::Length(1),
+    ])
+    .areas(frame.area());
+
+    let group = review.current();
+    let title = Line::from(vec![
+        format!("Group {}/{}", review.group + 1, review.groups.len()).bold(),
+        format!(
+            " · {} × {} files",
+            format_bytes(group.size),
+            group.files.len()
+        )
+        .into(),
+    ]);
+    let marked = review.marked().count();
+    let reclaim = Line::from(vec![
+        "Reclaimable: ".dim(),
+        format_bytes(review.reclaimable()).green().bold(),
+        format!(" ({} files marked)", marked).dim(),
+    ]);
+    frame.render_widget(Paragraph::new(vec![title, reclaim]), header);
+
+    let items: Vec<ListItem> = group
+        .files
+        .iter()
+        .zip(&group.delete)
+        .zip(&group.protected)
+        .map(|((path, delete), protected)| {
+            let mark = if *delete {
+                Span::styled("[delete] ", Style::default().fg(Color::Red))
+            } else if *protected {
+                Span::styled("[protect]", Style::default().fg(Color::Cyan))
+            } else {
+                Span::styled("[keep]   ", Style::default().fg(Color::Green))
+            };
+            ListItem::new(Line::from(vec![mark, path.display().to_string().into()]))
+        })
+        .collect();
+    let list = List::new(items)
+        .block(Block::bordered())
+        .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+    let mut state = ListState::default().with_selected(Some(review.file));
+    frame.render_stateful_widget(list, body, &mut state);
+
+    let help = if review.confirming {
+        Line::from(format!(
+            "Delete {} files and reclaim {}? y to confirm, any other key to go back",
+            marked,
+            format_bytes(review.reclaimable())
+        ))
+        .yellow()
+        .bold()
+    } else {
+        Line::from("↑/↓ file  ←/→ group  space keep/delete  enter apply  q quit").dim()
+    };
+    frame.render_widget(Paragraph::new(help), footer);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_toggle_keeps_one_copy() {
+        let mut review = Review {
+            groups: vec![Group {
+                hash: "h".to_string(),
+                size: 10,
+                files: vec![PathBuf::from("/data/a"), PathBuf::from("/data/b")],
+                delete: vec![false; 2],
+                protected: vec![false; 2],
+            }],
+            group: 0,
+            file: 0,
+            confirming: false,
+        };
+
+        review.toggle();
+        review.move_file(true);
+        review.toggle();
+        assert_eq!(review.groups[0].delete, vec![true, false]);
+        assert_eq!(review.reclaimable(), 10);
+
+        review.move_file(false);
+        review.toggle();
+        assert_eq!(review.reclaimable(), 0);
+    }
+
+    #[test]
+    fn test_protected_files_cannot_be_marked() {
+        let dir = tempfile::tempdir().unwrap();
+        let originals = dir.path().join("originals");
+        fs::create_dir(&originals).unwrap();
+        let (a, b) = (dir.path().join("a"), originals.join("b"));
+        fs::write(&a, "same").unwrap();
+        fs::write(&b, "same").unwrap();
+        let hashes = HashMap::from([("h".to_string(), vec![a, b])]);
+        let mut review = Review::new(&hashes, &[originals.join("../originals")]);
+        assert_eq!(review.groups[0].protected, vec![false, true]);
+
+        review.move_file(true);
+        review.toggle();
+        assert_eq!(review.marked().count(), 0);
+        review.move_file(false);
+        review.toggle();
+        assert_eq!(review.marked().count(), 1);
+    }
+
+    #[test]
+    fn test_selection_becomes_a_delete_plan() {
+        let dir = tempfile::tempdir().unwrap();
+        let (a, b, c) = (
+            dir.path().join("a"),
+            dir.path().join("b"),
+            dir.path().join("c"),
+        );
+        for path in [&a, &b, &c] {
+            fs::write(path, "same").unwrap();
+        }
+        let mut files = vec![a.clone(), b.clone(), c.clone()];
+        #[cfg(unix)]
+        {
+            let link = dir.path().join("link");
+            std::os::unix::fs::symlink(&a, &link).unwrap();
+            files.push(link);
+        }
+        let hashes = HashMap::from([("h#1".to_string(), files)]);
+        let mut review = Review::new(&hashes, &[]);
+        // The symlink is not offered as a copy.
+        assert_eq!(
+            review.groups[0].files,
+            vec![a.clone(), b.clone(), c.clone()]
+        );
+
+        review.toggle();
+        review.move_file(true);
+        review.move_file(true);
+        review.toggle();
+        let plan = review.plan(&[]);
+        assert_eq!(plan.groups.len(), 1);
+        assert_eq!(plan.groups[0].hash, "h");
+        assert_eq!(plan.groups[0].keep, b);
+        assert_eq!(plan.groups[0].remove, vec![(a, 4), (c, 4)]);
+
+        let summary = actions::apply_plan(&plan, &Action::Delete, None, false).unwrap();
+        assert_eq!(summary.files_affected, 2);
+        assert!(b.exists() && !plan.groups[0].remove[0].0.exists());
+    }
+}
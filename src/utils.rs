@@ -1,17 +1,228 @@
-use anyhow::{Result, bail};
-use std::path::Path;
+use log::warn;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
 use std::sync::atomic::AtomicBool;
 
+use crate::error::DupFindError;
+
 pub static INTERRUPTED: AtomicBool = AtomicBool::new(false);
 
-pub fn validate_path(path: &Path) -> Result<()> {
-    if !path.exists() {
-        bail!("Path does not exist: {}", path.display());
+/// Device and inode of a file (volume serial and file index on Windows).
+/// Two paths with the same id are hard links to the same data.
+#[cfg(unix)]
+pub fn file_id(path: &Path) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+    std::fs::metadata(path).ok().map(|m| (m.dev(), m.ino()))
+}
+
+#[cfg(windows)]
+pub fn file_id(path: &Path) -> Option<(u64, u64)> {
+    use winapi_util::{Handle, file};
+    let handle = Handle::from_path_any(path).ok()?;
+    let info = file::information(&handle).ok()?;
+    Some((info.volume_serial_number(), info.file_index()))
+}
+
+#[cfg(not(any(unix, windows)))]
+pub fn file_id(_path: &Path) -> Option<(u64, u64)> {
+    None
+}
+
+/// Distinct devices the given files live on, sorted; files whose device
+/// cannot be read are left out.
+pub fn devices(files: &[PathBuf]) -> Vec<u64> {
+    let mut devices: Vec<u64> = files
+        .iter()
+        .filter_map(|f| file_id(f).map(|(dev, _)| dev))
+        .collect();
+    devices.sort_unstable();
+    devices.dedup();
+    devices
+}
+
+/// Number of hard links to the file, 1 when the platform cannot tell.
+#[cfg(unix)]
+pub fn link_count(path: &Path) -> u64 {
+    use std::os::unix::fs::MetadataExt;
+    std::fs::metadata(path).map(|m| m.nlink()).unwrap_or(1)
+}
+
+#[cfg(windows)]
+pub fn link_count(path: &Path) -> u64 {
+    use winapi_util::{Handle, file};
+    Handle::from_path_any(path)
+        .and_then(|handle| file::information(&handle))
+        .map(|info| info.number_of_links())
+        .unwrap_or(1)
+}
+
+#[cfg(not(any(unix, windows)))]
+pub fn link_count(_path: &Path) -> u64 {
+    1
+}
+
+/// `fs::canonicalize`, or the path unchanged (with a warning) if that fails.
+pub fn canonical_or_original(path: &Path) -> PathBuf {
+    std::fs::canonicalize(path).unwrap_or_else(|e| {
+        warn!(
+            "Cannot resolve {}, reporting it as is: {}",
+            path.display(),
+            e
+        );
+        path.to_path_buf()
+    })
+}
+
+/// Permission bits as `rwxr-x---`, or the file's attributes on Windows.
+#[cfg(unix)]
+pub fn permissions_string(meta: &std::fs::Metadata) -> String {
+    use std::os::unix::fs::PermissionsExt;
+    let mode = meta.permissions().mode();
+    (0..9)
+        .map(|i| match mode & (1 << (8 - i)) {
+            0 => '-',
+            _ => ['r', 'w', 'x'][i % 3],
+        })
+        .collect()
+}
+
+#[cfg(windows)]
+pub fn permissions_string(meta: &std::fs::Metadata) -> String {
+    use std::os::windows::fs::MetadataExt;
+    let names = [
+        (0x1, "readonly"),
+        (0x2, "hidden"),
+        (0x4, "system"),
+        (0x20, "archive"),
+    ];
+    let set: Vec<_> = names
+        .iter()
+        .filter(|(bit, _)| meta.file_attributes() & bit != 0)
+        .map(|(_, name)| *name)
+        .collect();
+    if set.is_empty() {
+        "normal".to_string()
+    } else {
+        set.join(",")
     }
-    if !path.is_dir() {
-        bail!("Path is not a directory: {}", path.display());
+}
+
+#[cfg(not(any(unix, windows)))]
+pub fn permissions_string(meta: &std::fs::Metadata) -> String {
+    if meta.permissions().readonly() {
+        "readonly".to_string()
+    } else {
+        "writable".to_string()
     }
-    Ok(())
+}
+
+/// Owning user and group ids, where the platform has them.
+#[cfg(unix)]
+pub fn owner(meta: &std::fs::Metadata) -> Option<(u32, u32)> {
+    use std::os::unix::fs::MetadataExt;
+    Some((meta.uid(), meta.gid()))
+}
+
+#[cfg(not(unix))]
+pub fn owner(_meta: &std::fs::Metadata) -> Option<(u32, u32)> {
+    None
+}
+
+/// Split roots into those to scan and those repeating or inside another root,
+/// which would list every file twice. Paths are compared canonicalized, but
+/// kept roots are returned as given.
+pub fn dedupe_roots(roots: &[PathBuf]) -> (Vec<PathBuf>, Vec<PathBuf>) {
+    let canonical: Vec<PathBuf> = roots
+        .iter()
+        .map(|root| std::fs::canonicalize(root).unwrap_or_else(|_| root.clone()))
+        .collect();
+    let mut kept = Vec::new();
+    let mut dropped = Vec::new();
+    for (i, root) in roots.iter().enumerate() {
+        // Of two equal roots the first one stays.
+        let covered = canonical.iter().enumerate().any(|(j, other)| {
+            j != i && canonical[i].starts_with(other) && (canonical[i] != *other || j < i)
+        });
+        if covered {
+            dropped.push(root.clone());
+        } else {
+            kept.push(root.clone());
+        }
+    }
+    (kept, dropped)
+}
+
+/// Write `path` through a uniquely named temporary file next to it, synced
+/// and renamed into place only once `write` succeeds. A run killed or failing
+/// mid-write leaves the previous file intact, so a cron job never loses its
+/// last good report, and concurrent runs never share a temporary file.
+/// Targets that are not regular files, like `/dev/stdout` or a FIFO, cannot
+/// be renamed over and are written directly.
+pub fn write_atomically<E>(
+    path: &Path,
+    write: impl FnOnce(&mut BufWriter<File>) -> Result<(), E>,
+) -> Result<(), E>
+where
+    E: From<DupFindError>,
+{
+    if fs::metadata(path).is_ok_and(|m| !m.is_file()) {
+        let file = File::create(path).map_err(|e| DupFindError::io(path, e))?;
+        let mut writer = BufWriter::new(file);
+        write(&mut writer)?;
+        return writer.flush().map_err(|e| DupFindError::io(path, e).into());
+    }
+
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let mut builder = tempfile::Builder::new();
+    builder.prefix(".dupfind-").suffix(".tmp");
+    // Same mode a plain create would give, instead of owner-only.
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        builder.permissions(fs::Permissions::from_mode(0o666));
+    }
+    let (file, tmp_path) = builder
+        .tempfile_in(dir)
+        .map_err(|e| DupFindError::io(dir, e))?
+        .into_parts();
+    let mut writer = BufWriter::new(file);
+
+    // `tmp_path` deletes the temporary file if it is dropped unpersisted.
+    write(&mut writer)?;
+    let file = writer
+        .into_inner()
+        .map_err(|e| DupFindError::io(&tmp_path, e.into_error()))?;
+    file.sync_all()
+        .map_err(|e| DupFindError::io(&tmp_path, e))?;
+    tmp_path
+        .persist(path)
+        .map_err(|e| DupFindError::io(path, e.error).into())
+}
+
+pub fn validate_path(path: &Path) -> Result<(), DupFindError> {
+    match std::fs::metadata(path) {
+        Ok(meta) if meta.is_dir() => Ok(()),
+        Ok(_) => Err(DupFindError::NotADirectory(path.to_path_buf())),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            Err(DupFindError::PathNotFound(path.to_path_buf()))
+        }
+        Err(e) => Err(DupFindError::io(path, e)),
+    }
+}
+
+/// A temporary directory for tests that walk it. `tempfile` names its
+/// directories `.tmpXXXX`, which the walk skips as a hidden root; the prefix
+/// only exists to get past that check.
+#[cfg(test)]
+pub(crate) fn scan_tempdir() -> tempfile::TempDir {
+    tempfile::Builder::new()
+        .prefix("dupfind")
+        .tempdir()
+        .unwrap()
 }
 
 #[cfg(test)]
@@ -30,7 +241,7 @@ mod tests {
     fn test_validate_path_nonexistent() {
         let path = Path::new("/nonexistent/path/that/does/not/exist");
         let result = validate_path(path);
-        assert!(result.is_err());
+        assert!(matches!(result, Err(DupFindError::PathNotFound(_))));
         assert!(result.unwrap_err().to_string().contains("does not exist"));
     }
 
@@ -41,7 +252,66 @@ mod tests {
         fs::write(&file_path, "test").unwrap();
 
         let result = validate_path(&file_path);
-        assert!(result.is_err());
+        assert!(matches!(result, Err(DupFindError::NotADirectory(_))));
         assert!(result.unwrap_err().to_string().contains("not a directory"));
     }
+
+    #[test]
+    fn test_dedupe_roots() {
+        let dir = tempdir().unwrap();
+        let inner = dir.path().join("inner");
+        fs::create_dir(&inner).unwrap();
+        let other = tempdir().unwrap();
+        let roots = vec![
+            inner.clone(),
+            dir.path().to_path_buf(),
+            other.path().to_path_buf(),
+            dir.path().join("."),
+        ];
+
+        let (kept, dropped) = dedupe_roots(&roots);
+        assert_eq!(kept, [dir.path(), other.path()]);
+        assert_eq!(dropped, [inner, dir.path().join(".")]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_permissions_string() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("file");
+        fs::write(&path, "x").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o750)).unwrap();
+        assert_eq!(
+            permissions_string(&fs::metadata(&path).unwrap()),
+            "rwxr-x---"
+        );
+    }
+
+    #[test]
+    fn test_failed_write_keeps_previous_file() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("results.json");
+        fs::write(&path, "previous").unwrap();
+
+        let result = write_atomically(&path, |writer| {
+            writer.write_all(b"partial")?;
+            Err(anyhow::anyhow!("killed"))
+        });
+        assert!(result.is_err());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "previous");
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+
+        write_atomically::<anyhow::Error>(&path, |writer| Ok(writer.write_all(b"new")?)).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new");
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_write_to_device_is_direct() {
+        let null = Path::new("/dev/null");
+        write_atomically::<anyhow::Error>(null, |writer| Ok(writer.write_all(b"report")?)).unwrap();
+        assert!(!fs::metadata(null).unwrap().is_file());
+    }
 }
@@ -0,0 +1,93 @@
+use anyhow::{Context, Result, bail};
+use dupfind::output::{PrintOptions, build_results, print_results};
+use dupfind::statistics::{ScanResults, calculate_statistics, retain_new_since};
+use dupfind::utils::INTERRUPTED;
+use dupfind::{DupFindError, DupFinder};
+use log::{info, warn};
+use notify::{Event, RecursiveMode, Watcher};
+use std::path::PathBuf;
+use std::sync::atomic::Ordering;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::time::Duration;
+
+use crate::cli_progress::CliProgress;
+
+/// How often an idle watch checks for Ctrl-C.
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+type Events = Receiver<notify::Result<Event>>;
+
+/// Rescan `roots` whenever something under them changes, printing only the
+/// groups that are new since the previous scan, until Ctrl-C. `known` is the
+/// result of the scan already reported. With `--cache`, unchanged files are
+/// not hashed again.
+pub fn watch(
+    finder: &DupFinder,
+    roots: &[PathBuf],
+    debounce: Duration,
+    mut known: ScanResults,
+    options: &PrintOptions,
+    progress: &CliProgress,
+) -> Result<()> {
+    let (sender, events) = mpsc::channel();
+    let mut watcher =
+        notify::recommended_watcher(sender).context("Failed to start watching for changes")?;
+    for root in roots {
+        watcher
+            .watch(root, RecursiveMode::Recursive)
+            .with_context(|| format!("Failed to watch {}", root.display()))?;
+    }
+    info!("Watching for changes, press Ctrl-C to stop");
+
+    while wait_for_change(&events, debounce)? {
+        info!("Changes detected, rescanning");
+        let found = finder.find();
+        progress.finish();
+        let found = match found {
+            Err(DupFindError::Interrupted) => break,
+            found => found?,
+        };
+
+        let mut new = found.hashes.clone();
+        retain_new_since(&mut new, &known);
+        if new.is_empty() {
+            info!("No new duplicates");
+        } else {
+            let stats = &found.stats;
+            let new_stats = calculate_statistics(
+                &new,
+                stats.total_files_scanned,
+                stats.total_bytes_scanned,
+                stats.total_size_groups,
+            )?;
+            print_results(&new_stats, &new, options)?;
+        }
+        known = build_results(&found);
+    }
+    Ok(())
+}
+
+/// Block until something under the roots changes and then stays quiet for
+/// `debounce`, so a copy in progress triggers one rescan. Reads, including
+/// dupfind's own, are not changes. Returns false once Ctrl-C is pressed.
+fn wait_for_change(events: &Events, debounce: Duration) -> Result<bool> {
+    loop {
+        if INTERRUPTED.load(Ordering::Relaxed) {
+            return Ok(false);
+        }
+        match events.recv_timeout(POLL_INTERVAL) {
+            Ok(Ok(event)) if !event.kind.is_access() => break,
+            Ok(Err(e)) => warn!("File watcher error: {}", e),
+            Ok(_) | Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => bail!("File watcher stopped"),
+        }
+    }
+    loop {
+        match events.recv_timeout(debounce) {
+            Ok(_) if !INTERRUPTED.load(Ordering::Relaxed) => {}
+            Ok(_) => return Ok(false),
+            Err(RecvTimeoutError::Timeout) => return Ok(!INTERRUPTED.load(Ordering::Relaxed)),
+            Err(RecvTimeoutError::Disconnected) => bail!("File watcher stopped"),
+        }
+    }
+}